@@ -1,6 +1,10 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::configure().compile(
-        &["./src/proto/trade.proto", "./src/proto/account.proto"],
+        &[
+            "./src/proto/trade.proto",
+            "./src/proto/account.proto",
+            "./src/proto/error.proto",
+        ],
         &["proto"],
     )?;
     Ok(())
@@ -4,6 +4,8 @@ mod account_rpc {
 
 use crate::database::models::account::{Account, NewAccount};
 use crate::database::pool::PgPool;
+use crate::error::Error;
+use crate::wallet::error::Error::KeyfileStorage;
 use crate::wallet::wallet::Wallet;
 use account_rpc::account_service_server::{AccountService, AccountServiceServer};
 use account_rpc::{
@@ -41,15 +43,16 @@ impl AccountService for AccountHandler {
         &self,
         req: Request<CreateAccountRequest>,
     ) -> Result<Response<CreateAccountResponse>, Status> {
-        let mut conn = self.pool.get().expect("Failed to get connection from pool");
+        let mut conn = self.pool.get().map_err(Error::from)?;
         let create_req = req.into_inner();
-        let (addr, kf) = Wallet::new_account().unwrap();
+        let (addr, kf) = Wallet::new_account().map_err(Error::Keystore)?;
         let new_account = NewAccount {
             user_id: create_req.user_id,
             address: addr.to_string(),
-            keystore: serde_json::to_value(&kf).unwrap(),
+            keystore: serde_json::to_value(&kf)
+                .map_err(|e| Error::Keystore(KeyfileStorage(e.to_string())))?,
         };
-        let account = new_account.create(&mut conn).await;
+        let account = new_account.create(&mut conn).await.map_err(Error::from)?;
 
         Ok(Response::new(CreateAccountResponse {
             id: account.id,
@@ -61,28 +64,25 @@ impl AccountService for AccountHandler {
         &self,
         _req: Request<EmptyRequest>,
     ) -> Result<Response<FindAllAccountsResponse>, Status> {
-        let mut conn = self.pool.get().expect("Failed to get connection from pool");
-        let accounts = match Account::list(&mut conn).await {
-            Ok(acc) => {
-                let accounts_response = acc.iter().map(|t| into_response(t)).collect::<Vec<_>>();
-                FindAllAccountsResponse {
-                    accounts: accounts_response,
-                }
-            }
-            Err(_e) => return Err(Status::not_found("Not Found")),
-        };
-        Ok(Response::new(accounts))
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let accounts = Account::list(&mut conn).await.map_err(Error::from)?;
+        Ok(Response::new(FindAllAccountsResponse {
+            accounts: accounts.iter().map(into_response).collect(),
+        }))
     }
 
     async fn by_id(
         &self,
         req: Request<ByIdRequest>,
     ) -> Result<Response<FindOneAccountResponse>, Status> {
-        let mut conn = self.pool.get().expect("Failed to get connection from pool");
+        let mut conn = self.pool.get().map_err(Error::from)?;
         let account_id: i32 = req.into_inner().id;
         let account = match Account::find_by_id(&account_id, &mut conn).await {
             Ok(acc) => into_response(&acc),
-            Err(_e) => return Err(Status::not_found("Not Found")),
+            Err(diesel::result::Error::NotFound) => {
+                return Err(Error::AccountNotFound(account_id.to_string()).into())
+            }
+            Err(e) => return Err(Error::from(e).into()),
         };
         Ok(Response::new(account))
     }
@@ -91,11 +91,14 @@ impl AccountService for AccountHandler {
         &self,
         req: Request<ByUserIdRequest>,
     ) -> Result<Response<FindOneAccountResponse>, Status> {
-        let mut conn = self.pool.get().expect("Failed to get connection from pool");
+        let mut conn = self.pool.get().map_err(Error::from)?;
         let user_id: String = req.into_inner().user_id;
         let account = match Account::find_by_user_id(&user_id, &mut conn).await {
             Ok(acc) => into_response(&acc),
-            Err(_e) => return Err(Status::not_found("Not Found")),
+            Err(diesel::result::Error::NotFound) => {
+                return Err(Error::AccountNotFound(user_id).into())
+            }
+            Err(e) => return Err(Error::from(e).into()),
         };
         Ok(Response::new(account))
     }
@@ -0,0 +1,22 @@
+CREATE TABLE transactions (
+    id SERIAL PRIMARY KEY,
+    account_id INTEGER NOT NULL REFERENCES accounts (id),
+    chain_id BIGINT NOT NULL,
+    nonce BIGINT NOT NULL,
+    to_address VARCHAR,
+    value VARCHAR NOT NULL,
+    data VARCHAR NOT NULL,
+    gas_limit BIGINT NOT NULL,
+    gas_price VARCHAR,
+    max_fee_per_gas VARCHAR,
+    max_priority_fee_per_gas VARCHAR,
+    tx_type SMALLINT NOT NULL,
+    hash VARCHAR NOT NULL UNIQUE,
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX transactions_account_id_status ON transactions (account_id, status);
+
+SELECT diesel_manage_updated_at('transactions');
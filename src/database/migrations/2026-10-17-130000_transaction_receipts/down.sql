@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS transactions_chain_id_status;
+
+ALTER TABLE transactions
+    DROP COLUMN block_number,
+    DROP COLUMN gas_used,
+    DROP COLUMN effective_gas_price,
+    DROP COLUMN reverted;
@@ -0,0 +1,7 @@
+ALTER TABLE transactions
+    ADD COLUMN block_number BIGINT,
+    ADD COLUMN gas_used BIGINT,
+    ADD COLUMN effective_gas_price VARCHAR,
+    ADD COLUMN reverted BOOLEAN;
+
+CREATE INDEX transactions_chain_id_status ON transactions (chain_id, status);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS transactions_account_id_chain_id_nonce;
+
+ALTER TABLE transactions
+    DROP COLUMN replaces,
+    DROP COLUMN replaced_by;
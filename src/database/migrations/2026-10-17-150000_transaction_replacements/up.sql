@@ -0,0 +1,5 @@
+ALTER TABLE transactions
+    ADD COLUMN replaces VARCHAR,
+    ADD COLUMN replaced_by VARCHAR;
+
+CREATE INDEX transactions_account_id_chain_id_nonce ON transactions (account_id, chain_id, nonce);
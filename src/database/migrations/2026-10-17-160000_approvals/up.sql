@@ -0,0 +1,14 @@
+CREATE TABLE approvals (
+    id SERIAL PRIMARY KEY,
+    account_id INTEGER NOT NULL REFERENCES accounts (id),
+    chain_id BIGINT NOT NULL,
+    token VARCHAR NOT NULL,
+    spender VARCHAR NOT NULL,
+    amount VARCHAR NOT NULL,
+    hash VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (account_id, chain_id, token, spender)
+);
+
+SELECT diesel_manage_updated_at('approvals');
@@ -0,0 +1,18 @@
+CREATE TABLE positions (
+    id SERIAL PRIMARY KEY,
+    account_id INTEGER NOT NULL REFERENCES accounts (id),
+    chain_id BIGINT NOT NULL,
+    token_id VARCHAR NOT NULL,
+    token0 VARCHAR NOT NULL,
+    token1 VARCHAR NOT NULL,
+    fee INTEGER NOT NULL,
+    tick_lower INTEGER NOT NULL,
+    tick_upper INTEGER NOT NULL,
+    liquidity VARCHAR NOT NULL,
+    hash VARCHAR NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+SELECT diesel_manage_updated_at('positions');
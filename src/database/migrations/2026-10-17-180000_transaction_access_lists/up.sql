@@ -0,0 +1 @@
+ALTER TABLE transactions ADD COLUMN access_list JSON;
@@ -1,3 +1,3 @@
 pub mod models;
 pub mod pool;
-pub mod schema;
\ No newline at end of file
+pub mod schema;
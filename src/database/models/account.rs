@@ -19,11 +19,8 @@ impl NewAccount {
     pub async fn create(
         &self,
         conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-    ) -> Account {
-        insert_into(accounts)
-            .values(self)
-            .get_result(conn)
-            .expect("Error saving new account")
+    ) -> Result<Account, diesel::result::Error> {
+        insert_into(accounts).values(self).get_result(conn)
     }
 }
 
@@ -0,0 +1,37 @@
+use crate::database::schema::approvals;
+use crate::database::schema::approvals::dsl::*;
+use crate::diesel::ExpressionMethods;
+use diesel::insert_into;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::RunQueryDsl;
+
+/// Allowance granted by an account beyond the amount of a single trade
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = approvals)]
+pub struct NewApproval {
+    pub account_id: i32,
+    pub chain_id: i64,
+    pub token: String,
+    pub spender: String,
+    /// Decimal string in token units
+    pub amount: String,
+    /// Hash of the approve transaction
+    pub hash: String,
+}
+
+impl NewApproval {
+    /// Record the allowance, a new approval of the same spender replaces the previous one
+    pub async fn create(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<(), diesel::result::Error> {
+        insert_into(approvals)
+            .values(self)
+            .on_conflict((account_id, chain_id, token, spender))
+            .do_update()
+            .set((amount.eq(&self.amount), hash.eq(&self.hash)))
+            .execute(conn)?;
+        Ok(())
+    }
+}
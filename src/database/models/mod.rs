@@ -1,2 +1,4 @@
 pub mod account;
+pub mod approval;
+pub mod position;
 pub mod transaction;
@@ -0,0 +1,149 @@
+use crate::database::schema::positions;
+use crate::database::schema::positions::dsl::*;
+use crate::diesel::ExpressionMethods;
+use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::RunQueryDsl;
+use diesel::{insert_into, update, QueryDsl};
+use std::fmt;
+use std::str::FromStr;
+
+/// Lifecycle of a concentrated-liquidity position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionStatus {
+    /// Mint transaction broadcast, the token ID is the simulated one until it is mined
+    Pending,
+    /// Minted, with liquidity or fees left
+    Open,
+    /// NFT burned by the manager
+    Burnt,
+    /// Mint transaction reverted, replaced or dropped
+    Failed,
+}
+
+impl PositionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            PositionStatus::Pending => "pending",
+            PositionStatus::Open => "open",
+            PositionStatus::Burnt => "burnt",
+            PositionStatus::Failed => "failed",
+        }
+    }
+}
+
+impl fmt::Display for PositionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PositionStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(PositionStatus::Pending),
+            "open" => Ok(PositionStatus::Open),
+            "burnt" => Ok(PositionStatus::Burnt),
+            "failed" => Ok(PositionStatus::Failed),
+            _ => Err(format!("Unknown position status {}", s)),
+        }
+    }
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = positions)]
+pub struct NewPosition {
+    pub account_id: i32,
+    pub chain_id: i64,
+    /// Decimal string of the NonfungiblePositionManager token ID
+    pub token_id: String,
+    pub token0: String,
+    pub token1: String,
+    pub fee: i32,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    /// Decimal string of the position liquidity
+    pub liquidity: String,
+    /// Hash of the mint transaction
+    pub hash: String,
+    pub status: String,
+}
+
+impl NewPosition {
+    pub async fn create(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<Position, diesel::result::Error> {
+        insert_into(positions).values(self).get_result(conn)
+    }
+}
+
+#[derive(Queryable, Clone, Debug)]
+#[diesel(table_name = positions)]
+pub struct Position {
+    pub id: i32,
+    pub account_id: i32,
+    pub chain_id: i64,
+    pub token_id: String,
+    pub token0: String,
+    pub token1: String,
+    pub fee: i32,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub liquidity: String,
+    pub hash: String,
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl Position {
+    pub async fn find_by_id(
+        pos_account_id: &i32,
+        pos_id: &i32,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<Position, diesel::result::Error> {
+        positions
+            .filter(account_id.eq(pos_account_id))
+            .filter(id.eq(pos_id))
+            .first(conn)
+    }
+
+    /// Positions of an account on a chain, newest first
+    pub async fn list_by_account(
+        pos_account_id: &i32,
+        pos_chain_id: &i64,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<Vec<Position>, diesel::result::Error> {
+        positions
+            .filter(account_id.eq(pos_account_id))
+            .filter(chain_id.eq(pos_chain_id))
+            .order(created_at.desc())
+            .load::<Position>(conn)
+    }
+
+    /// Store the on-chain token ID, liquidity and status of the position
+    pub async fn update_state(
+        pos_id: &i32,
+        pos_token_id: &str,
+        pos_liquidity: &str,
+        pos_status: PositionStatus,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<(), diesel::result::Error> {
+        update(positions.filter(id.eq(pos_id)))
+            .set((
+                token_id.eq(pos_token_id),
+                liquidity.eq(pos_liquidity),
+                status.eq(pos_status.as_str()),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn status(&self) -> PositionStatus {
+        self.status.parse().unwrap_or(PositionStatus::Pending)
+    }
+}
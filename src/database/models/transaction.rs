@@ -0,0 +1,274 @@
+use crate::database::schema::accounts;
+use crate::database::schema::transactions;
+use crate::database::schema::transactions::dsl::*;
+use crate::diesel::ExpressionMethods;
+use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::RunQueryDsl;
+use diesel::{insert_into, update, JoinOnDsl, QueryDsl};
+use std::fmt;
+use std::str::FromStr;
+use web3::types::{AccessList, TransactionParameters, H256};
+
+/// Lifecycle of a submitted transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxStatus {
+    /// Broadcast, not mined or not confirmed yet
+    Pending,
+    /// Mined and executed successfully, with enough confirmations
+    Confirmed,
+    /// Rejected by the node or reverted on chain
+    Failed,
+    /// Another transaction of the sender was mined with the same nonce
+    Replaced,
+    /// Evicted from the mempool
+    Dropped,
+}
+
+impl TxStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            TxStatus::Pending => "pending",
+            TxStatus::Confirmed => "confirmed",
+            TxStatus::Failed => "failed",
+            TxStatus::Replaced => "replaced",
+            TxStatus::Dropped => "dropped",
+        }
+    }
+}
+
+impl fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TxStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(TxStatus::Pending),
+            "confirmed" => Ok(TxStatus::Confirmed),
+            "failed" => Ok(TxStatus::Failed),
+            "replaced" => Ok(TxStatus::Replaced),
+            "dropped" => Ok(TxStatus::Dropped),
+            _ => Err(format!("Unknown transaction status {}", s)),
+        }
+    }
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = transactions)]
+pub struct NewTransaction {
+    pub account_id: i32,
+    pub chain_id: i64,
+    pub nonce: i64,
+    pub to_address: Option<String>,
+    /// Amounts are decimal strings in wei
+    pub value: String,
+    /// Calldata as 0x-prefixed hex
+    pub data: String,
+    pub gas_limit: i64,
+    pub gas_price: Option<String>,
+    pub max_fee_per_gas: Option<String>,
+    pub max_priority_fee_per_gas: Option<String>,
+    pub tx_type: i16,
+    pub hash: String,
+    pub status: String,
+    /// Hash of the transaction this one replaces at the same nonce
+    pub replaces: Option<String>,
+    /// Access list of typed transactions, kept to send replacements with it
+    pub access_list: Option<serde_json::Value>,
+}
+
+impl NewTransaction {
+    /// Pending row for a signed transaction, `tx` must have its nonce and fees set
+    pub fn new(account: i32, chain: u64, tx: &TransactionParameters, tx_hash: &H256) -> Self {
+        Self {
+            account_id: account,
+            chain_id: chain as i64,
+            nonce: tx.nonce.unwrap_or_default().low_u64() as i64,
+            to_address: tx.to.map(|a| format!("{:?}", a)),
+            value: tx.value.to_string(),
+            data: format!("0x{}", hex::encode(&tx.data.0)),
+            gas_limit: tx.gas.low_u64() as i64,
+            gas_price: tx.gas_price.map(|p| p.to_string()),
+            max_fee_per_gas: tx.max_fee_per_gas.map(|p| p.to_string()),
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas.map(|p| p.to_string()),
+            tx_type: tx.transaction_type.unwrap_or_default().low_u64() as i16,
+            hash: format!("{:?}", tx_hash),
+            status: TxStatus::Pending.to_string(),
+            replaces: None,
+            access_list: tx
+                .access_list
+                .as_ref()
+                .and_then(|l| serde_json::to_value(l).ok()),
+        }
+    }
+
+    /// Insert the transaction, a rebroadcast of a known hash makes it pending again
+    pub async fn create(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<Transaction, diesel::result::Error> {
+        insert_into(transactions)
+            .values(self)
+            .on_conflict(hash)
+            .do_update()
+            .set(status.eq(&self.status))
+            .get_result(conn)
+    }
+}
+
+#[derive(Queryable, Debug)]
+#[diesel(table_name = transactions)]
+pub struct Transaction {
+    pub id: i32,
+    pub account_id: i32,
+    pub chain_id: i64,
+    pub nonce: i64,
+    pub to_address: Option<String>,
+    pub value: String,
+    pub data: String,
+    pub gas_limit: i64,
+    pub gas_price: Option<String>,
+    pub max_fee_per_gas: Option<String>,
+    pub max_priority_fee_per_gas: Option<String>,
+    pub tx_type: i16,
+    pub hash: String,
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub block_number: Option<i64>,
+    pub gas_used: Option<i64>,
+    pub effective_gas_price: Option<String>,
+    pub reverted: Option<bool>,
+    pub revert_reason: Option<String>,
+    pub replaces: Option<String>,
+    /// Hash of the transaction mined at the nonce of this one
+    pub replaced_by: Option<String>,
+    pub access_list: Option<serde_json::Value>,
+}
+
+/// Receipt fields of a mined transaction, all `None` once its block is reorganized away
+#[derive(AsChangeset, Clone, Debug, Default, PartialEq, Eq)]
+#[diesel(table_name = transactions, treat_none_as_null = true)]
+pub struct TxReceipt {
+    pub block_number: Option<i64>,
+    pub gas_used: Option<i64>,
+    /// Decimal string in wei
+    pub effective_gas_price: Option<String>,
+    pub reverted: Option<bool>,
+    pub revert_reason: Option<String>,
+}
+
+impl Transaction {
+    pub async fn find_by_hash(
+        tx_account_id: &i32,
+        tx_hash: &str,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<Transaction, diesel::result::Error> {
+        transactions
+            .filter(account_id.eq(tx_account_id))
+            .filter(hash.eq(tx_hash.to_lowercase()))
+            .first(conn)
+    }
+
+    /// Transactions of an account, newest first, optionally with the given status only
+    pub async fn list_by_account(
+        tx_account_id: &i32,
+        tx_status: Option<TxStatus>,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<Vec<Transaction>, diesel::result::Error> {
+        let mut query = transactions
+            .filter(account_id.eq(tx_account_id))
+            .order(created_at.desc())
+            .into_boxed();
+        if let Some(s) = tx_status {
+            query = query.filter(status.eq(s.as_str()));
+        }
+        query.load::<Transaction>(conn)
+    }
+
+    pub async fn update_status(
+        tx_hash: &str,
+        tx_status: TxStatus,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<(), diesel::result::Error> {
+        update(transactions.filter(hash.eq(tx_hash)))
+            .set(status.eq(tx_status.as_str()))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Pending transactions of a chain, with the address of their sender
+    pub async fn list_pending(
+        tx_chain_id: &i64,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<Vec<(Transaction, String)>, diesel::result::Error> {
+        transactions
+            .inner_join(accounts::table.on(accounts::id.eq(account_id)))
+            .filter(chain_id.eq(tx_chain_id))
+            .filter(status.eq(TxStatus::Pending.as_str()))
+            .order(nonce.asc())
+            .select((transactions::all_columns, accounts::address))
+            .load(conn)
+    }
+
+    pub async fn update_receipt(
+        tx_hash: &str,
+        receipt: &TxReceipt,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<(), diesel::result::Error> {
+        update(transactions.filter(hash.eq(tx_hash)))
+            .set(receipt)
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Receipt fields currently stored for the transaction
+    pub fn receipt(&self) -> TxReceipt {
+        TxReceipt {
+            block_number: self.block_number,
+            gas_used: self.gas_used,
+            effective_gas_price: self.effective_gas_price.clone(),
+            reverted: self.reverted,
+            revert_reason: self.revert_reason.clone(),
+        }
+    }
+
+    /// Access list the transaction was signed with
+    pub fn access_list(&self) -> Result<Option<AccessList>, serde_json::Error> {
+        self.access_list
+            .clone()
+            .map(serde_json::from_value)
+            .transpose()
+    }
+
+    /// Other transactions of the account sent at the same nonce on the same chain
+    pub async fn siblings(
+        tx: &Transaction,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<Vec<Transaction>, diesel::result::Error> {
+        transactions
+            .filter(account_id.eq(tx.account_id))
+            .filter(chain_id.eq(tx.chain_id))
+            .filter(nonce.eq(tx.nonce))
+            .filter(hash.ne(&tx.hash))
+            .load::<Transaction>(conn)
+    }
+
+    /// Mark the transaction replaced, by `by` when the mined transaction is known
+    pub async fn mark_replaced(
+        tx_hash: &str,
+        by: Option<&str>,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    ) -> Result<(), diesel::result::Error> {
+        update(transactions.filter(hash.eq(tx_hash)))
+            .set((status.eq(TxStatus::Replaced.as_str()), replaced_by.eq(by)))
+            .execute(conn)?;
+        Ok(())
+    }
+}
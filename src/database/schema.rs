@@ -8,3 +8,71 @@ diesel::table! {
         keystore -> Json,
     }
 }
+
+diesel::table! {
+    approvals (id) {
+        id -> Int4,
+        account_id -> Int4,
+        chain_id -> Int8,
+        token -> Varchar,
+        spender -> Varchar,
+        amount -> Varchar,
+        hash -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    positions (id) {
+        id -> Int4,
+        account_id -> Int4,
+        chain_id -> Int8,
+        token_id -> Varchar,
+        token0 -> Varchar,
+        token1 -> Varchar,
+        fee -> Int4,
+        tick_lower -> Int4,
+        tick_upper -> Int4,
+        liquidity -> Varchar,
+        hash -> Varchar,
+        status -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    transactions (id) {
+        id -> Int4,
+        account_id -> Int4,
+        chain_id -> Int8,
+        nonce -> Int8,
+        to_address -> Nullable<Varchar>,
+        value -> Varchar,
+        data -> Varchar,
+        gas_limit -> Int8,
+        gas_price -> Nullable<Varchar>,
+        max_fee_per_gas -> Nullable<Varchar>,
+        max_priority_fee_per_gas -> Nullable<Varchar>,
+        tx_type -> Int2,
+        hash -> Varchar,
+        status -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        block_number -> Nullable<Int8>,
+        gas_used -> Nullable<Int8>,
+        effective_gas_price -> Nullable<Varchar>,
+        reverted -> Nullable<Bool>,
+        revert_reason -> Nullable<Varchar>,
+        replaces -> Nullable<Varchar>,
+        replaced_by -> Nullable<Varchar>,
+        access_list -> Nullable<Json>,
+    }
+}
+
+diesel::joinable!(approvals -> accounts (account_id));
+diesel::joinable!(positions -> accounts (account_id));
+diesel::joinable!(transactions -> accounts (account_id));
+
+diesel::allow_tables_to_appear_in_same_query!(accounts, approvals, positions, transactions,);
@@ -0,0 +1,348 @@
+//! # Service errors
+//!
+//! Failures of the gRPC handlers and their mapping to `tonic::Status`. Every status
+//! carries an `ErrorInfo` detail with a machine readable reason and its context.
+
+use crate::wallet::error::Error as WalletError;
+use diesel::r2d2::PoolError;
+use prost::Message;
+use std::collections::HashMap;
+use std::{error, fmt};
+use tonic::{Code, Status};
+use web3::contract::Error as ContractError;
+use web3::Error as Web3Error;
+
+pub mod error_rpc {
+    tonic::include_proto!("error");
+}
+use error_rpc::ErrorInfo;
+
+/// Domain of the `ErrorInfo` details
+const DOMAIN: &str = "dex-service";
+
+/// Service errors
+#[derive(Debug)]
+pub enum Error {
+    /// No account for the user id
+    AccountNotFound(String),
+
+    /// No transaction of the account with the hash
+    TransactionNotFound(String),
+
+    /// No position of the account with the id
+    PositionNotFound(i32),
+
+    /// Malformed or out of range request field
+    InvalidArgument(String),
+
+    /// Token symbol not listed in the market of the chain
+    UnknownAsset(u32, String),
+
+    /// Exchange not listed in the market of the chain, or not supported
+    UnknownExchange(u32, String),
+
+    /// No node connection for the chain
+    UnsupportedChain(u32),
+
+    /// Request can't be executed in the current state, e.g. a reverted simulation
+    Rejected(String),
+
+    /// Unexpected failure of the service, e.g. a malformed contract output
+    Internal(String),
+
+    /// Signing or sending a transaction failed
+    Wallet(WalletError),
+
+    /// Keystore file can't be created
+    Keystore(WalletError),
+
+    /// Database query failed
+    Database(diesel::result::Error),
+
+    /// No database connection available
+    Pool(PoolError),
+
+    /// JSON-RPC call to the node failed
+    Rpc(Web3Error),
+}
+
+impl Error {
+    /// `ErrorInfo` reason of the error
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            Error::AccountNotFound(_) => "ACCOUNT_NOT_FOUND",
+            Error::TransactionNotFound(_) => "TRANSACTION_NOT_FOUND",
+            Error::PositionNotFound(_) => "POSITION_NOT_FOUND",
+            Error::InvalidArgument(_) => "INVALID_ARGUMENT",
+            Error::UnknownAsset(..) => "UNKNOWN_ASSET",
+            Error::UnknownExchange(..) => "UNKNOWN_EXCHANGE",
+            Error::UnsupportedChain(_) => "UNSUPPORTED_CHAIN",
+            Error::Rejected(_) => "REJECTED",
+            Error::Internal(_) => "INTERNAL_ERROR",
+            Error::Wallet(_) => "WALLET_ERROR",
+            Error::Keystore(_) => "KEYSTORE_ERROR",
+            Error::Database(_) => "DATABASE_ERROR",
+            Error::Pool(_) => "DATABASE_UNAVAILABLE",
+            Error::Rpc(_) => "RPC_ERROR",
+        }
+    }
+
+    /// Status code of the error
+    pub fn code(&self) -> Code {
+        match *self {
+            Error::AccountNotFound(_)
+            | Error::TransactionNotFound(_)
+            | Error::PositionNotFound(_) => Code::NotFound,
+            Error::InvalidArgument(_)
+            | Error::UnknownAsset(..)
+            | Error::UnknownExchange(..)
+            | Error::UnsupportedChain(_) => Code::InvalidArgument,
+            Error::Rejected(_) => Code::FailedPrecondition,
+            Error::Internal(_) => Code::Internal,
+            Error::Wallet(ref err) => match *err {
+                WalletError::RpcFault(ref err) => rpc_code(err),
+                WalletError::InvalidTransaction(_) | WalletError::SignerMismatch(_) => {
+                    Code::FailedPrecondition
+                }
+                _ => Code::Internal,
+            },
+            Error::Keystore(_) => Code::Internal,
+            Error::Database(diesel::result::Error::NotFound) => Code::NotFound,
+            Error::Database(_) => Code::Internal,
+            Error::Pool(_) => Code::Unavailable,
+            Error::Rpc(ref err) => rpc_code(err),
+        }
+    }
+
+    /// Context of the error for the `ErrorInfo` metadata
+    fn metadata(&self) -> HashMap<String, String> {
+        let entries = match *self {
+            Error::AccountNotFound(ref user_id) => vec![("user_id", user_id.clone())],
+            Error::TransactionNotFound(ref hash) => vec![("hash", hash.clone())],
+            Error::PositionNotFound(id) => vec![("position_id", id.to_string())],
+            Error::UnknownAsset(chain, ref symbol) => {
+                vec![("chain_id", chain.to_string()), ("symbol", symbol.clone())]
+            }
+            Error::UnknownExchange(chain, ref exchange) => {
+                vec![
+                    ("chain_id", chain.to_string()),
+                    ("exchange", exchange.clone()),
+                ]
+            }
+            Error::UnsupportedChain(chain) => vec![("chain_id", chain.to_string())],
+            _ => vec![],
+        };
+        entries
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v))
+            .collect()
+    }
+}
+
+/// Node rejections are the caller's to fix, connection failures may be retried
+fn rpc_code(err: &Web3Error) -> Code {
+    match *err {
+        Web3Error::Rpc(_) => Code::FailedPrecondition,
+        Web3Error::Unreachable
+        | Web3Error::Transport(_)
+        | Web3Error::Io(_)
+        | Web3Error::InvalidResponse(_) => Code::Unavailable,
+        _ => Code::Internal,
+    }
+}
+
+impl From<WalletError> for Error {
+    fn from(err: WalletError) -> Self {
+        match err {
+            WalletError::RpcFault(err) => Error::Rpc(err),
+            err => Error::Wallet(err),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(err: diesel::result::Error) -> Self {
+        Error::Database(err)
+    }
+}
+
+impl From<PoolError> for Error {
+    fn from(err: PoolError) -> Self {
+        Error::Pool(err)
+    }
+}
+
+impl From<Web3Error> for Error {
+    fn from(err: Web3Error) -> Self {
+        Error::Rpc(err)
+    }
+}
+
+impl From<ContractError> for Error {
+    fn from(err: ContractError) -> Self {
+        match err {
+            ContractError::Api(err) => Error::Rpc(err),
+            err => Error::Internal(err.to_string()),
+        }
+    }
+}
+
+/// Errors of the trade module, known error types keep their own status code.
+/// Messages raised by the module itself are rejections, other errors are internal.
+impl From<Box<dyn error::Error>> for Error {
+    fn from(err: Box<dyn error::Error>) -> Self {
+        let err = match err.downcast::<WalletError>() {
+            Ok(err) => return Error::from(*err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<Web3Error>() {
+            Ok(err) => return Error::Rpc(*err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<ContractError>() {
+            Ok(err) => return Error::from(*err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<diesel::result::Error>() {
+            Ok(err) => return Error::Database(*err),
+            Err(err) => err,
+        };
+        // Errors boxed from a `String` or `&str` format their message in both ways
+        let msg = err.to_string();
+        if format!("{:?}", err) == format!("{:?}", msg) {
+            Error::Rejected(msg)
+        } else {
+            Error::Internal(msg)
+        }
+    }
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        let info = ErrorInfo {
+            reason: err.reason().to_owned(),
+            domain: DOMAIN.to_owned(),
+            metadata: err.metadata(),
+        };
+        Status::with_details(err.code(), err.to_string(), info.encode_to_vec().into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::AccountNotFound(ref user_id) => {
+                write!(f, "Account not found for user: {}", user_id)
+            }
+            Error::TransactionNotFound(ref hash) => write!(f, "Transaction not found: {}", hash),
+            Error::PositionNotFound(id) => write!(f, "Position not found: {}", id),
+            Error::InvalidArgument(ref str) => write!(f, "Invalid argument: {}", str),
+            Error::UnknownAsset(chain, ref symbol) => {
+                write!(f, "Unknown token {} on chain {}", symbol, chain)
+            }
+            Error::UnknownExchange(chain, ref exchange) => {
+                write!(f, "Unknown exchange {} on chain {}", exchange, chain)
+            }
+            Error::UnsupportedChain(chain) => write!(f, "Unsupported chain: {}", chain),
+            Error::Rejected(ref str) => f.write_str(str),
+            Error::Internal(ref str) => write!(f, "Internal error: {}", str),
+            Error::Wallet(ref err) => write!(f, "{}", err),
+            Error::Keystore(ref err) => write!(f, "Keystore error: {}", err),
+            Error::Database(ref err) => write!(f, "Database error: {}", err),
+            Error::Pool(ref err) => write!(f, "Database unavailable: {}", err),
+            Error::Rpc(ref err) => write!(f, "RPC error: {}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Service error"
+    }
+
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            Error::Wallet(ref err) => Some(err),
+            Error::Keystore(ref err) => Some(err),
+            Error::Database(ref err) => Some(err),
+            Error::Pool(ref err) => Some(err),
+            Error::Rpc(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(status: &Status) -> ErrorInfo {
+        ErrorInfo::decode(status.details()).unwrap()
+    }
+
+    #[test]
+    fn lookups_map_to_not_found_and_invalid_argument() {
+        let status = Status::from(Error::AccountNotFound("user-1".into()));
+        assert_eq!(status.code(), Code::NotFound);
+        let detail = info(&status);
+        assert_eq!(detail.reason, "ACCOUNT_NOT_FOUND");
+        assert_eq!(detail.domain, DOMAIN);
+        assert_eq!(detail.metadata["user_id"], "user-1");
+
+        let status = Status::from(Error::UnknownAsset(80001, "FOO".into()));
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let detail = info(&status);
+        assert_eq!(detail.reason, "UNKNOWN_ASSET");
+        assert_eq!(detail.metadata["chain_id"], "80001");
+        assert_eq!(detail.metadata["symbol"], "FOO");
+    }
+
+    #[test]
+    fn node_rejections_and_outages_have_distinct_codes() {
+        let rejected: web3::error::Error = Web3Error::Rpc(
+            serde_json::from_value(
+                serde_json::json!({"code": -32000, "message": "insufficient funds"}),
+            )
+            .unwrap(),
+        );
+        assert_eq!(Error::Rpc(rejected).code(), Code::FailedPrecondition);
+        assert_eq!(Error::Rpc(Web3Error::Unreachable).code(), Code::Unavailable);
+        assert_eq!(
+            Error::from(WalletError::RpcFault(Web3Error::Unreachable)).code(),
+            Code::Unavailable
+        );
+    }
+
+    #[test]
+    fn trade_errors_keep_their_type() {
+        let boxed: Box<dyn error::Error> = Box::new(Web3Error::Unreachable);
+        assert_eq!(Error::from(boxed).code(), Code::Unavailable);
+
+        let boxed: Box<dyn error::Error> = Box::new(ContractError::Api(Web3Error::Unreachable));
+        assert_eq!(Error::from(boxed).code(), Code::Unavailable);
+
+        let boxed: Box<dyn error::Error> = "Simulation reverted: STF".into();
+        let err = Error::from(boxed);
+        assert_eq!(err.code(), Code::FailedPrecondition);
+        assert_eq!(err.reason(), "REJECTED");
+        assert_eq!(err.to_string(), "Simulation reverted: STF");
+
+        let boxed: Box<dyn error::Error> = format!("Insufficient {} balance", "token0").into();
+        assert_eq!(Error::from(boxed).reason(), "REJECTED");
+    }
+
+    #[test]
+    fn unexpected_trade_errors_are_internal() {
+        let boxed: Box<dyn error::Error> = Box::new(hex::decode("0xzz").unwrap_err());
+        let err = Error::from(boxed);
+        assert_eq!(err.code(), Code::Internal);
+        assert_eq!(err.reason(), "INTERNAL_ERROR");
+
+        let boxed: Box<dyn error::Error> = Box::new(serde_json::from_str::<u32>("x").unwrap_err());
+        assert_eq!(Error::from(boxed).code(), Code::Internal);
+
+        let boxed: Box<dyn error::Error> =
+            Box::new(ContractError::InvalidOutputType("Expected Uint".into()));
+        assert_eq!(Error::from(boxed).code(), Code::Internal);
+    }
+}
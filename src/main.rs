@@ -1,5 +1,6 @@
 mod account;
 mod database;
+mod error;
 mod market;
 mod trade;
 mod wallet;
@@ -26,11 +27,18 @@ async fn main() -> Result<()> {
     let pool = build_pool().await.unwrap();
     let market = market::market::build_market().await;
     let web3 = wallet::wallet::build_wallet().await;
+    let tracker = wallet::tracker::Tracker::from_env(pool.clone());
+    tracker.clone().spawn(&web3);
 
     println!("Server listening on {}", addr);
     Server::builder()
         .add_service(account::handler::new_grpc_service(pool.clone()))
-        .add_service(trade::handler::new_grpc_service(pool.clone(), market, web3))
+        .add_service(trade::handler::new_grpc_service(
+            pool.clone(),
+            market,
+            web3,
+            tracker,
+        ))
         .serve(addr)
         .await?;
 
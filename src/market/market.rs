@@ -4,11 +4,19 @@ use web3::types::Address;
 
 const ASSETS_JSON: &str = include_str!("./json/address_book/assets.json");
 const EXCHANGES_JSON: &str = include_str!("./json/address_book/exchanges.json");
+const NATIVES_JSON: &str = include_str!("./json/address_book/natives.json");
+const APPROVALS_JSON: &str = include_str!("./json/address_book/approvals.json");
+const FEE_ON_TRANSFER_JSON: &str = include_str!("./json/address_book/fee_on_transfer.json");
 
 pub async fn build_market() -> Market {
     let assets: HashMap<u32, HashMap<String, Address>> = serde_json::from_str(ASSETS_JSON).unwrap();
     let exchanges: HashMap<u32, HashMap<String, Address>> =
         serde_json::from_str(EXCHANGES_JSON).unwrap();
+    let natives: HashMap<u32, Native> = serde_json::from_str(NATIVES_JSON).unwrap();
+    let approvals: HashMap<u32, HashMap<String, Vec<ApprovalMethod>>> =
+        serde_json::from_str(APPROVALS_JSON).unwrap();
+    let transfer_taxes: HashMap<u32, HashMap<String, u32>> =
+        serde_json::from_str(FEE_ON_TRANSFER_JSON).unwrap();
     let mut abis: HashMap<String, Vec<u8>> = HashMap::new();
     let abi_dir = env::current_dir().unwrap().join("src/market/json/abi/");
     for entry in fs::read_dir(abi_dir).unwrap() {
@@ -22,7 +30,25 @@ pub async fn build_market() -> Market {
         let abi_value = fs::read(path).unwrap();
         abis.insert(abi_name, abi_value);
     }
-    Market::new(assets, exchanges, abis).clone()
+    Market::new(assets, exchanges, natives, approvals, transfer_taxes, abis).clone()
+}
+
+/// Native currency of a chain and the asset which wraps it as an ERC20 token
+#[derive(Clone, Debug, Deserialize)]
+pub struct Native {
+    pub symbol: String,
+    /// Asset name of the wrapped-native contract
+    pub wrapped: String,
+}
+
+/// Signature based approval a token supports, next to `approve`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalMethod {
+    /// EIP-2612 `permit`
+    Permit,
+    /// Uniswap Permit2 allowances
+    Permit2,
 }
 
 /// Wrapper around a hash map that maps a [Chain] to the contract's deployed address on that chain.
@@ -30,6 +56,11 @@ pub async fn build_market() -> Market {
 pub struct Market {
     pub assets: HashMap<u32, HashMap<String, Address>>,
     pub exchanges: HashMap<u32, HashMap<String, Address>>,
+    pub natives: HashMap<u32, Native>,
+    /// Approval methods of the assets of a chain, assets not listed only support `approve`
+    pub approvals: HashMap<u32, HashMap<String, Vec<ApprovalMethod>>>,
+    /// Tax of the fee-on-transfer assets of a chain, in basis points of every transfer
+    pub transfer_taxes: HashMap<u32, HashMap<String, u32>>,
     pub abis: HashMap<String, Vec<u8>>,
 }
 
@@ -37,11 +68,17 @@ impl Market {
     pub fn new(
         assets: HashMap<u32, HashMap<String, Address>>,
         exchanges: HashMap<u32, HashMap<String, Address>>,
+        natives: HashMap<u32, Native>,
+        approvals: HashMap<u32, HashMap<String, Vec<ApprovalMethod>>>,
+        transfer_taxes: HashMap<u32, HashMap<String, u32>>,
         abis: HashMap<String, Vec<u8>>,
     ) -> Self {
         Self {
             assets,
             exchanges,
+            natives,
+            approvals,
+            transfer_taxes,
             abis,
         }
     }
@@ -51,8 +88,7 @@ impl Market {
     // }
 
     pub fn try_get_asset(&self, chain: &u32, name: &String) -> Option<Address> {
-        let assets = self.assets.get(chain).unwrap();
-        assets.get(name).cloned()
+        self.assets.get(chain)?.get(name).cloned()
     }
 
     // pub fn try_get_exchanges(&self, chain: &u32) -> Option<HashMap<String, Address>> {
@@ -60,8 +96,46 @@ impl Market {
     // }
 
     pub fn try_get_exchange(&self, chain: &u32, name: &String) -> Option<Address> {
-        let exchange = self.exchanges.get(chain).unwrap();
-        exchange.get(name).cloned()
+        self.exchanges.get(chain)?.get(name).cloned()
+    }
+
+    /// Whether `name` is the native currency of the chain rather than a listed asset
+    pub fn is_native(&self, chain: &u32, name: &str) -> bool {
+        self.natives.get(chain).is_some_and(|n| n.symbol == name)
+    }
+
+    /// Whether `name` is the asset which wraps the native currency of the chain
+    pub fn is_wrapped_native(&self, chain: &u32, name: &str) -> bool {
+        self.natives.get(chain).is_some_and(|n| n.wrapped == name)
+    }
+
+    /// Address of the wrapped-native contract of the chain
+    pub fn try_get_wrapped_native(&self, chain: &u32) -> Option<Address> {
+        let native = self.natives.get(chain)?;
+        self.try_get_asset(chain, &native.wrapped)
+    }
+
+    /// Whether the token supports a signature based approval method
+    pub fn supports_approval(&self, chain: &u32, token: &Address, method: ApprovalMethod) -> bool {
+        let (assets, approvals) = match (self.assets.get(chain), self.approvals.get(chain)) {
+            (Some(assets), Some(approvals)) => (assets, approvals),
+            _ => return false,
+        };
+        approvals
+            .iter()
+            .any(|(name, methods)| assets.get(name) == Some(token) && methods.contains(&method))
+    }
+
+    /// Transfer tax of a token in basis points, 0 for tokens which are not fee-on-transfer
+    pub fn transfer_tax(&self, chain: &u32, token: &Address) -> u32 {
+        let (assets, taxes) = match (self.assets.get(chain), self.transfer_taxes.get(chain)) {
+            (Some(assets), Some(taxes)) => (assets, taxes),
+            _ => return 0,
+        };
+        taxes
+            .iter()
+            .find(|(name, _)| assets.get(*name) == Some(token))
+            .map_or(0, |(_, tax)| *tax)
     }
 
     pub fn try_get_abi(&self, name: &String) -> Option<Vec<u8>> {
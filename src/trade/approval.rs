@@ -0,0 +1,339 @@
+//! # Approvals
+//!
+//! Routers take the input token of a swap through an ERC20 allowance, an EIP-2612
+//! permit redeemed in the swap call, or a Permit2 allowance signed for the Universal
+//! Router. Signatures are used for the tokens which support them in the market's
+//! approval registry, other tokens are approved following `APPROVAL_POLICY`, to
+//! Permit2 itself for the Universal Router.
+
+use crate::database::models::account::Account;
+use crate::database::models::approval::NewApproval;
+use crate::database::pool::PgPool;
+use crate::market::market::{ApprovalMethod, Market};
+use crate::trade::trade::Trade;
+use crate::wallet::fee::{gas_limit, Fees, Speed};
+use crate::wallet::permit::{digest, Permit, PermitDetails, PermitSingle};
+use crate::wallet::util::get_deadline;
+use crate::wallet::wallet::Wallet;
+use crate::wallet::Signature;
+use std::env;
+use std::error::Error;
+use web3::contract::{tokens::Tokenize, Contract, Options};
+use web3::ethabi::Token;
+use web3::types::{Address, Bytes, TransactionParameters, H256, U256};
+use web3::{transports::WebSocket, Web3};
+
+/// Lifetime of the Permit2 allowances signed for a spender, in seconds
+const PERMIT2_EXPIRATION: u32 = 30 * 24 * 3600;
+
+/// Largest Permit2 allowance, a `uint160`
+fn permit2_max() -> U256 {
+    (U256::one() << 160) - 1
+}
+
+/// Amount approved with `approve` when an allowance is too low
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApprovalPolicy {
+    /// The amount of the trade only
+    Exact,
+    /// Unlimited allowance, recorded in the `approvals` table
+    Unlimited,
+}
+
+impl ApprovalPolicy {
+    /// Policy from `APPROVAL_POLICY` (`exact` or `unlimited`), exact when unset
+    pub fn from_env() -> Self {
+        dotenv::dotenv().ok();
+        match env::var("APPROVAL_POLICY").as_deref() {
+            Ok("unlimited") => ApprovalPolicy::Unlimited,
+            _ => ApprovalPolicy::Exact,
+        }
+    }
+
+    fn allowance(&self, amount: U256) -> U256 {
+        match *self {
+            ApprovalPolicy::Exact => amount,
+            ApprovalPolicy::Unlimited => U256::MAX,
+        }
+    }
+}
+
+/// Contract taking the tokens, by the approval methods it accepts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Spender {
+    /// ERC20 allowances, or EIP-2612 permits redeemed with `selfPermit` in the same call
+    SelfPermit(Address),
+    /// Permit2 allowances, signed for the Universal Router's `PERMIT2_PERMIT`
+    Permit2(Address),
+}
+
+impl Spender {
+    pub fn address(&self) -> Address {
+        match *self {
+            Spender::SelfPermit(a) | Spender::Permit2(a) => a,
+        }
+    }
+}
+
+/// What the spender needs, next to its own call, to take the tokens
+#[derive(Clone, Debug)]
+pub enum Authorization {
+    /// The allowance covers the amount, possibly after an `approve` transaction
+    Approved,
+    /// EIP-2612 permit to redeem with `selfPermit`
+    Permit(SignedPermit),
+    /// Permit2 allowance to redeem with `PERMIT2_PERMIT`
+    Permit2(SignedPermitSingle),
+}
+
+#[derive(Clone, Debug)]
+pub struct SignedPermit {
+    pub token: Address,
+    pub value: U256,
+    pub deadline: U256,
+    pub signature: Signature,
+}
+
+impl SignedPermit {
+    /// `selfPermit` call of a router multicall redeeming the permit
+    pub fn self_permit_call(&self, m: &Market) -> Result<Vec<u8>, Box<dyn Error>> {
+        let abi = web3::ethabi::Contract::load(Trade::abi(m, "ISelfPermit")?.as_slice())?;
+        Ok(abi.function("selfPermit")?.encode_input(&[
+            Token::Address(self.token),
+            Token::Uint(self.value),
+            Token::Uint(self.deadline),
+            Token::Uint(U256::from(self.signature.v)),
+            Token::FixedBytes(self.signature.r.to_vec()),
+            Token::FixedBytes(self.signature.s.to_vec()),
+        ])?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SignedPermitSingle {
+    pub permit: PermitSingle,
+    pub signature: Signature,
+}
+
+impl SignedPermitSingle {
+    /// Signature in the `r || s || v` layout Permit2 verifies
+    pub fn signature_bytes(&self) -> Vec<u8> {
+        let mut sig = Vec::with_capacity(65);
+        sig.extend_from_slice(&self.signature.r);
+        sig.extend_from_slice(&self.signature.s);
+        sig.push(self.signature.v);
+        sig
+    }
+}
+
+pub struct Approval {}
+
+impl Approval {
+    /// Let `spender` take `amount` of the token until `deadline`, with a signature when
+    /// both the token and the spender support one, otherwise with `approve`. Permit2
+    /// spenders always get a signed allowance, which works with any ERC20.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn authorize(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        chain: &u32,
+        t_cont: &Contract<WebSocket>,
+        spender: Spender,
+        amount: U256,
+        deadline: U256,
+        speed: Speed,
+    ) -> Result<Authorization, Box<dyn Error>> {
+        let owner: Address = acc.address.parse()?;
+        let token = t_cont.address();
+        match spender {
+            Spender::SelfPermit(s)
+                if m.supports_approval(chain, &token, ApprovalMethod::Permit) =>
+            {
+                if Self::allowance(t_cont, owner, s).await? >= amount {
+                    return Ok(Authorization::Approved);
+                }
+                let permit = Self::permit(w3, m, acc, token, s, amount, deadline).await?;
+                Ok(Authorization::Permit(permit))
+            }
+            Spender::Permit2(s) => {
+                let permit2 = m
+                    .try_get_exchange(chain, &"Permit2".to_string())
+                    .ok_or("No Permit2 contract on chain")?;
+                // Permit2 holds an unlimited allowance of the registered tokens, other
+                // tokens are approved to it following the policy. Spenders get signed
+                // allowances from it either way.
+                let policy = if m.supports_approval(chain, &token, ApprovalMethod::Permit2) {
+                    ApprovalPolicy::Unlimited
+                } else {
+                    ApprovalPolicy::from_env()
+                };
+                Self::approve(w3, db, acc, t_cont, permit2, amount, policy, speed).await?;
+                Self::permit2(w3, m, acc, permit2, token, s, amount, deadline).await
+            }
+            _ => {
+                let policy = ApprovalPolicy::from_env();
+                let s = spender.address();
+                Self::approve(w3, db, acc, t_cont, s, amount, policy, speed).await?;
+                Ok(Authorization::Approved)
+            }
+        }
+    }
+
+    /// Approve `spender` following `policy` when its allowance is lower than `amount`.
+    /// Unlimited approvals are recorded for the account.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn approve(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        t_cont: &Contract<WebSocket>,
+        spender: Address,
+        amount: U256,
+        policy: ApprovalPolicy,
+        speed: Speed,
+    ) -> Result<(), Box<dyn Error>> {
+        let owner: Address = acc.address.parse()?;
+        if Self::allowance(t_cont, owner, spender).await? >= amount {
+            return Ok(());
+        }
+
+        let allowance = policy.allowance(amount);
+        let chain = w3.eth().chain_id().await?.as_u64();
+        let fees = Fees::estimate(w3, speed).await?;
+        let data = t_cont
+            .abi()
+            .function("approve")?
+            .encode_input(&(spender, allowance).into_tokens())?;
+        let gas_estimate = t_cont
+            .estimate_gas("approve", (spender, allowance), owner, Options::default())
+            .await?;
+        let mut tx_payload = TransactionParameters {
+            to: Some(t_cont.address()),
+            gas: gas_limit(gas_estimate),
+            value: U256::zero(),
+            data: Bytes(data),
+            chain_id: Some(chain),
+            ..Default::default()
+        };
+        fees.apply(&mut tx_payload);
+        let hash = Wallet::send(w3, db, acc, &tx_payload).await?;
+
+        if policy == ApprovalPolicy::Unlimited {
+            let mut conn = db.get()?;
+            NewApproval {
+                account_id: acc.id,
+                chain_id: chain as i64,
+                token: format!("{:?}", t_cont.address()),
+                spender: format!("{:?}", spender),
+                amount: allowance.to_string(),
+                hash: format!("{:?}", hash),
+            }
+            .create(&mut conn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn allowance(
+        t_cont: &Contract<WebSocket>,
+        owner: Address,
+        spender: Address,
+    ) -> Result<U256, Box<dyn Error>> {
+        Ok(t_cont
+            .query(
+                "allowance",
+                (owner, spender),
+                None,
+                Options::default(),
+                None,
+            )
+            .await?)
+    }
+
+    /// EIP-2612 permit of `amount` for `spender`, signed with the token's current nonce
+    pub(crate) async fn permit(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        acc: &Account,
+        token: Address,
+        spender: Address,
+        amount: U256,
+        deadline: U256,
+    ) -> Result<SignedPermit, Box<dyn Error>> {
+        let owner: Address = acc.address.parse()?;
+        let p_cont = Contract::from_json(w3.eth(), token, &Trade::abi(m, "IERC20Permit")?)?;
+        let nonce: U256 = p_cont
+            .query("nonces", owner, None, Options::default(), None)
+            .await?;
+        let domain: H256 = p_cont
+            .query("DOMAIN_SEPARATOR", (), None, Options::default(), None)
+            .await?;
+        let permit = Permit {
+            owner,
+            spender,
+            value: amount,
+            nonce,
+            deadline,
+        };
+        let signature = Wallet::sign_digest(acc, digest(&domain, &permit.struct_hash()))?;
+        Ok(SignedPermit {
+            token,
+            value: amount,
+            deadline,
+            signature,
+        })
+    }
+
+    /// Permit2 allowance of `amount` for `spender`, unless the current one still covers it.
+    /// Allowances follow `APPROVAL_POLICY` and expire after `PERMIT2_EXPIRATION`.
+    #[allow(clippy::too_many_arguments)]
+    async fn permit2(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        acc: &Account,
+        permit2: Address,
+        token: Address,
+        spender: Address,
+        amount: U256,
+        deadline: U256,
+    ) -> Result<Authorization, Box<dyn Error>> {
+        let owner: Address = acc.address.parse()?;
+        let p_cont = Contract::from_json(w3.eth(), permit2, &Trade::abi(m, "IPermit2")?)?;
+        let (allowed, expiration, nonce): (U256, U256, U256) = p_cont
+            .query(
+                "allowance",
+                (owner, token, spender),
+                None,
+                Options::default(),
+                None,
+            )
+            .await?;
+        if allowed >= amount && expiration > get_deadline(0) {
+            return Ok(Authorization::Approved);
+        }
+
+        let domain: H256 = p_cont
+            .query("DOMAIN_SEPARATOR", (), None, Options::default(), None)
+            .await?;
+        let permit = PermitSingle {
+            details: PermitDetails {
+                token,
+                amount: match ApprovalPolicy::from_env() {
+                    ApprovalPolicy::Exact => amount,
+                    ApprovalPolicy::Unlimited => permit2_max(),
+                },
+                expiration: get_deadline(PERMIT2_EXPIRATION).as_u64(),
+                nonce: nonce.as_u64(),
+            },
+            spender,
+            sig_deadline: deadline,
+        };
+        let signature = Wallet::sign_digest(acc, digest(&domain, &permit.struct_hash()))?;
+        Ok(Authorization::Permit2(SignedPermitSingle {
+            permit,
+            signature,
+        }))
+    }
+}
@@ -1,27 +1,142 @@
 use crate::database::models::account::Account;
+use crate::database::models::position::Position;
+use crate::database::models::transaction::{Transaction, TxStatus};
 use crate::database::pool::PgPool;
+use crate::error::Error;
 use crate::market::market::Market;
-use crate::trade::trade::Trade;
+use crate::trade::liquidity::{AddedLiquidity, Liquidity, RemovedLiquidity, LP_MULT};
+use crate::trade::position::{PositionChange, PositionManager, PositionState};
+use crate::trade::replace::Replace;
+use crate::trade::router::Router;
+use crate::trade::tick_math::tick_to_price;
+use crate::trade::trade::{PairState, Quote, SplitQuote, Trade};
+use crate::trade::universal::TradeUniversal;
+use crate::trade::v3::{TradeV3, FEE_TIERS};
+use crate::trade::wrap::Wrap;
+use crate::wallet::fee::Speed;
+use crate::wallet::tracker::{Tracker, TxEvent, TxEventKind};
+use crate::wallet::util::convert_from_wei;
 use crate::wallet::wallet::Wallet;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use futures::channel::mpsc;
+use futures::SinkExt;
+use tokio::sync::broadcast::error::RecvError;
 use tonic::{Request, Response, Status};
+use web3::types::{Address, H256};
+use web3::{transports::WebSocket, Web3};
 
 pub mod trade_rpc {
     tonic::include_proto!("trade");
 }
 use trade_rpc::{
     trade_service_server::{TradeService, TradeServiceServer},
-    SwapRequest, SwapResponse,
+    AddLiquidityRequest, AddLiquidityResponse, Allocation, DecreaseLiquidityRequest,
+    GetPairRequest, GetTransactionRequest, IncreaseLiquidityRequest, ListPositionsRequest,
+    ListPositionsResponse, ListTransactionsRequest, ListTransactionsResponse, MintPositionRequest,
+    PairResponse, PositionInfo, PositionRequest, PositionResponse, QuoteRequest, QuoteResponse,
+    RemoveLiquidityRequest, RemoveLiquidityResponse, ReplaceTransactionRequest,
+    ReplaceTransactionResponse, SplitQuoteResponse, SplitSwapResponse, SwapRequest, SwapResponse,
+    TransactionEvent, TransactionEventKind, TransactionInfo, WrapRequest,
 };
 
+/// Events buffered for a `watchTransaction` client
+const WATCH_BUFFER: usize = 16;
+
 pub struct TradeHandler {
     pub pool: PgPool,
     pub market: Market,
     pub web3: Wallet,
+    pub tracker: Tracker,
 }
 
 impl TradeHandler {
-    pub fn new_grpc_service(pool: PgPool, market: Market, web3: Wallet) -> Self {
-        Self { pool, market, web3 }
+    pub fn new_grpc_service(pool: PgPool, market: Market, web3: Wallet, tracker: Tracker) -> Self {
+        Self {
+            pool,
+            market,
+            web3,
+            tracker,
+        }
+    }
+
+    fn instance(&self, chain: &u32) -> Result<Web3<WebSocket>, Error> {
+        self.web3
+            .try_get_instance(chain)
+            .ok_or(Error::UnsupportedChain(*chain))
+    }
+
+    fn asset(&self, chain: &u32, symbol: &String) -> Result<Address, Error> {
+        self.market
+            .try_get_asset(chain, symbol)
+            .ok_or_else(|| Error::UnknownAsset(*chain, symbol.clone()))
+    }
+
+    fn exchange(&self, chain: &u32, name: &String) -> Result<Address, Error> {
+        self.market
+            .try_get_exchange(chain, name)
+            .ok_or_else(|| Error::UnknownExchange(*chain, name.clone()))
+    }
+
+    /// Wrap native currency of the user, or unwrap it when `unwrap` is set
+    async fn convert_native(
+        &self,
+        user_id: &String,
+        chain: &u32,
+        amount: f32,
+        speed: Speed,
+        unwrap: bool,
+    ) -> Result<H256, Error> {
+        let mut conn = self.pool.get()?;
+        let acc = find_account(user_id, &mut conn).await?;
+        let w3 = self.instance(chain)?;
+        let hash = if unwrap {
+            Wrap::unwrap(&w3, &self.pool, &acc, &self.market, chain, amount, speed).await
+        } else {
+            Wrap::wrap(&w3, &self.pool, &acc, &self.market, chain, amount, speed).await
+        };
+        Ok(hash?)
+    }
+
+    /// Speed up, or cancel when `cancel` is set, a pending transaction of the user
+    async fn replace_transaction(
+        &self,
+        payload: ReplaceTransactionRequest,
+        cancel: bool,
+    ) -> Result<Response<ReplaceTransactionResponse>, Status> {
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let original = find_transaction(&acc.id, &payload.hash, &mut conn).await?;
+        Replace::check_replaceable(&original).map_err(Error::Rejected)?;
+        let w3 = self.instance(&(original.chain_id as u32))?;
+
+        let speed = payload.speed().into();
+        let hash = if cancel {
+            Replace::cancel(&w3, &self.pool, &acc, &original, speed).await
+        } else {
+            Replace::speed_up(&w3, &self.pool, &acc, &original, speed).await
+        };
+        let hash = hash.map_err(Error::from)?;
+
+        Ok(Response::new(ReplaceTransactionResponse {
+            hash: format!("{:?}", hash),
+            replaces: original.hash,
+        }))
+    }
+
+    /// Open position of the user, refreshed from the position manager
+    async fn open_position(
+        &self,
+        user_id: &String,
+        position_id: &i32,
+    ) -> Result<(Account, Web3<WebSocket>, PositionState), Error> {
+        let mut conn = self.pool.get()?;
+        let acc = find_account(user_id, &mut conn).await?;
+        let pos = find_position(&acc.id, position_id, &mut conn).await?;
+        let w3 = self.instance(&(pos.chain_id as u32))?;
+        let state = PositionManager::refresh(&w3, &self.pool, &acc, &self.market, pos).await?;
+        PositionManager::check_open(&state.position).map_err(Error::Rejected)?;
+        Ok((acc, w3, state))
     }
 }
 
@@ -29,8 +144,9 @@ pub fn new_grpc_service(
     pool: PgPool,
     market: Market,
     web3: Wallet,
+    tracker: Tracker,
 ) -> TradeServiceServer<TradeHandler> {
-    let handler = TradeHandler::new_grpc_service(pool, market, web3);
+    let handler = TradeHandler::new_grpc_service(pool, market, web3, tracker);
     TradeServiceServer::new(handler)
 }
 
@@ -40,45 +156,682 @@ pub fn new_grpc_service(
 //     }
 // }
 
+fn into_quote_response(quote: &Quote) -> QuoteResponse {
+    QuoteResponse {
+        amount_out: convert_from_wei(quote.amount_out, quote.t1_mult),
+        amount_out_min: convert_from_wei(quote.amount_out_min, quote.t1_mult),
+        route: quote.route.iter().map(|a| format!("{:?}", a)).collect(),
+        price_impact: quote.price_impact as f32,
+        gas: quote.gas.low_u64(),
+        gas_price: quote.gas_price.to_string(),
+        gas_cost: convert_from_wei(quote.gas * quote.gas_price, 1e18),
+        fees: quote.fees.clone(),
+        exchange: quote.exchange.clone(),
+    }
+}
+
+fn into_allocations(quote: &SplitQuote) -> Vec<Allocation> {
+    quote
+        .allocations
+        .iter()
+        .map(|a| Allocation {
+            exchange: a.exchange.clone(),
+            route: a.tokens.iter().map(|t| format!("{:?}", t)).collect(),
+            amount_in: convert_from_wei(a.amount_in, quote.t0_mult),
+            amount_out: convert_from_wei(a.amount_received(), quote.t1_mult),
+        })
+        .collect()
+}
+
+fn into_add_liquidity_response(added: &AddedLiquidity) -> AddLiquidityResponse {
+    AddLiquidityResponse {
+        hash: format!("{:?}", added.hash),
+        pair: added.pair.map(|p| format!("{:?}", p)).unwrap_or_default(),
+        amount0: convert_from_wei(added.amount_a, added.a_mult),
+        amount1: convert_from_wei(added.amount_b, added.b_mult),
+        liquidity: convert_from_wei(added.liquidity, LP_MULT),
+    }
+}
+
+fn into_remove_liquidity_response(removed: &RemovedLiquidity) -> RemoveLiquidityResponse {
+    RemoveLiquidityResponse {
+        hash: format!("{:?}", removed.hash),
+        pair: format!("{:?}", removed.pair),
+        amount0: convert_from_wei(removed.amount_a, removed.a_mult),
+        amount1: convert_from_wei(removed.amount_b, removed.b_mult),
+    }
+}
+
+fn into_pair_response(pair: &PairState) -> PairResponse {
+    let liquidity = if pair.sqrt_price_x96.is_zero() {
+        String::new()
+    } else {
+        pair.liquidity.to_string()
+    };
+    PairResponse {
+        pair: format!("{:?}", pair.pair),
+        token0: format!("{:?}", pair.token0),
+        token1: format!("{:?}", pair.token1),
+        reserve0: convert_from_wei(pair.reserve0, pair.t0_mult),
+        reserve1: convert_from_wei(pair.reserve1, pair.t1_mult),
+        sqrt_price_x96: pair.sqrt_price_x96.to_string(),
+        tick: pair.tick,
+        liquidity,
+        fee: pair.fee,
+        price0: pair.price0() as f32,
+        price1: pair.price1() as f32,
+        total_supply: convert_from_wei(pair.total_supply, LP_MULT),
+    }
+}
+
+fn into_position_info(state: &PositionState) -> PositionInfo {
+    let pos = &state.position;
+    let price = |tick| tick_to_price(tick, state.t0_mult, state.t1_mult) as f32;
+    PositionInfo {
+        id: pos.id,
+        chain_id: pos.chain_id as u64,
+        token_id: pos.token_id.clone(),
+        token0: pos.token0.clone(),
+        token1: pos.token1.clone(),
+        fee: pos.fee as u32,
+        tick_lower: pos.tick_lower,
+        tick_upper: pos.tick_upper,
+        price_lower: price(pos.tick_lower),
+        price_upper: price(pos.tick_upper),
+        liquidity: pos.liquidity.clone(),
+        status: pos.status.clone(),
+    }
+}
+
+fn into_position_response(change: &PositionChange) -> PositionResponse {
+    PositionResponse {
+        hash: format!("{:?}", change.hash),
+        position: Some(into_position_info(&change.state)),
+        amount0: convert_from_wei(change.amount0, change.state.t0_mult),
+        amount1: convert_from_wei(change.amount1, change.state.t1_mult),
+    }
+}
+
+fn into_transaction_info(acc: &Account, tx: &Transaction) -> TransactionInfo {
+    TransactionInfo {
+        hash: tx.hash.clone(),
+        chain_id: tx.chain_id as u64,
+        nonce: tx.nonce as u64,
+        from: acc.address.clone(),
+        to: tx.to_address.clone().unwrap_or_default(),
+        value: tx.value.clone(),
+        data: tx.data.clone(),
+        gas_limit: tx.gas_limit as u64,
+        gas_price: tx.gas_price.clone().unwrap_or_default(),
+        max_fee_per_gas: tx.max_fee_per_gas.clone().unwrap_or_default(),
+        max_priority_fee_per_gas: tx.max_priority_fee_per_gas.clone().unwrap_or_default(),
+        tx_type: tx.tx_type as u32,
+        status: tx.status.clone(),
+        created_at: tx.created_at.and_utc().timestamp(),
+        updated_at: tx.updated_at.and_utc().timestamp(),
+        replaces: tx.replaces.clone().unwrap_or_default(),
+        replaced_by: tx.replaced_by.clone().unwrap_or_default(),
+    }
+}
+
+fn into_transaction_event(event: &TxEvent) -> TransactionEvent {
+    let kind = match event.kind {
+        TxEventKind::Pending => TransactionEventKind::Pending,
+        TxEventKind::Included => TransactionEventKind::Included,
+        TxEventKind::Confirmed => TransactionEventKind::Confirmed,
+        TxEventKind::Reverted => TransactionEventKind::Reverted,
+        TxEventKind::Replaced => TransactionEventKind::Replaced,
+        TxEventKind::Dropped => TransactionEventKind::Dropped,
+    };
+    TransactionEvent {
+        hash: event.hash.clone(),
+        kind: kind.into(),
+        block_number: event.block_number.unwrap_or_default(),
+        confirmations: event.confirmations,
+        is_final: event.is_final,
+        reason: event.reason.clone().unwrap_or_default(),
+        replaced_by: event.replaced_by.clone().unwrap_or_default(),
+    }
+}
+
+/// Stored status of a transaction as an event, confirmations counted up to the latest block
+async fn stored_event(
+    pool: &PgPool,
+    tracker: &Tracker,
+    w3: &Web3<WebSocket>,
+    account_id: &i32,
+    hash: &str,
+) -> Result<TxEvent, Error> {
+    let mut conn = pool.get()?;
+    let tx = find_transaction(account_id, hash, &mut conn).await?;
+    let head = w3.eth().block_number().await?;
+    Ok(tracker.snapshot(&tx, head.as_u64()))
+}
+
+/// Account of the user, `AccountNotFound` when there is none
+async fn find_account(
+    user_id: &String,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Account, Error> {
+    match Account::find_by_user_id(user_id, conn).await {
+        Err(diesel::result::Error::NotFound) => Err(Error::AccountNotFound(user_id.clone())),
+        res => Ok(res?),
+    }
+}
+
+/// Transaction of the account, `TransactionNotFound` when there is none
+async fn find_transaction(
+    account_id: &i32,
+    hash: &str,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Transaction, Error> {
+    match Transaction::find_by_hash(account_id, hash, conn).await {
+        Err(diesel::result::Error::NotFound) => Err(Error::TransactionNotFound(hash.to_owned())),
+        res => Ok(res?),
+    }
+}
+
+/// Position of the account, `PositionNotFound` when there is none
+async fn find_position(
+    account_id: &i32,
+    position_id: &i32,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Position, Error> {
+    match Position::find_by_id(account_id, position_id, conn).await {
+        Err(diesel::result::Error::NotFound) => Err(Error::PositionNotFound(*position_id)),
+        res => Ok(res?),
+    }
+}
+
 #[tonic::async_trait]
 impl TradeService for TradeHandler {
+    type WatchTransactionStream = mpsc::Receiver<Result<TransactionEvent, Status>>;
+
     async fn swap(&self, req: Request<SwapRequest>) -> Result<Response<SwapResponse>, Status> {
         let payload = req.into_inner();
-        let mut conn = self.pool.get().expect("Failed to get connection from pool");
-        let acc = Account::find_by_user_id(&payload.user_id, &mut conn)
+        Trade::check_amount(payload.amount).map_err(Error::InvalidArgument)?;
+        Trade::check_slippage(payload.slippage).map_err(Error::InvalidArgument)?;
+        Trade::check_deadline(payload.deadline).map_err(Error::InvalidArgument)?;
+        // Native currency and its wrapped token convert 1:1, without the router
+        let (m, chain) = (&self.market, &payload.chain_id);
+        let wrap =
+            m.is_native(chain, &payload.token0) && m.is_wrapped_native(chain, &payload.token1);
+        let unwrap =
+            m.is_wrapped_native(chain, &payload.token0) && m.is_native(chain, &payload.token1);
+        if wrap || unwrap {
+            let speed = payload.speed().into();
+            let hash = self
+                .convert_native(&payload.user_id, chain, payload.amount, speed, unwrap)
+                .await?;
+            return Ok(Response::new(SwapResponse {
+                hash: format!("{:?}", hash),
+            }));
+        }
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let w3 = self.instance(&payload.chain_id)?;
+        let t0 = self.asset(&payload.chain_id, &payload.token0)?;
+        let t1 = self.asset(&payload.chain_id, &payload.token1)?;
+        let tx_hash = if TradeV3::supports(&payload.exchange) {
+            let ex = self.exchange(&payload.chain_id, &payload.exchange)?;
+            TradeV3::swap(&w3, &self.pool, &acc, &self.market, &ex, &t0, &t1, &payload).await
+        } else if TradeUniversal::supports(&payload.exchange) {
+            let ex = self.exchange(&payload.chain_id, &payload.exchange)?;
+            let m = &self.market;
+            TradeUniversal::swap(&w3, &self.pool, &acc, m, &ex, &t0, &t1, &payload).await
+        } else if Router::supports(&payload.exchange) {
+            Trade::swap(&w3, &self.pool, &acc, &self.market, &t0, &t1, &payload).await
+        } else {
+            return Err(Error::UnknownExchange(payload.chain_id, payload.exchange).into());
+        }
+        .map_err(Error::from)?;
+
+        Ok(Response::new(SwapResponse {
+            hash: format!("{:?}", tx_hash),
+        }))
+    }
+
+    async fn quote(&self, req: Request<QuoteRequest>) -> Result<Response<QuoteResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_amount(payload.amount).map_err(Error::InvalidArgument)?;
+        Trade::check_slippage(payload.slippage).map_err(Error::InvalidArgument)?;
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let w3 = self.instance(&payload.chain_id)?;
+        let t0 = self.asset(&payload.chain_id, &payload.token0)?;
+        let t1 = self.asset(&payload.chain_id, &payload.token1)?;
+        let quote = if TradeV3::supports(&payload.exchange) {
+            let ex = self.exchange(&payload.chain_id, &payload.exchange)?;
+            TradeV3::quote(&w3, &acc, &self.market, &ex, &t0, &t1, &payload).await
+        } else if TradeUniversal::supports(&payload.exchange) {
+            let ex = self.exchange(&payload.chain_id, &payload.exchange)?;
+            TradeUniversal::quote(&w3, &acc, &self.market, &ex, &t0, &t1, &payload).await
+        } else if Router::supports(&payload.exchange) {
+            Trade::quote(&w3, &acc, &self.market, &t0, &t1, &payload).await
+        } else {
+            return Err(Error::UnknownExchange(payload.chain_id, payload.exchange).into());
+        };
+        let quote = match quote {
+            Ok(quote) => quote,
+            Err(e) => return Err(Error::from(e).into()),
+        };
+
+        Ok(Response::new(into_quote_response(&quote)))
+    }
+
+    async fn split_quote(
+        &self,
+        req: Request<QuoteRequest>,
+    ) -> Result<Response<SplitQuoteResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_amount(payload.amount).map_err(Error::InvalidArgument)?;
+        Trade::check_slippage(payload.slippage).map_err(Error::InvalidArgument)?;
+        if !Router::supports(&payload.exchange) {
+            return Err(Error::UnknownExchange(payload.chain_id, payload.exchange).into());
+        }
+        let w3 = self.instance(&payload.chain_id)?;
+        let t0 = self.asset(&payload.chain_id, &payload.token0)?;
+        let t1 = self.asset(&payload.chain_id, &payload.token1)?;
+
+        let quote = match Trade::split_quote(&w3, &self.market, &t0, &t1, &payload).await {
+            Ok(quote) => quote,
+            Err(e) => return Err(Error::from(e).into()),
+        };
+
+        Ok(Response::new(SplitQuoteResponse {
+            allocations: into_allocations(&quote),
+            amount_out: convert_from_wei(quote.amount_out, quote.t1_mult),
+            amount_out_min: convert_from_wei(quote.amount_out_min, quote.t1_mult),
+        }))
+    }
+
+    async fn split_swap(
+        &self,
+        req: Request<SwapRequest>,
+    ) -> Result<Response<SplitSwapResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_amount(payload.amount).map_err(Error::InvalidArgument)?;
+        Trade::check_slippage(payload.slippage).map_err(Error::InvalidArgument)?;
+        Trade::check_deadline(payload.deadline).map_err(Error::InvalidArgument)?;
+        if !Router::supports(&payload.exchange) {
+            return Err(Error::UnknownExchange(payload.chain_id, payload.exchange).into());
+        }
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let w3 = self.instance(&payload.chain_id)?;
+        let t0 = self.asset(&payload.chain_id, &payload.token0)?;
+        let t1 = self.asset(&payload.chain_id, &payload.token1)?;
+
+        let (quote, hashes) = match Trade::split_swap(
+            &w3,
+            &self.pool,
+            &acc,
+            &self.market,
+            &t0,
+            &t1,
+            &payload,
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(e) => return Err(Error::from(e).into()),
+        };
+
+        Ok(Response::new(SplitSwapResponse {
+            allocations: into_allocations(&quote),
+            hashes: hashes.iter().map(|h| format!("{:?}", h)).collect(),
+        }))
+    }
+
+    async fn wrap(&self, req: Request<WrapRequest>) -> Result<Response<SwapResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_amount(payload.amount).map_err(Error::InvalidArgument)?;
+        let speed = payload.speed().into();
+        let hash = self
+            .convert_native(
+                &payload.user_id,
+                &payload.chain_id,
+                payload.amount,
+                speed,
+                false,
+            )
+            .await?;
+        Ok(Response::new(SwapResponse {
+            hash: format!("{:?}", hash),
+        }))
+    }
+
+    async fn unwrap(&self, req: Request<WrapRequest>) -> Result<Response<SwapResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_amount(payload.amount).map_err(Error::InvalidArgument)?;
+        let speed = payload.speed().into();
+        let hash = self
+            .convert_native(
+                &payload.user_id,
+                &payload.chain_id,
+                payload.amount,
+                speed,
+                true,
+            )
+            .await?;
+        Ok(Response::new(SwapResponse {
+            hash: format!("{:?}", hash),
+        }))
+    }
+
+    async fn add_liquidity(
+        &self,
+        req: Request<AddLiquidityRequest>,
+    ) -> Result<Response<AddLiquidityResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_amount(payload.amount0).map_err(Error::InvalidArgument)?;
+        Trade::check_amount(payload.amount1).map_err(Error::InvalidArgument)?;
+        Trade::check_slippage(payload.slippage).map_err(Error::InvalidArgument)?;
+        Trade::check_deadline(payload.deadline).map_err(Error::InvalidArgument)?;
+        if !Liquidity::supports(&payload.exchange) {
+            return Err(Error::UnknownExchange(payload.chain_id, payload.exchange).into());
+        }
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let w3 = self.instance(&payload.chain_id)?;
+        let ex = self.exchange(&payload.chain_id, &payload.exchange)?;
+        let ta = self.asset(&payload.chain_id, &payload.token0)?;
+        let tb = self.asset(&payload.chain_id, &payload.token1)?;
+
+        let m = &self.market;
+        let added = Liquidity::add(&w3, &self.pool, &acc, m, &ex, &ta, &tb, &payload)
+            .await
+            .map_err(Error::from)?;
+        Ok(Response::new(into_add_liquidity_response(&added)))
+    }
+
+    async fn remove_liquidity(
+        &self,
+        req: Request<RemoveLiquidityRequest>,
+    ) -> Result<Response<RemoveLiquidityResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_amount(payload.liquidity).map_err(Error::InvalidArgument)?;
+        Trade::check_slippage(payload.slippage).map_err(Error::InvalidArgument)?;
+        Trade::check_deadline(payload.deadline).map_err(Error::InvalidArgument)?;
+        if !Liquidity::supports(&payload.exchange) {
+            return Err(Error::UnknownExchange(payload.chain_id, payload.exchange).into());
+        }
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let w3 = self.instance(&payload.chain_id)?;
+        let ex = self.exchange(&payload.chain_id, &payload.exchange)?;
+        let ta = self.asset(&payload.chain_id, &payload.token0)?;
+        let tb = self.asset(&payload.chain_id, &payload.token1)?;
+
+        let m = &self.market;
+        let removed = Liquidity::remove(&w3, &self.pool, &acc, m, &ex, &ta, &tb, &payload)
             .await
-            .expect("Failed to get asset from market");
-        let w3 = self
-            .web3
-            .try_get_instance(&payload.chain_id)
-            .expect("Failed to get web3 instance from web3");
-        let t0 = self
-            .market
-            .try_get_asset(&payload.chain_id, &payload.token0)
-            .expect("Failed to get asset from market");
-        let t1 = self
-            .market
-            .try_get_asset(&payload.chain_id, &payload.token1)
-            .expect("Failed to get asset from market");
-        let ex = self
-            .market
-            .try_get_exchange(&payload.chain_id, &payload.exchange)
-            .expect("Failed to get exchange from market");
-        let r_abi = self
-            .market
-            .try_get_abi(&"IUniswapV2Router02".to_string())
-            .expect("Failed to get abi from market");
-        let t0_abi = self
-            .market
-            .try_get_abi(&"IERC20".to_string())
-            .expect("Failed to get abi from market");
-
-        let tx_hash = Trade::swap(&w3, &acc, &r_abi, &t0_abi, &ex, &t0, &t1, &payload)
+            .map_err(Error::from)?;
+        Ok(Response::new(into_remove_liquidity_response(&removed)))
+    }
+
+    async fn mint_position(
+        &self,
+        req: Request<MintPositionRequest>,
+    ) -> Result<Response<PositionResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_amounts(payload.amount0, payload.amount1).map_err(Error::InvalidArgument)?;
+        Trade::check_slippage(payload.slippage).map_err(Error::InvalidArgument)?;
+        Trade::check_deadline(payload.deadline).map_err(Error::InvalidArgument)?;
+        PositionManager::check_range(payload.fee, payload.price_lower, payload.price_upper)
+            .map_err(Error::InvalidArgument)?;
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let chain = payload.chain_id;
+        let w3 = self.instance(&chain)?;
+        let ta = self.asset(&chain, &payload.token0)?;
+        let tb = self.asset(&chain, &payload.token1)?;
+
+        let m = &self.market;
+        let minted = PositionManager::mint(&w3, &self.pool, &acc, m, &chain, &ta, &tb, &payload)
             .await
-            .unwrap();
+            .map_err(Error::from)?;
+        Ok(Response::new(into_position_response(&minted)))
+    }
 
-        Ok(Response::new(SwapResponse {
-            hash: tx_hash.to_string(),
+    async fn increase_liquidity(
+        &self,
+        req: Request<IncreaseLiquidityRequest>,
+    ) -> Result<Response<PositionResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_amounts(payload.amount0, payload.amount1).map_err(Error::InvalidArgument)?;
+        Trade::check_slippage(payload.slippage).map_err(Error::InvalidArgument)?;
+        Trade::check_deadline(payload.deadline).map_err(Error::InvalidArgument)?;
+        let (acc, w3, state) = self
+            .open_position(&payload.user_id, &payload.position_id)
+            .await?;
+
+        let m = &self.market;
+        let change = PositionManager::increase(&w3, &self.pool, &acc, m, state, &payload)
+            .await
+            .map_err(Error::from)?;
+        Ok(Response::new(into_position_response(&change)))
+    }
+
+    async fn decrease_liquidity(
+        &self,
+        req: Request<DecreaseLiquidityRequest>,
+    ) -> Result<Response<PositionResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_slippage(payload.slippage).map_err(Error::InvalidArgument)?;
+        Trade::check_deadline(payload.deadline).map_err(Error::InvalidArgument)?;
+        PositionManager::check_percentage(payload.percentage).map_err(Error::InvalidArgument)?;
+        let (acc, w3, state) = self
+            .open_position(&payload.user_id, &payload.position_id)
+            .await?;
+
+        let m = &self.market;
+        let change = PositionManager::decrease(&w3, &self.pool, &acc, m, state, &payload)
+            .await
+            .map_err(Error::from)?;
+        Ok(Response::new(into_position_response(&change)))
+    }
+
+    async fn collect_fees(
+        &self,
+        req: Request<PositionRequest>,
+    ) -> Result<Response<PositionResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_deadline(payload.deadline).map_err(Error::InvalidArgument)?;
+        let (acc, w3, state) = self
+            .open_position(&payload.user_id, &payload.position_id)
+            .await?;
+
+        let m = &self.market;
+        let change = PositionManager::collect(&w3, &self.pool, &acc, m, state, &payload)
+            .await
+            .map_err(Error::from)?;
+        Ok(Response::new(into_position_response(&change)))
+    }
+
+    async fn burn_position(
+        &self,
+        req: Request<PositionRequest>,
+    ) -> Result<Response<PositionResponse>, Status> {
+        let payload = req.into_inner();
+        Trade::check_slippage(payload.slippage).map_err(Error::InvalidArgument)?;
+        Trade::check_deadline(payload.deadline).map_err(Error::InvalidArgument)?;
+        let (acc, w3, state) = self
+            .open_position(&payload.user_id, &payload.position_id)
+            .await?;
+
+        let m = &self.market;
+        let change = PositionManager::burn(&w3, &self.pool, &acc, m, state, &payload)
+            .await
+            .map_err(Error::from)?;
+        Ok(Response::new(into_position_response(&change)))
+    }
+
+    async fn list_positions(
+        &self,
+        req: Request<ListPositionsRequest>,
+    ) -> Result<Response<ListPositionsResponse>, Status> {
+        let payload = req.into_inner();
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let w3 = self.instance(&payload.chain_id)?;
+        let chain = payload.chain_id as i64;
+        let stored = Position::list_by_account(&acc.id, &chain, &mut conn)
+            .await
+            .map_err(Error::from)?;
+
+        let mut positions = vec![];
+        for pos in stored {
+            let state = PositionManager::refresh(&w3, &self.pool, &acc, &self.market, pos)
+                .await
+                .map_err(Error::from)?;
+            positions.push(into_position_info(&state));
+        }
+        Ok(Response::new(ListPositionsResponse { positions }))
+    }
+
+    async fn get_pair(
+        &self,
+        req: Request<GetPairRequest>,
+    ) -> Result<Response<PairResponse>, Status> {
+        let payload = req.into_inner();
+        let chain = payload.chain_id;
+        let w3 = self.instance(&chain)?;
+        let ex = self.exchange(&chain, &payload.exchange)?;
+        let ta = self.asset(&chain, &payload.token0)?;
+        let tb = self.asset(&chain, &payload.token1)?;
+        if ta == tb {
+            return Err(Error::InvalidArgument("Tokens of a pair must differ".into()).into());
+        }
+
+        let m = &self.market;
+        let pair = if TradeV3::supports(&payload.exchange) {
+            if payload.fee != 0 && !FEE_TIERS.contains(&payload.fee) {
+                let msg = format!("Unsupported fee tier {}", payload.fee);
+                return Err(Error::InvalidArgument(msg).into());
+            }
+            TradeV3::pool(&w3, m, &chain, &ta, &tb, payload.fee).await
+        } else if Liquidity::supports(&payload.exchange) {
+            Trade::pair(&w3, m, &ex, &ta, &tb).await
+        } else {
+            return Err(Error::UnknownExchange(chain, payload.exchange).into());
+        };
+        Ok(Response::new(into_pair_response(
+            &pair.map_err(Error::from)?,
+        )))
+    }
+
+    async fn list_transactions(
+        &self,
+        req: Request<ListTransactionsRequest>,
+    ) -> Result<Response<ListTransactionsResponse>, Status> {
+        let payload = req.into_inner();
+        let status = match payload.status.as_str() {
+            "" => None,
+            s => Some(s.parse::<TxStatus>().map_err(Error::InvalidArgument)?),
+        };
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let txs = Transaction::list_by_account(&acc.id, status, &mut conn)
+            .await
+            .map_err(Error::from)?;
+
+        Ok(Response::new(ListTransactionsResponse {
+            transactions: txs
+                .iter()
+                .map(|tx| into_transaction_info(&acc, tx))
+                .collect(),
         }))
     }
+
+    async fn get_transaction(
+        &self,
+        req: Request<GetTransactionRequest>,
+    ) -> Result<Response<TransactionInfo>, Status> {
+        let payload = req.into_inner();
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let tx = find_transaction(&acc.id, &payload.hash, &mut conn).await?;
+
+        Ok(Response::new(into_transaction_info(&acc, &tx)))
+    }
+
+    async fn watch_transaction(
+        &self,
+        req: Request<GetTransactionRequest>,
+    ) -> Result<Response<Self::WatchTransactionStream>, Status> {
+        let payload = req.into_inner();
+        // Subscribe before reading the stored status, so no event is missed in between
+        let mut events = self.tracker.subscribe();
+        let mut conn = self.pool.get().map_err(Error::from)?;
+        let acc = find_account(&payload.user_id, &mut conn).await?;
+        let tx = find_transaction(&acc.id, &payload.hash, &mut conn).await?;
+        let w3 = self.instance(&(tx.chain_id as u32))?;
+        let mut event = stored_event(&self.pool, &self.tracker, &w3, &acc.id, &tx.hash).await?;
+
+        let (mut sender, receiver) = mpsc::channel(WATCH_BUFFER);
+        let (pool, tracker) = (self.pool.clone(), self.tracker.clone());
+        tokio::spawn(async move {
+            let mut hash = tx.hash;
+            loop {
+                // Sending fails once the client is gone
+                if sender
+                    .send(Ok(into_transaction_event(&event)))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                if let Some(by) = &event.replaced_by {
+                    // Follow the transaction which took the nonce
+                    hash = by.clone();
+                    event = match stored_event(&pool, &tracker, &w3, &acc.id, &hash).await {
+                        Ok(e) => e,
+                        Err(e) => {
+                            let _ = sender.send(Err(e.into())).await;
+                            return;
+                        }
+                    };
+                    continue;
+                }
+                if event.is_final {
+                    return;
+                }
+                event = loop {
+                    match events.recv().await {
+                        Ok(e) if e.hash == hash => break e,
+                        Ok(_) => continue,
+                        // Missed events may include the final one, read the stored status
+                        Err(RecvError::Lagged(_)) => {
+                            match stored_event(&pool, &tracker, &w3, &acc.id, &hash).await {
+                                Ok(e) if e.is_final => break e,
+                                Ok(_) => continue,
+                                Err(e) => {
+                                    let _ = sender.send(Err(e.into())).await;
+                                    return;
+                                }
+                            }
+                        }
+                        Err(RecvError::Closed) => return,
+                    }
+                };
+            }
+        });
+
+        Ok(Response::new(receiver))
+    }
+
+    async fn speed_up_transaction(
+        &self,
+        req: Request<ReplaceTransactionRequest>,
+    ) -> Result<Response<ReplaceTransactionResponse>, Status> {
+        self.replace_transaction(req.into_inner(), false).await
+    }
+
+    async fn cancel_transaction(
+        &self,
+        req: Request<ReplaceTransactionRequest>,
+    ) -> Result<Response<ReplaceTransactionResponse>, Status> {
+        self.replace_transaction(req.into_inner(), true).await
+    }
 }
@@ -0,0 +1,401 @@
+//! # Uniswap V2 liquidity
+//!
+//! Deposits into and withdrawals from the pairs of a V2 router. Deposits are sized
+//! from the pair's reserves the way the router does, withdrawals are priced from the
+//! share of the reserves the LP tokens stand for. Both are bounded by the slippage
+//! tolerance, and the amounts reported are the ones simulated on the pending block.
+
+use crate::database::models::account::Account;
+use crate::database::pool::PgPool;
+use crate::market::market::Market;
+use crate::trade::approval::{Approval, ApprovalPolicy};
+use crate::trade::handler::trade_rpc::{AddLiquidityRequest, RemoveLiquidityRequest};
+use crate::trade::router::V2_ROUTERS;
+use crate::trade::trade::Trade;
+use crate::wallet::fee::{gas_limit, Fees, Speed};
+use crate::wallet::util::{apply_slippage, convert_to_wei, get_deadline};
+use crate::wallet::wallet::Wallet;
+use std::error::Error;
+use web3::contract::{tokens::Tokenize, Contract, Options};
+use web3::ethabi::Token;
+use web3::types::{Address, Bytes, CallRequest, TransactionParameters, H256, U256};
+use web3::{transports::WebSocket, Web3};
+
+/// LP tokens of V2 pairs have 18 decimals
+pub const LP_MULT: f32 = 1e18;
+
+/// Deposit into a pair
+#[derive(Clone, Debug)]
+pub struct AddedLiquidity {
+    pub hash: H256,
+    /// `None` when the deposit creates the pair
+    pub pair: Option<Address>,
+    pub amount_a: U256,
+    pub amount_b: U256,
+    /// LP tokens minted
+    pub liquidity: U256,
+    pub a_mult: f32,
+    pub b_mult: f32,
+}
+
+/// Withdrawal from a pair
+#[derive(Clone, Debug)]
+pub struct RemovedLiquidity {
+    pub hash: H256,
+    pub pair: Address,
+    pub amount_a: U256,
+    pub amount_b: U256,
+    pub a_mult: f32,
+    pub b_mult: f32,
+}
+
+/// Amount of token B worth `amount_a` at the reserves price, like `UniswapV2Library.quote`
+pub fn quote(amount_a: U256, reserve_a: U256, reserve_b: U256) -> U256 {
+    amount_a * reserve_b / reserve_a
+}
+
+/// Amounts deposited out of the desired ones, picked like the router's `_addLiquidity`:
+/// the whole of one token and its worth of the other. The first deposit sets the price.
+pub fn optimal_amounts(
+    desired_a: U256,
+    desired_b: U256,
+    reserve_a: U256,
+    reserve_b: U256,
+) -> (U256, U256) {
+    if reserve_a.is_zero() && reserve_b.is_zero() {
+        return (desired_a, desired_b);
+    }
+    let b_optimal = quote(desired_a, reserve_a, reserve_b);
+    if b_optimal <= desired_b {
+        (desired_a, b_optimal)
+    } else {
+        (quote(desired_b, reserve_b, reserve_a), desired_b)
+    }
+}
+
+/// Share of the reserves paid out for burning `liquidity` out of `total_supply`
+pub fn withdrawn_amounts(
+    liquidity: U256,
+    total_supply: U256,
+    reserve_a: U256,
+    reserve_b: U256,
+) -> (U256, U256) {
+    if total_supply.is_zero() {
+        return (U256::zero(), U256::zero());
+    }
+    (
+        liquidity * reserve_a / total_supply,
+        liquidity * reserve_b / total_supply,
+    )
+}
+
+pub struct Liquidity {}
+
+impl Liquidity {
+    /// Liquidity is managed on a named V2 router
+    pub fn supports(exchange: &str) -> bool {
+        V2_ROUTERS.contains(&exchange)
+    }
+
+    /// Deposit at most `amount0` of token A and `amount1` of token B, with
+    /// `addLiquidityETH` when one of them is the router's wrapped native token
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        r: &Address,
+        ta: &Address,
+        tb: &Address,
+        p: &AddLiquidityRequest,
+    ) -> Result<AddedLiquidity, Box<dyn Error>> {
+        if ta == tb {
+            return Err("Tokens of a pair must differ".into());
+        }
+        let addr: Address = acc.address.parse()?;
+        let r_cont = Contract::from_json(w3.eth(), *r, &Trade::abi(m, "IUniswapV2Router02")?)?;
+        let weth: Address = r_cont
+            .query("WETH", (), None, Options::default(), None)
+            .await?;
+        let t_abi = Trade::abi(m, "IERC20")?;
+        let ta_cont = Contract::from_json(w3.eth(), *ta, &t_abi)?;
+        let tb_cont = Contract::from_json(w3.eth(), *tb, &t_abi)?;
+        let a_mult = Trade::multiplier(&ta_cont).await?;
+        let b_mult = Trade::multiplier(&tb_cont).await?;
+
+        let desired_a = convert_to_wei(p.amount0, a_mult)?;
+        let desired_b = convert_to_wei(p.amount1, b_mult)?;
+        let pair = Self::pair(w3, m, &r_cont, ta, tb).await?;
+        let (reserve_a, reserve_b) = match pair {
+            Some(pair) => Self::reserves(w3, m, pair, ta).await?,
+            None => (U256::zero(), U256::zero()),
+        };
+        let (amount_a, amount_b) = optimal_amounts(desired_a, desired_b, reserve_a, reserve_b);
+        let min_a = apply_slippage(amount_a, p.slippage);
+        let min_b = apply_slippage(amount_b, p.slippage);
+
+        let speed = p.speed().into();
+        let policy = ApprovalPolicy::from_env();
+        let deposits = [
+            ("token0", &ta_cont, desired_a),
+            ("token1", &tb_cont, desired_b),
+        ];
+        for (name, t_cont, amount) in deposits {
+            if t_cont.address() == weth {
+                let balance = w3.eth().balance(addr, None).await?;
+                if balance < amount {
+                    return Err("Insufficient native balance".into());
+                }
+                continue;
+            }
+            let balance: U256 = t_cont
+                .query("balanceOf", addr, None, Options::default(), None)
+                .await?;
+            if balance < amount {
+                return Err(format!("Insufficient {} balance", name).into());
+            }
+            Approval::approve(w3, db, acc, t_cont, *r, amount, policy, speed).await?;
+        }
+
+        let deadline = get_deadline(p.deadline);
+        let (func, params, value) = if ta == &weth {
+            let params = (*tb, desired_b, min_b, min_a, addr, deadline);
+            ("addLiquidityETH", params.into_tokens(), desired_a)
+        } else if tb == &weth {
+            let params = (*ta, desired_a, min_a, min_b, addr, deadline);
+            ("addLiquidityETH", params.into_tokens(), desired_b)
+        } else {
+            let params = (*ta, *tb, desired_a, desired_b, min_a, min_b, addr, deadline);
+            ("addLiquidity", params.into_tokens(), U256::zero())
+        };
+        let (out, hash) = Self::send(w3, db, acc, &r_cont, func, &params, value, speed).await?;
+
+        // addLiquidityETH returns the token amount first
+        let (amount_a, amount_b) = if ta == &weth {
+            (out[1], out[0])
+        } else {
+            (out[0], out[1])
+        };
+        Ok(AddedLiquidity {
+            hash,
+            pair,
+            amount_a,
+            amount_b,
+            liquidity: out[2],
+            a_mult,
+            b_mult,
+        })
+    }
+
+    /// Burn `liquidity` LP tokens of the pair, with `removeLiquidityETH` when one of the
+    /// tokens is the router's wrapped native token. Without an allowance for the router
+    /// the LP tokens are approved with a permit signature, in the `WithPermit` variants.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn remove(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        r: &Address,
+        ta: &Address,
+        tb: &Address,
+        p: &RemoveLiquidityRequest,
+    ) -> Result<RemovedLiquidity, Box<dyn Error>> {
+        if ta == tb {
+            return Err("Tokens of a pair must differ".into());
+        }
+        let addr: Address = acc.address.parse()?;
+        let r_cont = Contract::from_json(w3.eth(), *r, &Trade::abi(m, "IUniswapV2Router02")?)?;
+        let weth: Address = r_cont
+            .query("WETH", (), None, Options::default(), None)
+            .await?;
+        let eth = ta == &weth || tb == &weth;
+        let token = if ta == &weth { *tb } else { *ta };
+        // The router takes the tokens before sending them on, so a transfer tax makes
+        // it short of the amount it pays out
+        if eth && m.transfer_tax(&p.chain_id, &token) > 0 {
+            return Err("Fee-on-transfer tokens can't be withdrawn with native currency".into());
+        }
+        let t_abi = Trade::abi(m, "IERC20")?;
+        let a_mult = Trade::multiplier(&Contract::from_json(w3.eth(), *ta, &t_abi)?).await?;
+        let b_mult = Trade::multiplier(&Contract::from_json(w3.eth(), *tb, &t_abi)?).await?;
+
+        let pair = Self::pair(w3, m, &r_cont, ta, tb)
+            .await?
+            .ok_or("No pair for token pair")?;
+        let p_cont = Contract::from_json(w3.eth(), pair, &Trade::abi(m, "IUniswapV2Pair")?)?;
+        let liquidity = convert_to_wei(p.liquidity, LP_MULT)?;
+        let balance: U256 = p_cont
+            .query("balanceOf", addr, None, Options::default(), None)
+            .await?;
+        if balance < liquidity {
+            return Err("Insufficient liquidity balance".into());
+        }
+
+        let (reserve_a, reserve_b) = Self::reserves(w3, m, pair, ta).await?;
+        let total_supply: U256 = p_cont
+            .query("totalSupply", (), None, Options::default(), None)
+            .await?;
+        let (amount_a, amount_b) = withdrawn_amounts(liquidity, total_supply, reserve_a, reserve_b);
+        let min_a = apply_slippage(amount_a, p.slippage);
+        let min_b = apply_slippage(amount_b, p.slippage);
+
+        let deadline = get_deadline(p.deadline);
+        let (func, mut params) = if eth {
+            let (token_min, eth_min) = if ta == &weth {
+                (min_b, min_a)
+            } else {
+                (min_a, min_b)
+            };
+            let params = (token, liquidity, token_min, eth_min, addr, deadline);
+            ("removeLiquidityETH".to_string(), params.into_tokens())
+        } else {
+            let params = (*ta, *tb, liquidity, min_a, min_b, addr, deadline);
+            ("removeLiquidity".to_string(), params.into_tokens())
+        };
+        let allowance: U256 = p_cont
+            .query("allowance", (addr, *r), None, Options::default(), None)
+            .await?;
+        let func = if allowance < liquidity {
+            let permit = Approval::permit(w3, m, acc, pair, *r, liquidity, deadline).await?;
+            params.extend([
+                Token::Bool(false),
+                Token::Uint(U256::from(permit.signature.v)),
+                Token::FixedBytes(permit.signature.r.to_vec()),
+                Token::FixedBytes(permit.signature.s.to_vec()),
+            ]);
+            format!("{}WithPermit", func)
+        } else {
+            func
+        };
+
+        let speed = p.speed().into();
+        let (out, hash) =
+            Self::send(w3, db, acc, &r_cont, &func, &params, U256::zero(), speed).await?;
+        // removeLiquidityETH returns the token amount first
+        let (amount_a, amount_b) = if ta == &weth {
+            (out[1], out[0])
+        } else {
+            (out[0], out[1])
+        };
+        Ok(RemovedLiquidity {
+            hash,
+            pair,
+            amount_a,
+            amount_b,
+            a_mult,
+            b_mult,
+        })
+    }
+
+    /// Pair of the router's factory, `None` when it doesn't exist yet
+    async fn pair(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        r_cont: &Contract<WebSocket>,
+        ta: &Address,
+        tb: &Address,
+    ) -> Result<Option<Address>, Box<dyn Error>> {
+        let factory: Address = r_cont
+            .query("factory", (), None, Options::default(), None)
+            .await?;
+        let f_cont = Contract::from_json(w3.eth(), factory, &Trade::abi(m, "IUniswapV2Factory")?)?;
+        let pair: Address = f_cont
+            .query("getPair", (*ta, *tb), None, Options::default(), None)
+            .await?;
+        Ok(if pair.is_zero() { None } else { Some(pair) })
+    }
+
+    /// Reserves of the pair, token A's first
+    async fn reserves(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        pair: Address,
+        ta: &Address,
+    ) -> Result<(U256, U256), Box<dyn Error>> {
+        let p_cont = Contract::from_json(w3.eth(), pair, &Trade::abi(m, "IUniswapV2Pair")?)?;
+        let token0: Address = p_cont
+            .query("token0", (), None, Options::default(), None)
+            .await?;
+        let (r0, r1, _): (U256, U256, U256) = p_cont
+            .query("getReserves", (), None, Options::default(), None)
+            .await?;
+        Ok(if &token0 == ta { (r0, r1) } else { (r1, r0) })
+    }
+
+    /// Simulate a router call, then send it. Returns the amounts output by the simulation.
+    #[allow(clippy::too_many_arguments)]
+    async fn send(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        r_cont: &Contract<WebSocket>,
+        func: &str,
+        params: &[Token],
+        value: U256,
+        speed: Speed,
+    ) -> Result<(Vec<U256>, H256), Box<dyn Error>> {
+        let addr: Address = acc.address.parse()?;
+        let f = r_cont.abi().function(func)?;
+        let data = Bytes(f.encode_input(params)?);
+        let call = CallRequest {
+            from: Some(addr),
+            to: Some(r_cont.address()),
+            value: Some(value),
+            data: Some(data.clone()),
+            ..Default::default()
+        };
+        let output = Trade::simulate(w3, &call).await?;
+        let amounts = f
+            .decode_output(&output.0)?
+            .into_iter()
+            .filter_map(Token::into_uint)
+            .collect();
+
+        let fees = Fees::estimate(w3, speed).await?;
+        let gas = Trade::estimate_gas(w3, call).await?;
+        let mut tx_payload = TransactionParameters {
+            to: Some(r_cont.address()),
+            gas: gas_limit(gas),
+            value,
+            data,
+            ..Default::default()
+        };
+        fees.apply(&mut tx_payload);
+        let hash = Wallet::send(w3, db, acc, &tx_payload).await?;
+        Ok((amounts, hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deposit_keeps_the_pair_price() {
+        let (reserve_a, reserve_b) = (U256::from(1_000), U256::from(4_000));
+        // Token B is the limiting one
+        let (a, b) = optimal_amounts(U256::from(100), U256::from(200), reserve_a, reserve_b);
+        assert_eq!((a, b), (U256::from(50), U256::from(200)));
+        // Token A is the limiting one
+        let (a, b) = optimal_amounts(U256::from(100), U256::from(1_000), reserve_a, reserve_b);
+        assert_eq!((a, b), (U256::from(100), U256::from(400)));
+        // The first deposit sets the price
+        let (a, b) = optimal_amounts(U256::from(7), U256::from(3), U256::zero(), U256::zero());
+        assert_eq!((a, b), (U256::from(7), U256::from(3)));
+    }
+
+    #[test]
+    fn withdrawal_is_the_share_of_the_reserves() {
+        let (a, b) = withdrawn_amounts(
+            U256::from(250),
+            U256::from(1_000),
+            U256::from(2_000),
+            U256::from(8_000),
+        );
+        assert_eq!((a, b), (U256::from(500), U256::from(2_000)));
+        let (a, b) = withdrawn_amounts(U256::one(), U256::zero(), U256::one(), U256::one());
+        assert!(a.is_zero() && b.is_zero());
+    }
+}
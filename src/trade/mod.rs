@@ -1,2 +1,11 @@
+pub mod approval;
 pub mod handler;
-pub mod trade;
\ No newline at end of file
+pub mod liquidity;
+pub mod position;
+pub mod replace;
+pub mod router;
+pub mod tick_math;
+pub mod trade;
+pub mod universal;
+pub mod v3;
+pub mod wrap;
@@ -0,0 +1,685 @@
+//! # Uniswap V3 positions
+//!
+//! Concentrated-liquidity positions of the NonfungiblePositionManager. Price bounds
+//! are rounded outwards to the ticks of the fee tier, deposits are sized from the
+//! pool's current price the way the manager does, and every call is simulated on the
+//! pending block before it is sent. Positions are stored per account and refreshed
+//! from the manager whenever they are read, tokens are always deposited and paid out
+//! as ERC20s.
+
+use crate::database::models::account::Account;
+use crate::database::models::position::{NewPosition, Position, PositionStatus};
+use crate::database::models::transaction::{Transaction, TxStatus};
+use crate::database::pool::PgPool;
+use crate::market::market::Market;
+use crate::trade::approval::{Approval, ApprovalPolicy};
+use crate::trade::handler::trade_rpc::{
+    DecreaseLiquidityRequest, IncreaseLiquidityRequest, MintPositionRequest, PositionRequest,
+};
+use crate::trade::tick_math::{
+    amounts_for_liquidity, liquidity_for_amounts, price_to_tick, sqrt_ratio_at_tick, tick_spacing,
+};
+use crate::trade::trade::Trade;
+use crate::trade::v3::TradeV3;
+use crate::wallet::fee::{gas_limit, Fees, Speed};
+use crate::wallet::util::{apply_slippage, convert_to_wei, get_deadline};
+use crate::wallet::wallet::Wallet;
+use std::error::Error;
+use web3::contract::{tokens::Tokenizable, Contract, Error as ContractError, Options};
+use web3::ethabi::Token;
+use web3::types::{Address, Bytes, CallRequest, TransactionParameters, H256, U256, U64};
+use web3::{transports::WebSocket, Web3};
+
+/// Stored position with the multipliers of its tokens
+#[derive(Clone, Debug)]
+pub struct PositionState {
+    pub position: Position,
+    pub t0_mult: f32,
+    pub t1_mult: f32,
+}
+
+/// Transaction on a position, with the amounts deposited or paid out
+#[derive(Clone, Debug)]
+pub struct PositionChange {
+    pub hash: H256,
+    /// Position as read before the transaction is mined, the new one once minted
+    pub state: PositionState,
+    pub amount0: U256,
+    pub amount1: U256,
+}
+
+/// Outputs of the manager's `positions`, the liquidity is the 8th
+type ManagedPosition = (
+    Token,
+    Token,
+    Token,
+    Token,
+    Token,
+    Token,
+    Token,
+    U256,
+    Token,
+    Token,
+    Token,
+    Token,
+);
+
+/// Outcome of the mint transaction of a pending position
+enum Mint {
+    Pending,
+    Minted(U256),
+    Failed,
+}
+
+/// Uint output `i` of a call, zero when missing
+fn uint(out: &[Token], i: usize) -> U256 {
+    out.get(i)
+        .cloned()
+        .and_then(Token::into_uint)
+        .unwrap_or_default()
+}
+
+pub struct PositionManager {}
+
+impl PositionManager {
+    /// Fee tier must be enabled and the price bounds must be an increasing range
+    pub fn check_range(fee: u32, lower: f32, upper: f32) -> Result<(), String> {
+        if tick_spacing(fee).is_none() {
+            return Err(format!("Unsupported fee tier {}", fee));
+        }
+        if !lower.is_finite() || !upper.is_finite() || lower <= 0.0 || lower >= upper {
+            return Err(format!(
+                "Price range must be positive and increasing, got {} to {}",
+                lower, upper
+            ));
+        }
+        Ok(())
+    }
+
+    /// Percentage of liquidity must be within `(0, 100]`
+    pub fn check_percentage(percentage: f32) -> Result<(), String> {
+        if !percentage.is_finite() || percentage <= 0.0 || percentage > 100.0 {
+            return Err(format!(
+                "Percentage must be above 0 and at most 100, got {}",
+                percentage
+            ));
+        }
+        Ok(())
+    }
+
+    /// Only minted positions which haven't been burnt can be changed
+    pub fn check_open(pos: &Position) -> Result<(), String> {
+        match pos.status() {
+            PositionStatus::Open => Ok(()),
+            status => Err(format!("Position {} is {}", pos.id, status)),
+        }
+    }
+
+    /// Mint a position between two prices of token A in token B, depositing at most
+    /// `amount0` of token A and `amount1` of token B
+    #[allow(clippy::too_many_arguments)]
+    pub async fn mint(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        chain: &u32,
+        ta: &Address,
+        tb: &Address,
+        p: &MintPositionRequest,
+    ) -> Result<PositionChange, Box<dyn Error>> {
+        if ta == tb {
+            return Err("Tokens of a pool must differ".into());
+        }
+        TradeV3::check_transfer_tax(m, chain, ta, tb)?;
+        let spacing = tick_spacing(p.fee).ok_or("Unsupported fee tier")?;
+        let addr: Address = acc.address.parse()?;
+        let mgr = Self::manager(w3, m, chain)?;
+
+        // Pools order their tokens by address, prices of the other order are inverted
+        let flip = tb < ta;
+        let (t0, t1) = if flip { (*tb, *ta) } else { (*ta, *tb) };
+        let (amount0, amount1, lower, upper) = if flip {
+            let (lower, upper) = (1.0 / p.price_upper as f64, 1.0 / p.price_lower as f64);
+            (p.amount1, p.amount0, lower, upper)
+        } else {
+            let (lower, upper) = (p.price_lower as f64, p.price_upper as f64);
+            (p.amount0, p.amount1, lower, upper)
+        };
+        let t_abi = Trade::abi(m, "IERC20")?;
+        let t0_cont = Contract::from_json(w3.eth(), t0, &t_abi)?;
+        let t1_cont = Contract::from_json(w3.eth(), t1, &t_abi)?;
+        let t0_mult = Trade::multiplier(&t0_cont).await?;
+        let t1_mult = Trade::multiplier(&t1_cont).await?;
+        let tick_lower = price_to_tick(lower, t0_mult, t1_mult, spacing, false);
+        let tick_upper = price_to_tick(upper, t0_mult, t1_mult, spacing, true);
+        if tick_lower >= tick_upper {
+            return Err("Price range is outside the prices of the pool".into());
+        }
+
+        let sqrt_price = Self::pool_price(w3, m, chain, &t0, &t1, p.fee).await?;
+        let desired0 = convert_to_wei(amount0, t0_mult)?;
+        let desired1 = convert_to_wei(amount1, t1_mult)?;
+        let (min0, min1) = Self::minimums(
+            sqrt_price, tick_lower, tick_upper, desired0, desired1, p.slippage,
+        )?;
+
+        let speed = p.speed().into();
+        let deposits = [
+            ("token0", &t0_cont, desired0),
+            ("token1", &t1_cont, desired1),
+        ];
+        Self::deposit(w3, db, acc, mgr.address(), &deposits, speed).await?;
+
+        let params = Token::Tuple(vec![
+            Token::Address(t0),
+            Token::Address(t1),
+            Token::Uint(U256::from(p.fee)),
+            tick_lower.into_token(),
+            tick_upper.into_token(),
+            Token::Uint(desired0),
+            Token::Uint(desired1),
+            Token::Uint(min0),
+            Token::Uint(min1),
+            Token::Address(addr),
+            Token::Uint(get_deadline(p.deadline)),
+        ]);
+        let (out, hash) = Self::send(w3, db, acc, &mgr, &[("mint", vec![params])], speed).await?;
+
+        // The token id is the simulated one until the transaction is mined
+        let mut conn = db.get()?;
+        let position = NewPosition {
+            account_id: acc.id,
+            chain_id: *chain as i64,
+            token_id: uint(&out[0], 0).to_string(),
+            token0: format!("{:?}", t0),
+            token1: format!("{:?}", t1),
+            fee: p.fee as i32,
+            tick_lower,
+            tick_upper,
+            liquidity: uint(&out[0], 1).to_string(),
+            hash: format!("{:?}", hash),
+            status: PositionStatus::Pending.to_string(),
+        }
+        .create(&mut conn)
+        .await?;
+        Ok(PositionChange {
+            hash,
+            state: PositionState {
+                position,
+                t0_mult,
+                t1_mult,
+            },
+            amount0: uint(&out[0], 2),
+            amount1: uint(&out[0], 3),
+        })
+    }
+
+    /// Deposit at most `amount0` of token0 and `amount1` of token1 into an open position
+    pub async fn increase(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        state: PositionState,
+        p: &IncreaseLiquidityRequest,
+    ) -> Result<PositionChange, Box<dyn Error>> {
+        let pos = &state.position;
+        let chain = pos.chain_id as u32;
+        let mgr = Self::manager(w3, m, &chain)?;
+        let (t0, t1): (Address, Address) = (pos.token0.parse()?, pos.token1.parse()?);
+        let t_abi = Trade::abi(m, "IERC20")?;
+        let t0_cont = Contract::from_json(w3.eth(), t0, &t_abi)?;
+        let t1_cont = Contract::from_json(w3.eth(), t1, &t_abi)?;
+
+        let sqrt_price = Self::pool_price(w3, m, &chain, &t0, &t1, pos.fee as u32).await?;
+        let desired0 = convert_to_wei(p.amount0, state.t0_mult)?;
+        let desired1 = convert_to_wei(p.amount1, state.t1_mult)?;
+        let (min0, min1) = Self::minimums(
+            sqrt_price,
+            pos.tick_lower,
+            pos.tick_upper,
+            desired0,
+            desired1,
+            p.slippage,
+        )?;
+
+        let speed = p.speed().into();
+        let deposits = [
+            ("token0", &t0_cont, desired0),
+            ("token1", &t1_cont, desired1),
+        ];
+        Self::deposit(w3, db, acc, mgr.address(), &deposits, speed).await?;
+
+        let params = Token::Tuple(vec![
+            Token::Uint(U256::from_dec_str(&pos.token_id)?),
+            Token::Uint(desired0),
+            Token::Uint(desired1),
+            Token::Uint(min0),
+            Token::Uint(min1),
+            Token::Uint(get_deadline(p.deadline)),
+        ]);
+        let calls = [("increaseLiquidity", vec![params])];
+        let (out, hash) = Self::send(w3, db, acc, &mgr, &calls, speed).await?;
+        Ok(PositionChange {
+            hash,
+            amount0: uint(&out[0], 1),
+            amount1: uint(&out[0], 2),
+            state,
+        })
+    }
+
+    /// Withdraw a percentage of the position's liquidity, and collect it along with
+    /// the fees owed
+    pub async fn decrease(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        state: PositionState,
+        p: &DecreaseLiquidityRequest,
+    ) -> Result<PositionChange, Box<dyn Error>> {
+        let total = U256::from_dec_str(&state.position.liquidity)?;
+        let bps = U256::from((p.percentage * 100.0).round() as u64);
+        let liquidity = total * bps / U256::from(10_000);
+        if liquidity.is_zero() {
+            return Err("Position has no liquidity to withdraw".into());
+        }
+        let mgr = Self::manager(w3, m, &(state.position.chain_id as u32))?;
+        let mut calls = vec![];
+        calls.push(
+            Self::decrease_call(w3, m, &state.position, liquidity, p.slippage, p.deadline).await?,
+        );
+        calls.push(Self::collect_call(acc, &state.position)?);
+
+        let (out, hash) = Self::send(w3, db, acc, &mgr, &calls, p.speed().into()).await?;
+        Ok(PositionChange {
+            hash,
+            amount0: uint(&out[1], 0),
+            amount1: uint(&out[1], 1),
+            state,
+        })
+    }
+
+    /// Collect the fees owed to the position
+    pub async fn collect(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        state: PositionState,
+        p: &PositionRequest,
+    ) -> Result<PositionChange, Box<dyn Error>> {
+        let mgr = Self::manager(w3, m, &(state.position.chain_id as u32))?;
+        let calls = [Self::collect_call(acc, &state.position)?];
+        let (out, hash) = Self::send(w3, db, acc, &mgr, &calls, p.speed().into()).await?;
+        Ok(PositionChange {
+            hash,
+            amount0: uint(&out[0], 0),
+            amount1: uint(&out[0], 1),
+            state,
+        })
+    }
+
+    /// Withdraw all the liquidity, collect the tokens and fees, and burn the NFT.
+    /// The position is marked burnt once the transaction is mined.
+    pub async fn burn(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        state: PositionState,
+        p: &PositionRequest,
+    ) -> Result<PositionChange, Box<dyn Error>> {
+        let pos = &state.position;
+        let mgr = Self::manager(w3, m, &(pos.chain_id as u32))?;
+        let liquidity = U256::from_dec_str(&pos.liquidity)?;
+        let mut calls = vec![];
+        if !liquidity.is_zero() {
+            calls.push(Self::decrease_call(w3, m, pos, liquidity, p.slippage, p.deadline).await?);
+        }
+        calls.push(Self::collect_call(acc, pos)?);
+        let token_id = Token::Uint(U256::from_dec_str(&pos.token_id)?);
+        calls.push(("burn", vec![token_id]));
+
+        let (out, hash) = Self::send(w3, db, acc, &mgr, &calls, p.speed().into()).await?;
+        let collected = &out[calls.len() - 2];
+        Ok(PositionChange {
+            hash,
+            amount0: uint(collected, 0),
+            amount1: uint(collected, 1),
+            state,
+        })
+    }
+
+    /// Update a stored position from the manager: pending ones are settled from their
+    /// mint transaction, minted ones take their liquidity from `positions`
+    pub async fn refresh(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        pos: Position,
+    ) -> Result<PositionState, Box<dyn Error>> {
+        let mgr = Self::manager(w3, m, &(pos.chain_id as u32))?;
+        let mut token_id = pos.token_id.clone();
+        let mut status = pos.status();
+        let mut liquidity = pos.liquidity.clone();
+
+        if status == PositionStatus::Pending {
+            match Self::minted(w3, db, acc, &mgr, &pos.hash).await? {
+                Mint::Pending => {}
+                Mint::Minted(id) => {
+                    token_id = id.to_string();
+                    status = PositionStatus::Open;
+                }
+                Mint::Failed => status = PositionStatus::Failed,
+            }
+        }
+        if matches!(status, PositionStatus::Open | PositionStatus::Burnt) {
+            let id = U256::from_dec_str(&token_id)?;
+            let res: Result<ManagedPosition, _> = mgr
+                .query("positions", id, None, Options::default(), None)
+                .await;
+            // The manager reverts on the ids of burnt positions
+            let (s, l) = match res {
+                Ok(out) => (PositionStatus::Open, out.7),
+                Err(ContractError::Api(web3::Error::Rpc(_))) => {
+                    (PositionStatus::Burnt, U256::zero())
+                }
+                Err(err) => return Err(err.into()),
+            };
+            status = s;
+            liquidity = l.to_string();
+        }
+
+        let mut pos = pos;
+        if token_id != pos.token_id || status != pos.status() || liquidity != pos.liquidity {
+            let mut conn = db.get()?;
+            Position::update_state(&pos.id, &token_id, &liquidity, status, &mut conn).await?;
+            pos.token_id = token_id;
+            pos.status = status.to_string();
+            pos.liquidity = liquidity;
+        }
+
+        let t_abi = Trade::abi(m, "IERC20")?;
+        let t0_mult =
+            Trade::multiplier(&Contract::from_json(w3.eth(), pos.token0.parse()?, &t_abi)?).await?;
+        let t1_mult =
+            Trade::multiplier(&Contract::from_json(w3.eth(), pos.token1.parse()?, &t_abi)?).await?;
+        Ok(PositionState {
+            position: pos,
+            t0_mult,
+            t1_mult,
+        })
+    }
+
+    fn manager(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+    ) -> Result<Contract<WebSocket>, Box<dyn Error>> {
+        let addr = m
+            .try_get_exchange(chain, &"NonfungiblePositionManager".to_string())
+            .ok_or("No Uniswap V3 position manager on chain")?;
+        Ok(Contract::from_json(
+            w3.eth(),
+            addr,
+            &Trade::abi(m, "INonfungiblePositionManager")?,
+        )?)
+    }
+
+    /// `sqrtPriceX96` of the pool of the fee tier
+    async fn pool_price(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+        t0: &Address,
+        t1: &Address,
+        fee: u32,
+    ) -> Result<U256, Box<dyn Error>> {
+        let f_cont = TradeV3::factory(w3, m, chain)?;
+        let pool: Address = f_cont
+            .query(
+                "getPool",
+                (*t0, *t1, U256::from(fee)),
+                None,
+                Options::default(),
+                None,
+            )
+            .await?;
+        if pool.is_zero() {
+            return Err("No Uniswap V3 pool for token pair and fee tier".into());
+        }
+        let p_cont = Contract::from_json(w3.eth(), pool, &Trade::abi(m, "IUniswapV3Pool")?)?;
+        let (sqrt_price, ..): (U256, Token, Token, Token, Token, Token, Token) = p_cont
+            .query("slot0", (), None, Options::default(), None)
+            .await?;
+        if sqrt_price.is_zero() {
+            return Err("Uniswap V3 pool is not initialized".into());
+        }
+        Ok(sqrt_price)
+    }
+
+    /// Least amounts deposited out of the desired ones, after slippage
+    fn minimums(
+        sqrt_price: U256,
+        tick_lower: i32,
+        tick_upper: i32,
+        desired0: U256,
+        desired1: U256,
+        slippage: f32,
+    ) -> Result<(U256, U256), Box<dyn Error>> {
+        let sqrt_a = sqrt_ratio_at_tick(tick_lower);
+        let sqrt_b = sqrt_ratio_at_tick(tick_upper);
+        let liquidity = liquidity_for_amounts(sqrt_price, sqrt_a, sqrt_b, desired0, desired1);
+        if liquidity.is_zero() {
+            return Err("Amounts are too small for the price range".into());
+        }
+        let (amount0, amount1) = amounts_for_liquidity(sqrt_price, sqrt_a, sqrt_b, liquidity);
+        Ok((
+            apply_slippage(amount0, slippage),
+            apply_slippage(amount1, slippage),
+        ))
+    }
+
+    /// Check the balances of the deposited tokens and let the manager take them
+    async fn deposit(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        spender: Address,
+        deposits: &[(&str, &Contract<WebSocket>, U256)],
+        speed: Speed,
+    ) -> Result<(), Box<dyn Error>> {
+        let addr: Address = acc.address.parse()?;
+        let policy = ApprovalPolicy::from_env();
+        for (name, t_cont, amount) in deposits {
+            if amount.is_zero() {
+                continue;
+            }
+            let balance: U256 = t_cont
+                .query("balanceOf", addr, None, Options::default(), None)
+                .await?;
+            if balance < *amount {
+                return Err(format!("Insufficient {} balance", name).into());
+            }
+            Approval::approve(w3, db, acc, t_cont, spender, *amount, policy, speed).await?;
+        }
+        Ok(())
+    }
+
+    /// `decreaseLiquidity` of `liquidity`, bounded by its worth at the pool's price
+    async fn decrease_call(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        pos: &Position,
+        liquidity: U256,
+        slippage: f32,
+        deadline: u32,
+    ) -> Result<(&'static str, Vec<Token>), Box<dyn Error>> {
+        let (t0, t1): (Address, Address) = (pos.token0.parse()?, pos.token1.parse()?);
+        let chain = pos.chain_id as u32;
+        let sqrt_price = Self::pool_price(w3, m, &chain, &t0, &t1, pos.fee as u32).await?;
+        let (amount0, amount1) = amounts_for_liquidity(
+            sqrt_price,
+            sqrt_ratio_at_tick(pos.tick_lower),
+            sqrt_ratio_at_tick(pos.tick_upper),
+            liquidity,
+        );
+        let params = Token::Tuple(vec![
+            Token::Uint(U256::from_dec_str(&pos.token_id)?),
+            Token::Uint(liquidity),
+            Token::Uint(apply_slippage(amount0, slippage)),
+            Token::Uint(apply_slippage(amount1, slippage)),
+            Token::Uint(get_deadline(deadline)),
+        ]);
+        Ok(("decreaseLiquidity", vec![params]))
+    }
+
+    /// `collect` of everything owed to the position, paid to the account
+    fn collect_call(
+        acc: &Account,
+        pos: &Position,
+    ) -> Result<(&'static str, Vec<Token>), Box<dyn Error>> {
+        let addr: Address = acc.address.parse()?;
+        let params = Token::Tuple(vec![
+            Token::Uint(U256::from_dec_str(&pos.token_id)?),
+            Token::Address(addr),
+            Token::Uint(U256::from(u128::MAX)),
+            Token::Uint(U256::from(u128::MAX)),
+        ]);
+        Ok(("collect", vec![params]))
+    }
+
+    /// Token id minted by the transaction of a pending position, following the
+    /// replacements of the transaction
+    async fn minted(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        mgr: &Contract<WebSocket>,
+        hash: &str,
+    ) -> Result<Mint, Box<dyn Error>> {
+        let topic = mgr.abi().event("IncreaseLiquidity")?.signature();
+        let mut hash = hash.to_owned();
+        loop {
+            let tx = {
+                let mut conn = db.get()?;
+                Transaction::find_by_hash(&acc.id, &hash, &mut conn)
+                    .await
+                    .ok()
+            };
+            if let Some(tx) = tx {
+                match tx.status.parse() {
+                    // A speed-up mints in its place, a cancellation mints nothing
+                    Ok(TxStatus::Replaced) => match tx.replaced_by {
+                        Some(by) => {
+                            hash = by;
+                            continue;
+                        }
+                        None => return Ok(Mint::Failed),
+                    },
+                    Ok(TxStatus::Failed) | Ok(TxStatus::Dropped) => return Ok(Mint::Failed),
+                    _ => {}
+                }
+            }
+
+            let tx_hash: H256 = hash.parse()?;
+            let receipt = match w3.eth().transaction_receipt(tx_hash).await? {
+                Some(receipt) => receipt,
+                None => return Ok(Mint::Pending),
+            };
+            if receipt.status != Some(U64::one()) {
+                return Ok(Mint::Failed);
+            }
+            let minted = receipt
+                .logs
+                .iter()
+                .filter(|l| l.address == mgr.address() && l.topics.first() == Some(&topic))
+                .find_map(|l| l.topics.get(1))
+                .map(|id| U256::from_big_endian(id.as_bytes()));
+            return Ok(minted.map_or(Mint::Failed, Mint::Minted));
+        }
+    }
+
+    /// Simulate calls to the manager, batched in a `multicall` when there are several,
+    /// then send them. Returns the outputs of every call from the simulation.
+    async fn send(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        mgr: &Contract<WebSocket>,
+        calls: &[(&str, Vec<Token>)],
+        speed: Speed,
+    ) -> Result<(Vec<Vec<Token>>, H256), Box<dyn Error>> {
+        let addr: Address = acc.address.parse()?;
+        let abi = mgr.abi();
+        let mut encoded = vec![];
+        for (func, params) in calls {
+            encoded.push(abi.function(func)?.encode_input(params)?);
+        }
+        let multicall = abi.function("multicall")?;
+        let data = if encoded.len() == 1 {
+            encoded.remove(0)
+        } else {
+            let batch = encoded.into_iter().map(Token::Bytes).collect();
+            multicall.encode_input(&[Token::Array(batch)])?
+        };
+        let call = CallRequest {
+            from: Some(addr),
+            to: Some(mgr.address()),
+            data: Some(Bytes(data.clone())),
+            ..Default::default()
+        };
+        let output = Trade::simulate(w3, &call).await?;
+        let results = if calls.len() == 1 {
+            vec![output.0]
+        } else {
+            multicall
+                .decode_output(&output.0)?
+                .into_iter()
+                .next()
+                .and_then(Token::into_array)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(Token::into_bytes)
+                .collect()
+        };
+        let mut outputs = vec![];
+        for ((func, _), result) in calls.iter().zip(results) {
+            outputs.push(abi.function(func)?.decode_output(&result)?);
+        }
+
+        let fees = Fees::estimate(w3, speed).await?;
+        let gas = Trade::estimate_gas(w3, call).await?;
+        let mut tx_payload = TransactionParameters {
+            to: Some(mgr.address()),
+            gas: gas_limit(gas),
+            data: Bytes(data),
+            ..Default::default()
+        };
+        fees.apply(&mut tx_payload);
+        let hash = Wallet::send(w3, db, acc, &tx_payload).await?;
+        Ok((outputs, hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_and_percentages_are_checked() {
+        assert!(PositionManager::check_range(3000, 1500.0, 2500.0).is_ok());
+        assert!(PositionManager::check_range(2500, 1500.0, 2500.0).is_err());
+        assert!(PositionManager::check_range(500, 2500.0, 1500.0).is_err());
+        assert!(PositionManager::check_range(500, 0.0, 1500.0).is_err());
+        assert!(PositionManager::check_range(500, 1.0, f32::INFINITY).is_err());
+
+        assert!(PositionManager::check_percentage(100.0).is_ok());
+        assert!(PositionManager::check_percentage(0.5).is_ok());
+        assert!(PositionManager::check_percentage(0.0).is_err());
+        assert!(PositionManager::check_percentage(100.1).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::database::models::account::Account;
+use crate::database::models::transaction::{Transaction, TxStatus};
+use crate::database::pool::PgPool;
+use crate::wallet::fee::{Fees, Speed};
+use crate::wallet::wallet::Wallet;
+use std::error::Error;
+use web3::types::{AccessList, Address, Bytes, TransactionParameters, H256, U256, U64};
+use web3::{transports::WebSocket, Web3};
+
+/// Gas limit of a plain transfer
+const TRANSFER_GAS: u64 = 21_000;
+
+/// Replacements of a stuck transaction, sent at its nonce
+pub struct Replace {}
+
+impl Replace {
+    /// Resend the transaction with the same call and access list, and fees over the
+    /// replacement bump
+    pub async fn speed_up(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        original: &Transaction,
+        speed: Speed,
+    ) -> Result<H256, Box<dyn Error>> {
+        let to = match &original.to_address {
+            Some(to) => Some(to.parse()?),
+            None => None,
+        };
+        let tx = TransactionParameters {
+            to,
+            gas: U256::from(original.gas_limit as u64),
+            value: U256::from_dec_str(&original.value)?,
+            data: Bytes(hex::decode(original.data.trim_start_matches("0x"))?),
+            access_list: original.access_list()?,
+            ..Default::default()
+        };
+        Self::send(w3, db, acc, original, tx, speed).await
+    }
+
+    /// Take the nonce of the transaction with a zero value transfer to the account itself
+    pub async fn cancel(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        original: &Transaction,
+        speed: Speed,
+    ) -> Result<H256, Box<dyn Error>> {
+        let addr: Address = acc.address.parse()?;
+        let tx = TransactionParameters {
+            to: Some(addr),
+            gas: U256::from(TRANSFER_GAS),
+            value: U256::zero(),
+            data: Bytes::default(),
+            ..Default::default()
+        };
+        Self::send(w3, db, acc, original, tx, speed).await
+    }
+
+    /// Only transactions which are still waiting to be mined can be replaced
+    pub fn check_replaceable(original: &Transaction) -> Result<(), String> {
+        if original.status != TxStatus::Pending.as_str() || original.block_number.is_some() {
+            return Err(format!(
+                "Transaction {} is not pending anymore",
+                original.hash
+            ));
+        }
+        Ok(())
+    }
+
+    /// Send `tx` at the nonce of `original`, with fees which replace it
+    async fn send(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        original: &Transaction,
+        mut tx: TransactionParameters,
+        speed: Speed,
+    ) -> Result<H256, Box<dyn Error>> {
+        Self::check_replaceable(original)?;
+        let hash: H256 = original.hash.parse()?;
+        let fees = Fees::estimate(w3, speed)
+            .await?
+            .replacing(&Self::fees(original)?);
+
+        tx.nonce = Some(U256::from(original.nonce as u64));
+        tx.chain_id = Some(original.chain_id as u64);
+        fees.apply(&mut tx);
+        // Access list transactions are replaced by access list transactions
+        if original.tx_type == 1 {
+            tx.transaction_type = Some(U64::from(1));
+            tx.access_list.get_or_insert_with(AccessList::default);
+        }
+        Ok(Wallet::replace(w3, db, acc, &tx, &hash).await?)
+    }
+
+    /// Fees paid by a stored transaction
+    fn fees(tx: &Transaction) -> Result<Fees, Box<dyn Error>> {
+        let fee = |f: &Option<String>| -> Result<U256, Box<dyn Error>> {
+            let f = f.as_deref().ok_or("Missing fee of stored transaction")?;
+            Ok(U256::from_dec_str(f)?)
+        };
+        if tx.tx_type == 2 {
+            Ok(Fees::Eip1559 {
+                base_fee: U256::zero(),
+                max_fee_per_gas: fee(&tx.max_fee_per_gas)?,
+                max_priority_fee_per_gas: fee(&tx.max_priority_fee_per_gas)?,
+            })
+        } else {
+            Ok(Fees::Legacy {
+                gas_price: fee(&tx.gas_price)?,
+            })
+        }
+    }
+}
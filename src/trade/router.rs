@@ -0,0 +1,416 @@
+use crate::market::market::Market;
+use crate::trade::handler::trade_rpc::SwapMode;
+use crate::trade::trade::Trade;
+use std::error::Error;
+use web3::contract::{Contract, Options};
+use web3::types::{Address, U256};
+use web3::{transports::WebSocket, Web3};
+
+/// Uniswap V2 compatible routers searched when the request names no exchange
+pub const V2_ROUTERS: [&str; 5] = [
+    "UniswapV2Router02",
+    "UniswapV2Router01",
+    "SushiSwapRouter",
+    "PancakeRouter",
+    "QuickRouter",
+];
+
+/// Intermediate tokens tried between token0 and token1,
+/// next to the wrapped native token of every router
+pub const BASE_TOKENS: [&str; 4] = ["WETH", "USDC", "USDT", "DAI"];
+
+/// Number of parts an order is cut into by the split optimizer
+const SPLIT_STEPS: u64 = 20;
+
+/// Route through the pairs of a single V2 router
+#[derive(Clone, Debug)]
+pub struct V2Route {
+    /// Market name of the router
+    pub exchange: String,
+    pub router: Address,
+    /// Wrapped native token of the router
+    pub weth: Address,
+    pub tokens: Vec<Address>,
+    pub amount_in: U256,
+    /// Output priced by the router, before transfer taxes
+    pub amount_out: U256,
+    /// Share of the amount taken by the fee-on-transfer tokens of the route, in basis points
+    pub transfer_tax: u32,
+}
+
+impl V2Route {
+    /// Output left to the recipient once the fee-on-transfer tokens took their tax
+    pub fn amount_received(&self) -> U256 {
+        apply_tax(self.amount_out, self.transfer_tax)
+    }
+}
+
+/// Amount left after a tax given in basis points
+fn apply_tax(amount: U256, tax: u32) -> U256 {
+    amount * U256::from(10_000 - tax.min(10_000)) / U256::from(10_000)
+}
+
+pub struct Router {}
+
+impl Router {
+    /// V2 routers are picked by exchange name, an empty name searches all of them
+    pub fn supports(exchange: &str) -> bool {
+        exchange.is_empty() || V2_ROUTERS.contains(&exchange)
+    }
+
+    /// Quote every candidate path on the requested router, or on every V2 router
+    /// of the chain when `exchange` is empty, and keep the best one.
+    ///
+    /// `amount` is the exact input for exact input swaps, the route with the largest
+    /// output wins. It is the exact output for exact output swaps, the route with
+    /// the smallest input wins. Outputs are compared net of transfer taxes, exact output
+    /// swaps skip the routes through fee-on-transfer tokens.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn best_route(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+        exchange: &str,
+        t0: &Address,
+        t1: &Address,
+        amount: U256,
+        mode: SwapMode,
+    ) -> Result<V2Route, Box<dyn Error>> {
+        let r_abi = Trade::abi(m, "IUniswapV2Router02")?;
+        let routers = Self::routers(m, chain, exchange)?;
+        if mode == SwapMode::ExactOutput && Self::transfer_tax(m, chain, &[*t0, *t1]) > 0 {
+            return Err("Exact output swaps don't support fee-on-transfer tokens".into());
+        }
+
+        let mut best: Option<V2Route> = None;
+        for (name, r) in routers {
+            let r_cont = Contract::from_json(w3.eth(), r, &r_abi)?;
+            let weth: Address = r_cont
+                .query("WETH", (), None, Options::default(), None)
+                .await?;
+            let mut bases = Self::bases(m, chain);
+            if !bases.contains(&weth) {
+                bases.push(weth);
+            }
+
+            for tokens in Self::paths(t0, t1, &bases) {
+                let transfer_tax = Self::transfer_tax(m, chain, &tokens);
+                if mode == SwapMode::ExactOutput && transfer_tax > 0 {
+                    continue;
+                }
+                // Paths with a missing pair make the router revert
+                let (amount_in, amount_out) =
+                    match Self::quote_path(&r_cont, &tokens, amount, mode).await {
+                        Ok(amounts) => amounts,
+                        Err(_) => continue,
+                    };
+                let better = match &best {
+                    Some(b) if mode == SwapMode::ExactOutput => amount_in < b.amount_in,
+                    Some(b) => apply_tax(amount_out, transfer_tax) > b.amount_received(),
+                    None => true,
+                };
+                if better {
+                    best = Some(V2Route {
+                        exchange: name.clone(),
+                        router: r,
+                        weth,
+                        tokens,
+                        amount_in,
+                        amount_out,
+                        transfer_tax,
+                    });
+                }
+            }
+        }
+        best.ok_or_else(|| "No V2 route for token pair".into())
+    }
+
+    /// Split an exact input order across the candidate paths of the requested router,
+    /// or of every V2 router of the chain when `exchange` is empty.
+    ///
+    /// The order is cut into `SPLIT_STEPS` parts and every part goes to the path with
+    /// the largest marginal output, simulated from the pair reserves. Paths through
+    /// different base tokens share no pair, so they are simulated independently.
+    /// Marginal outputs are compared net of the transfer taxes of every path.
+    /// The amounts of the returned allocations are priced by the routers.
+    pub async fn split(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+        exchange: &str,
+        t0: &Address,
+        t1: &Address,
+        amount_in: U256,
+    ) -> Result<Vec<V2Route>, Box<dyn Error>> {
+        let r_abi = Trade::abi(m, "IUniswapV2Router02")?;
+        let f_abi = Trade::abi(m, "IUniswapV2Factory")?;
+        let p_abi = Trade::abi(m, "IUniswapV2Pair")?;
+        let routers = Self::routers(m, chain, exchange)?;
+
+        // Candidate route with the (reserve in, reserve out) of every hop
+        let mut candidates: Vec<(V2Route, Vec<(U256, U256)>)> = vec![];
+        for (name, r) in routers {
+            let r_cont = Contract::from_json(w3.eth(), r, &r_abi)?;
+            let weth: Address = r_cont
+                .query("WETH", (), None, Options::default(), None)
+                .await?;
+            let factory: Address = r_cont
+                .query("factory", (), None, Options::default(), None)
+                .await?;
+            let f_cont = Contract::from_json(w3.eth(), factory, &f_abi)?;
+            let mut bases = Self::bases(m, chain);
+            if !bases.contains(&weth) {
+                bases.push(weth);
+            }
+
+            for tokens in Self::paths(t0, t1, &bases) {
+                if let Some(reserves) = Self::reserves(w3, &f_cont, &p_abi, &tokens).await? {
+                    let route = V2Route {
+                        exchange: name.clone(),
+                        router: r,
+                        weth,
+                        transfer_tax: Self::transfer_tax(m, chain, &tokens),
+                        tokens,
+                        amount_in: U256::zero(),
+                        amount_out: U256::zero(),
+                    };
+                    candidates.push((route, reserves));
+                }
+            }
+        }
+        if candidates.is_empty() {
+            return Err("No V2 route for token pair".into());
+        }
+
+        let step = amount_in / SPLIT_STEPS;
+        let mut allocated = vec![U256::zero(); candidates.len()];
+        for i in 0..SPLIT_STEPS {
+            // The last part also takes the rounding remainder
+            let part = if i == SPLIT_STEPS - 1 {
+                amount_in - step * (SPLIT_STEPS - 1)
+            } else {
+                step
+            };
+            if part.is_zero() {
+                continue;
+            }
+            let mut best = (0, U256::zero());
+            for (j, (route, reserves)) in candidates.iter().enumerate() {
+                let marginal = apply_tax(
+                    Self::simulate(reserves, allocated[j] + part)
+                        - Self::simulate(reserves, allocated[j]),
+                    route.transfer_tax,
+                );
+                if marginal > best.1 {
+                    best = (j, marginal);
+                }
+            }
+            if best.1.is_zero() {
+                return Err("Insufficient liquidity for token pair".into());
+            }
+            allocated[best.0] += part;
+        }
+
+        let mut allocations = vec![];
+        for ((mut route, _), amount) in candidates.into_iter().zip(allocated) {
+            if amount.is_zero() {
+                continue;
+            }
+            let r_cont = Contract::from_json(w3.eth(), route.router, &r_abi)?;
+            let (amount_in, amount_out) =
+                Self::quote_path(&r_cont, &route.tokens, amount, SwapMode::ExactInput).await?;
+            route.amount_in = amount_in;
+            route.amount_out = amount_out;
+            allocations.push(route);
+        }
+        Ok(allocations)
+    }
+
+    /// Direct path and the paths through one base token
+    pub fn paths(t0: &Address, t1: &Address, bases: &[Address]) -> Vec<Vec<Address>> {
+        let mut paths = vec![vec![*t0, *t1]];
+        for base in bases {
+            if base != t0 && base != t1 {
+                paths.push(vec![*t0, *base, *t1]);
+            }
+        }
+        paths
+    }
+
+    /// Routers to search, by market name
+    fn routers(
+        m: &Market,
+        chain: &u32,
+        exchange: &str,
+    ) -> Result<Vec<(String, Address)>, Box<dyn Error>> {
+        let exchanges = m.exchanges.get(chain).ok_or("Unsupported chain")?;
+        if !exchange.is_empty() {
+            let r = exchanges
+                .get(exchange)
+                .ok_or_else(|| format!("Unknown exchange {}", exchange))?;
+            return Ok(vec![(exchange.to_string(), *r)]);
+        }
+
+        let routers: Vec<(String, Address)> = V2_ROUTERS
+            .iter()
+            .filter_map(|name| exchanges.get(*name).map(|r| (name.to_string(), *r)))
+            .collect();
+        if routers.is_empty() {
+            return Err("No V2 router on chain".into());
+        }
+        Ok(routers)
+    }
+
+    /// Combined tax of the fee-on-transfer tokens of a path, in basis points.
+    /// Every token of a path is transferred once: into the first pair, between
+    /// two pairs or out of the last pair.
+    fn transfer_tax(m: &Market, chain: &u32, tokens: &[Address]) -> u32 {
+        let kept = tokens.iter().fold(10_000u64, |kept, t| {
+            kept * (10_000 - m.transfer_tax(chain, t).min(10_000)) as u64 / 10_000
+        });
+        10_000 - kept as u32
+    }
+
+    /// Base token addresses listed in the chain's assets
+    fn bases(m: &Market, chain: &u32) -> Vec<Address> {
+        match m.assets.get(chain) {
+            Some(assets) => BASE_TOKENS
+                .iter()
+                .filter_map(|name| assets.get(*name).cloned())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Reserves of every pair along the path, oriented in the swap direction,
+    /// or `None` when a pair is missing or empty
+    async fn reserves(
+        w3: &Web3<WebSocket>,
+        f_cont: &Contract<WebSocket>,
+        p_abi: &[u8],
+        tokens: &[Address],
+    ) -> Result<Option<Vec<(U256, U256)>>, Box<dyn Error>> {
+        let mut reserves = vec![];
+        for hop in tokens.windows(2) {
+            let pair: Address = f_cont
+                .query("getPair", (hop[0], hop[1]), None, Options::default(), None)
+                .await?;
+            if pair.is_zero() {
+                return Ok(None);
+            }
+            let p_cont = Contract::from_json(w3.eth(), pair, p_abi)?;
+            let token0: Address = p_cont
+                .query("token0", (), None, Options::default(), None)
+                .await?;
+            let (r0, r1, _): (U256, U256, U256) = p_cont
+                .query("getReserves", (), None, Options::default(), None)
+                .await?;
+            let (r_in, r_out) = if token0 == hop[0] { (r0, r1) } else { (r1, r0) };
+            if r_in.is_zero() || r_out.is_zero() {
+                return Ok(None);
+            }
+            reserves.push((r_in, r_out));
+        }
+        Ok(Some(reserves))
+    }
+
+    /// Constant product output of a path for `amount_in`, with the 0.3% LP fee
+    fn simulate(reserves: &[(U256, U256)], amount_in: U256) -> U256 {
+        reserves.iter().fold(amount_in, |amount, (r_in, r_out)| {
+            let amount_with_fee = amount * 997;
+            amount_with_fee * r_out / (*r_in * 1000 + amount_with_fee)
+        })
+    }
+
+    /// Input and output amounts of a path priced by the router
+    async fn quote_path(
+        r_cont: &Contract<WebSocket>,
+        tokens: &[Address],
+        amount: U256,
+        mode: SwapMode,
+    ) -> Result<(U256, U256), Box<dyn Error>> {
+        match mode {
+            SwapMode::ExactInput => {
+                let amounts_out: Vec<U256> = r_cont
+                    .query(
+                        "getAmountsOut",
+                        (amount, tokens.to_vec()),
+                        None,
+                        Options::default(),
+                        None,
+                    )
+                    .await?;
+                let amount_out = *amounts_out.last().ok_or("Empty getAmountsOut result")?;
+                Ok((amount, amount_out))
+            }
+            SwapMode::ExactOutput => {
+                let amounts_in: Vec<U256> = r_cont
+                    .query(
+                        "getAmountsIn",
+                        (amount, tokens.to_vec()),
+                        None,
+                        Options::default(),
+                        None,
+                    )
+                    .await?;
+                let amount_in = *amounts_in.first().ok_or("Empty getAmountsIn result")?;
+                Ok((amount_in, amount))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const CHAIN: u32 = 1;
+
+    fn market(taxes: &[(&str, u32)]) -> Market {
+        let assets = ["FOT", "TAX", "WETH"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.to_string(), Address::repeat_byte(i as u8 + 1)))
+            .collect();
+        let taxes = taxes.iter().map(|(n, t)| (n.to_string(), *t)).collect();
+        Market::new(
+            HashMap::from([(CHAIN, assets)]),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::from([(CHAIN, taxes)]),
+            HashMap::new(),
+        )
+    }
+
+    #[test]
+    fn transfer_taxes_compound_along_the_path() {
+        let m = market(&[("FOT", 500), ("TAX", 1000)]);
+        let (fot, tax, weth) = (
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(3),
+        );
+
+        assert_eq!(Router::transfer_tax(&m, &CHAIN, &[weth, fot]), 500);
+        // 95% of 90% is kept
+        assert_eq!(Router::transfer_tax(&m, &CHAIN, &[fot, weth, tax]), 1450);
+        assert_eq!(Router::transfer_tax(&m, &2, &[fot, weth]), 0);
+    }
+
+    #[test]
+    fn received_amount_is_net_of_the_tax() {
+        let route = V2Route {
+            exchange: "UniswapV2Router02".to_string(),
+            router: Address::zero(),
+            weth: Address::zero(),
+            tokens: vec![],
+            amount_in: U256::from(1_000),
+            amount_out: U256::from(2_000),
+            transfer_tax: 250,
+        };
+        assert_eq!(route.amount_received(), U256::from(1_950));
+        assert_eq!(apply_tax(U256::from(2_000), 0), U256::from(2_000));
+    }
+}
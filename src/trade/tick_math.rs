@@ -0,0 +1,243 @@
+//! # Uniswap V3 tick math
+//!
+//! Conversions between prices, ticks and `sqrtPriceX96`, and the liquidity of token
+//! amounts in a tick range. `sqrt_ratio_at_tick` and the liquidity amounts follow
+//! `TickMath` and `LiquidityAmounts` of the Uniswap contracts, so they round the same way.
+
+use crate::wallet::util::to_f64;
+use std::convert::TryFrom;
+use web3::types::U256;
+
+/// Lowest tick of a pool, the price 1.0001^MIN_TICK is about 2^-128
+pub const MIN_TICK: i32 = -887272;
+
+/// Highest tick of a pool
+pub const MAX_TICK: i32 = -MIN_TICK;
+
+/// `1.0001^(1 / 2^i)` for every bit of the absolute tick, as Q128.128 numbers
+const TICK_RATIOS: [&str; 19] = [
+    "fff97272373d413259a46990580e213a",
+    "fff2e50f5f656932ef12357cf3c7fdcc",
+    "ffe5caca7e10e4e61c3624eaa0941cd0",
+    "ffcb9843d60f6159c9db58835c926644",
+    "ff973b41fa98c081472e6896dfb254c0",
+    "ff2ea16466c96a3843ec78b326b52861",
+    "fe5dee046a99a2a811c461f1969c3053",
+    "fcbe86c7900a88aedcffc83b479aa3a4",
+    "f987a7253ac413176f2b074cf7815e54",
+    "f3392b0822b70005940c7a398e4b70f3",
+    "e7159475a2c29b7443b29c7fa6e889d9",
+    "d097f3bdfd2022b8845ad8f792aa5825",
+    "a9f746462d870fdf8a65dc1f90e061e5",
+    "70d869a156d2a1b890bb3df62baf32f7",
+    "31be135f97d08fd981231505542fcfa6",
+    "9aa508b5b7a84e1c677de54f3e99bc9",
+    "5d6af8dedb81196699c329225ee604",
+    "2216e584f5fa1ea926041bedfe98",
+    "48a170391f7dc42444e8fa2",
+];
+
+/// Ratio of the first bit of the absolute tick
+const TICK_RATIO_0: &str = "fffcb933bd6fad37aa2d162d1a594001";
+
+/// Tick spacing of a fee tier, `None` for fees the factory doesn't enable
+pub fn tick_spacing(fee: u32) -> Option<i32> {
+    match fee {
+        100 => Some(1),
+        500 => Some(10),
+        3000 => Some(60),
+        10000 => Some(200),
+        _ => None,
+    }
+}
+
+fn q96() -> U256 {
+    U256::one() << 96
+}
+
+/// `a * b / d` without overflowing on the product, saturated to `U256::MAX`
+pub fn mul_div(a: U256, b: U256, d: U256) -> U256 {
+    U256::try_from(a.full_mul(b) / d).unwrap_or(U256::MAX)
+}
+
+/// `sqrt(1.0001^tick)` as a Q64.96 number, like `TickMath.getSqrtRatioAtTick`
+pub fn sqrt_ratio_at_tick(tick: i32) -> U256 {
+    let tick = tick.clamp(MIN_TICK, MAX_TICK);
+    let abs = tick.unsigned_abs();
+    let hex = |h: &str| U256::from_str_radix(h, 16).unwrap_or_default();
+    let mut ratio = if abs & 1 != 0 {
+        hex(TICK_RATIO_0)
+    } else {
+        U256::one() << 128
+    };
+    for (i, r) in TICK_RATIOS.iter().enumerate() {
+        if abs & (2 << i) != 0 {
+            ratio = (ratio * hex(r)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+    // Q128.128 to Q64.96, rounding up
+    let rounding = if (ratio & U256::from(u32::MAX)).is_zero() {
+        0
+    } else {
+        1
+    };
+    (ratio >> 32) + rounding
+}
+
+/// Raw price (token1 units per token0 unit) of a human price of token0 in token1
+pub fn raw_price(price: f64, t0_mult: f32, t1_mult: f32) -> f64 {
+    price * t1_mult as f64 / t0_mult as f64
+}
+
+/// Human price of token0 in token1 at a tick
+pub fn tick_to_price(tick: i32, t0_mult: f32, t1_mult: f32) -> f64 {
+    1.0001f64.powi(tick) * t0_mult as f64 / t1_mult as f64
+}
+
+/// Human price of token0 in token1 of a `sqrtPriceX96`
+pub fn sqrt_price_to_price(sqrt_price: U256, t0_mult: f32, t1_mult: f32) -> f64 {
+    let sqrt = to_f64(sqrt_price) / 2f64.powi(96);
+    sqrt * sqrt * t0_mult as f64 / t1_mult as f64
+}
+
+/// Tick of a human price of token0 in token1, rounded down to a usable tick of the
+/// spacing, or up when `round_up` is set. Prices beyond the pool range are clamped.
+pub fn price_to_tick(price: f64, t0_mult: f32, t1_mult: f32, spacing: i32, round_up: bool) -> i32 {
+    // Division truncates towards zero, inside the pool range on both sides
+    let min = MIN_TICK / spacing * spacing;
+    let max = MAX_TICK / spacing * spacing;
+    let raw = raw_price(price, t0_mult, t1_mult);
+    if raw <= 0.0 || !raw.is_finite() {
+        return if raw > 0.0 { max } else { min };
+    }
+    let exact = raw.ln() / 1.0001f64.ln();
+    let aligned = if round_up {
+        (exact / spacing as f64).ceil()
+    } else {
+        (exact / spacing as f64).floor()
+    };
+    (aligned as i64 * spacing as i64).clamp(min as i64, max as i64) as i32
+}
+
+/// Liquidity of `amount0` between two sqrt prices, like `getLiquidityForAmount0`
+fn liquidity_for_amount0(sqrt_a: U256, sqrt_b: U256, amount0: U256) -> U256 {
+    let (sqrt_a, sqrt_b) = (sqrt_a.min(sqrt_b), sqrt_a.max(sqrt_b));
+    let intermediate = mul_div(sqrt_a, sqrt_b, q96());
+    mul_div(amount0, intermediate, sqrt_b - sqrt_a)
+}
+
+/// Liquidity of `amount1` between two sqrt prices, like `getLiquidityForAmount1`
+fn liquidity_for_amount1(sqrt_a: U256, sqrt_b: U256, amount1: U256) -> U256 {
+    let (sqrt_a, sqrt_b) = (sqrt_a.min(sqrt_b), sqrt_a.max(sqrt_b));
+    mul_div(amount1, q96(), sqrt_b - sqrt_a)
+}
+
+/// Largest liquidity the amounts provide in the range at the current sqrt price,
+/// like `LiquidityAmounts.getLiquidityForAmounts`
+pub fn liquidity_for_amounts(
+    sqrt_price: U256,
+    sqrt_a: U256,
+    sqrt_b: U256,
+    amount0: U256,
+    amount1: U256,
+) -> U256 {
+    let (sqrt_a, sqrt_b) = (sqrt_a.min(sqrt_b), sqrt_a.max(sqrt_b));
+    if sqrt_price <= sqrt_a {
+        liquidity_for_amount0(sqrt_a, sqrt_b, amount0)
+    } else if sqrt_price < sqrt_b {
+        let l0 = liquidity_for_amount0(sqrt_price, sqrt_b, amount0);
+        let l1 = liquidity_for_amount1(sqrt_a, sqrt_price, amount1);
+        l0.min(l1)
+    } else {
+        liquidity_for_amount1(sqrt_a, sqrt_b, amount1)
+    }
+}
+
+/// Token amounts of `liquidity` in the range at the current sqrt price, rounded down,
+/// like `LiquidityAmounts.getAmountsForLiquidity`
+pub fn amounts_for_liquidity(
+    sqrt_price: U256,
+    sqrt_a: U256,
+    sqrt_b: U256,
+    liquidity: U256,
+) -> (U256, U256) {
+    let (sqrt_a, sqrt_b) = (sqrt_a.min(sqrt_b), sqrt_a.max(sqrt_b));
+    let amount0 = |lower: U256| mul_div(liquidity << 96, sqrt_b - lower, sqrt_b) / lower;
+    let amount1 = |upper: U256| mul_div(liquidity, upper - sqrt_a, q96());
+    if sqrt_price <= sqrt_a {
+        (amount0(sqrt_a), U256::zero())
+    } else if sqrt_price < sqrt_b {
+        (amount0(sqrt_price), amount1(sqrt_price))
+    } else {
+        (U256::zero(), amount1(sqrt_b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqrt_ratios_match_the_contract() {
+        assert_eq!(sqrt_ratio_at_tick(0), q96());
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK), U256::from(4295128739u64));
+        assert_eq!(
+            sqrt_ratio_at_tick(MAX_TICK),
+            U256::from_dec_str("1461446703485210103287273052203988822378723970342").unwrap()
+        );
+        // Every bit of the tick against floating point
+        for bit in 0..20 {
+            for tick in [1i32 << bit, -(1i32 << bit)] {
+                let expected = 1.0001f64.powf(tick as f64 / 2.0) * 2f64.powi(96);
+                let ratio = to_f64(sqrt_ratio_at_tick(tick)) / expected;
+                assert!((ratio - 1.0).abs() < 1e-9, "tick {}: {}", tick, ratio);
+            }
+        }
+    }
+
+    #[test]
+    fn prices_round_to_usable_ticks() {
+        // 2000 USDC (6 decimals) per WETH (18 decimals)
+        let (weth, usdc) = (1e18, 1e6);
+        let lower = price_to_tick(2000.0, weth, usdc, 60, false);
+        let upper = price_to_tick(2000.0, weth, usdc, 60, true);
+        assert_eq!((lower % 60, upper % 60), (0, 0));
+        assert_eq!(upper - lower, 60);
+        assert!(tick_to_price(lower, weth, usdc) <= 2000.0);
+        assert!(tick_to_price(upper, weth, usdc) >= 2000.0);
+        // Out of range prices stop at the last usable tick
+        assert_eq!(price_to_tick(0.0, weth, usdc, 200, false), -887200);
+        assert_eq!(price_to_tick(f64::INFINITY, weth, usdc, 200, true), 887200);
+    }
+
+    #[test]
+    fn sqrt_prices_match_tick_prices() {
+        let (weth, usdc) = (1e18, 1e6);
+        for tick in [-200_000, -60, 0, 60, 200_000] {
+            let price = sqrt_price_to_price(sqrt_ratio_at_tick(tick), weth, usdc);
+            let expected = tick_to_price(tick, weth, usdc);
+            assert!((price / expected - 1.0).abs() < 1e-9, "tick {}", tick);
+        }
+    }
+
+    #[test]
+    fn amounts_round_trip_through_liquidity() {
+        let (sqrt_a, sqrt_b) = (sqrt_ratio_at_tick(-600), sqrt_ratio_at_tick(600));
+        let amount = U256::exp10(18);
+        // In range, the scarcer amount sets the liquidity
+        let price = sqrt_ratio_at_tick(0);
+        let liquidity = liquidity_for_amounts(price, sqrt_a, sqrt_b, amount, amount * 2);
+        let (a0, a1) = amounts_for_liquidity(price, sqrt_a, sqrt_b, liquidity);
+        assert!(a0 <= amount && amount - a0 < U256::from(10));
+        assert!(a1 < amount * 2);
+        // Below the range only token0 is deposited
+        let price = sqrt_ratio_at_tick(-1200);
+        let liquidity = liquidity_for_amounts(price, sqrt_a, sqrt_b, amount, amount);
+        let (a0, a1) = amounts_for_liquidity(price, sqrt_a, sqrt_b, liquidity);
+        assert!(amount - a0 < U256::from(10));
+        assert!(a1.is_zero());
+    }
+}
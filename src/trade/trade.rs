@@ -1,223 +1,722 @@
 use crate::database::models::account::Account;
-use crate::trade::handler::trade_rpc::SwapRequest;
-use crate::wallet::util::{convert_to_wei, get_valid_timestamp};
+use crate::database::pool::PgPool;
+use crate::market::market::Market;
+use crate::trade::approval::{Approval, ApprovalPolicy};
+use crate::trade::handler::trade_rpc::{self, QuoteRequest, SwapMode, SwapRequest};
+use crate::trade::router::{Router, V2Route};
+use crate::trade::tick_math::sqrt_price_to_price;
+use crate::wallet::fee::{gas_limit, Fees, Speed};
+use crate::wallet::util::{
+    apply_slippage, apply_slippage_max, convert_to_wei, get_deadline, revert_reason, to_f64,
+};
 use crate::wallet::wallet::Wallet;
 use std::error::Error;
 use web3::contract::{tokens::Tokenize, Contract, Options};
-use web3::types::{Address, Bytes, TransactionParameters, TransactionRequest, H256, U256};
+use web3::types::{
+    Address, BlockId, BlockNumber, Bytes, CallRequest, TransactionParameters, H256, U256,
+};
 use web3::{transports::WebSocket, Web3};
 
+/// Gas limit reported by `quote` when the swap can't be estimated yet
+/// (e.g. the router has no allowance for token0)
+const FALLBACK_SWAP_GAS: u64 = 250_000;
+
+/// Highest accepted slippage tolerance, in percents
+pub const MAX_SLIPPAGE: f32 = 50.0;
+
+/// Longest accepted swap deadline, in seconds from now
+pub const MAX_DEADLINE: u32 = 3600;
+
+/// Deadline used to price a swap which is not sent, in seconds from now
+pub(crate) const DEFAULT_DEADLINE: u32 = 300;
+
+/// Uniswap V2 pairs keep 0.3% of the input amount as LP fee
+const V2_FEE_FACTOR: f64 = 0.997;
+
+/// V2 LP fee in hundredths of a bip, like the V3 fee tiers
+const V2_FEE: u32 = 3000;
+
+/// Expected result of a swap, priced without sending a transaction
+#[derive(Clone, Debug)]
+pub struct Quote {
+    /// Market name of the exchange the quote comes from
+    pub exchange: String,
+    pub amount_out: U256,
+    pub amount_out_min: U256,
+    pub route: Vec<Address>,
+    /// Pool fee of every hop for V3 routes, empty for V2
+    pub fees: Vec<u32>,
+    /// Share of the output lost to the trade moving the pool price (0..1)
+    pub price_impact: f64,
+    pub gas: U256,
+    pub gas_price: U256,
+    /// Output token decimals multiplier
+    pub t1_mult: f32,
+}
+
+/// State of a V2 pair or V3 pool, tokens in pair order
+#[derive(Clone, Debug)]
+pub struct PairState {
+    pub pair: Address,
+    pub token0: Address,
+    pub token1: Address,
+    /// Token balances of the pair
+    pub reserve0: U256,
+    pub reserve1: U256,
+    /// Zero for V2 pairs
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    /// In-range liquidity of V3 pools, zero for V2 pairs
+    pub liquidity: U256,
+    /// Fee tier in hundredths of a bip
+    pub fee: u32,
+    /// LP tokens of V2 pairs, zero for V3 pools
+    pub total_supply: U256,
+    pub t0_mult: f32,
+    pub t1_mult: f32,
+}
+
+impl PairState {
+    /// Spot price of token0 in token1, from the pool price of V3 pools
+    /// and from the reserves of V2 pairs
+    pub fn price0(&self) -> f64 {
+        if !self.sqrt_price_x96.is_zero() {
+            return sqrt_price_to_price(self.sqrt_price_x96, self.t0_mult, self.t1_mult);
+        }
+        if self.reserve0.is_zero() {
+            return 0.0;
+        }
+        to_f64(self.reserve1) / to_f64(self.reserve0) * self.t0_mult as f64 / self.t1_mult as f64
+    }
+
+    /// Spot price of token1 in token0
+    pub fn price1(&self) -> f64 {
+        let price0 = self.price0();
+        if price0 == 0.0 {
+            0.0
+        } else {
+            1.0 / price0
+        }
+    }
+}
+
+/// Exact input order split across several V2 routes
+#[derive(Clone, Debug)]
+pub struct SplitQuote {
+    /// Route and amounts of every part of the order
+    pub allocations: Vec<V2Route>,
+    /// Aggregate output of all the parts
+    pub amount_out: U256,
+    pub amount_out_min: U256,
+    /// Input token decimals multiplier
+    pub t0_mult: f32,
+    /// Output token decimals multiplier
+    pub t1_mult: f32,
+}
+
+impl From<trade_rpc::Speed> for Speed {
+    fn from(speed: trade_rpc::Speed) -> Self {
+        match speed {
+            trade_rpc::Speed::Slow => Speed::Slow,
+            trade_rpc::Speed::Standard => Speed::Standard,
+            trade_rpc::Speed::Fast => Speed::Fast,
+        }
+    }
+}
+
 pub struct Trade {}
 
 impl Trade {
+    pub async fn quote(
+        w3: &Web3<WebSocket>,
+        acc: &Account,
+        m: &Market,
+        t0: &Address,
+        t1: &Address,
+        p: &QuoteRequest,
+    ) -> Result<Quote, Box<dyn Error>> {
+        let addr: Address = acc.address.parse()?;
+        let r_abi = Self::abi(m, "IUniswapV2Router02")?;
+        let t_abi = Self::abi(m, "IERC20")?;
+        let t0_cont = Contract::from_json(w3.eth(), *t0, &t_abi)?;
+        let t1_cont = Contract::from_json(w3.eth(), *t1, &t_abi)?;
+        let t0_mult = Self::multiplier(&t0_cont).await?;
+        let t1_mult = Self::multiplier(&t1_cont).await?;
+
+        let amount_in = convert_to_wei(p.amount.to_owned(), t0_mult)?;
+        let best = Router::best_route(
+            w3,
+            m,
+            &p.chain_id,
+            &p.exchange,
+            t0,
+            t1,
+            amount_in,
+            SwapMode::ExactInput,
+        )
+        .await?;
+        let r_cont = Contract::from_json(w3.eth(), best.router, &r_abi)?;
+        let swap_type = Self::swap_type(&best, SwapMode::ExactInput);
+        let amount_out = best.amount_received();
+        let amount_out_min = apply_slippage(amount_out, p.slippage);
+        let price_impact =
+            Self::price_impact(w3, m, &r_cont, &best.tokens, amount_in, best.amount_out).await?;
+
+        let deadline = get_deadline(DEFAULT_DEADLINE);
+        let (data, value) = Self::swap_call(
+            &r_cont,
+            swap_type,
+            amount_in,
+            amount_out_min,
+            &best.tokens,
+            addr,
+            deadline,
+        )?;
+        let gas = w3
+            .eth()
+            .estimate_gas(
+                CallRequest {
+                    from: Some(addr),
+                    to: Some(best.router),
+                    value: Some(value),
+                    data: Some(data),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap_or_else(|_| U256::from(FALLBACK_SWAP_GAS * (best.tokens.len() as u64 - 1)));
+        let gas_price = Fees::estimate(w3, p.speed().into()).await?.gas_price();
+
+        Ok(Quote {
+            exchange: best.exchange,
+            amount_out,
+            amount_out_min,
+            route: best.tokens,
+            fees: vec![],
+            price_impact,
+            gas,
+            gas_price,
+            t1_mult,
+        })
+    }
+
     pub async fn swap(
         w3: &Web3<WebSocket>,
+        db: &PgPool,
         acc: &Account,
-        r_abi: &[u8],
-        t0_abi: &[u8],
-        r: &Address,
+        m: &Market,
         t0: &Address,
         t1: &Address,
         p: &SwapRequest,
     ) -> Result<H256, Box<dyn Error>> {
-        let addr = Address::from_slice(acc.address.as_bytes());
-        let r_cont = Contract::from_json(w3.eth(), r.clone(), r_abi).unwrap();
-        let t0_cont = Contract::from_json(w3.eth(), t0.clone(), t0_abi).unwrap();
-        let weth: Address = r_cont
-            .query("WETH", (), None, Options::default(), None)
-            .await
-            .unwrap();
-        let t0_dec: U256 = t0_cont
+        let addr: Address = acc.address.parse()?;
+        let t_abi = Self::abi(m, "IERC20")?;
+        let t0_cont = Contract::from_json(w3.eth(), *t0, &t_abi)?;
+        let mode = p.mode();
+
+        // For exact output swaps `amount` is the token1 amount to receive and
+        // the slippage bounds the token0 amount spent instead
+        let amount = match mode {
+            SwapMode::ExactInput => {
+                convert_to_wei(p.amount.to_owned(), Self::multiplier(&t0_cont).await?)?
+            }
+            SwapMode::ExactOutput => {
+                let t1_cont = Contract::from_json(w3.eth(), *t1, &t_abi)?;
+                convert_to_wei(p.amount.to_owned(), Self::multiplier(&t1_cont).await?)?
+            }
+        };
+        let best =
+            Router::best_route(w3, m, &p.chain_id, &p.exchange, t0, t1, amount, mode).await?;
+        let (amount_in, amount_out) = match mode {
+            SwapMode::ExactInput => (
+                best.amount_in,
+                apply_slippage(best.amount_received(), p.slippage),
+            ),
+            SwapMode::ExactOutput => (
+                apply_slippage_max(best.amount_in, p.slippage),
+                best.amount_out,
+            ),
+        };
+        let speed = p.speed().into();
+        // Routes from WETH are paid in native currency, nothing to approve
+        if best.tokens.first() == Some(&best.weth) {
+            let balance = w3.eth().balance(addr, None).await?;
+            if balance < amount_in {
+                return Err("Insufficient native balance".into());
+            }
+        } else {
+            let t0_balance: U256 = t0_cont
+                .query("balanceOf", addr, None, Options::default(), None)
+                .await?;
+            if t0_balance < amount_in {
+                return Err("Insufficient token0 balance".into());
+            }
+            let policy = ApprovalPolicy::from_env();
+            Approval::approve(w3, db, acc, &t0_cont, best.router, amount_in, policy, speed).await?;
+        }
+        Self::execute(
+            w3, db, acc, m, &best, mode, amount_in, amount_out, p.deadline, speed,
+        )
+        .await
+    }
+
+    /// Price an exact input order split across the routes of `Router::split`
+    pub async fn split_quote(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        t0: &Address,
+        t1: &Address,
+        p: &QuoteRequest,
+    ) -> Result<SplitQuote, Box<dyn Error>> {
+        let t_abi = Self::abi(m, "IERC20")?;
+        let t0_cont = Contract::from_json(w3.eth(), *t0, &t_abi)?;
+        let t1_cont = Contract::from_json(w3.eth(), *t1, &t_abi)?;
+        let t0_mult = Self::multiplier(&t0_cont).await?;
+        let t1_mult = Self::multiplier(&t1_cont).await?;
+
+        let amount_in = convert_to_wei(p.amount.to_owned(), t0_mult)?;
+        let allocations = Router::split(w3, m, &p.chain_id, &p.exchange, t0, t1, amount_in).await?;
+        let amount_out = allocations
+            .iter()
+            .fold(U256::zero(), |acc, a| acc + a.amount_received());
+
+        Ok(SplitQuote {
+            allocations,
+            amount_out,
+            amount_out_min: apply_slippage(amount_out, p.slippage),
+            t0_mult,
+            t1_mult,
+        })
+    }
+
+    /// Execute an exact input order split across several routes,
+    /// one router transaction per allocation
+    pub async fn split_swap(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        t0: &Address,
+        t1: &Address,
+        p: &SwapRequest,
+    ) -> Result<(SplitQuote, Vec<H256>), Box<dyn Error>> {
+        if p.mode() != SwapMode::ExactInput {
+            return Err("Split orders only support exact input swaps".into());
+        }
+        let addr: Address = acc.address.parse()?;
+        let t_abi = Self::abi(m, "IERC20")?;
+        let t0_cont = Contract::from_json(w3.eth(), *t0, &t_abi)?;
+        let quote = Self::split_quote(
+            w3,
+            m,
+            t0,
+            t1,
+            &QuoteRequest {
+                user_id: p.user_id.clone(),
+                chain_id: p.chain_id,
+                exchange: p.exchange.clone(),
+                token0: p.token0.clone(),
+                token1: p.token1.clone(),
+                amount: p.amount,
+                slippage: p.slippage,
+                speed: p.speed,
+            },
+        )
+        .await?;
+
+        // Routes from the WETH of their router are paid in native currency
+        let (native, tokens): (Vec<&V2Route>, Vec<&V2Route>) = quote
+            .allocations
+            .iter()
+            .partition(|a| a.tokens.first() == Some(&a.weth));
+        let native_in = native.iter().fold(U256::zero(), |acc, a| acc + a.amount_in);
+        if !native_in.is_zero() && w3.eth().balance(addr, None).await? < native_in {
+            return Err("Insufficient native balance".into());
+        }
+        let amount_in = tokens.iter().fold(U256::zero(), |acc, a| acc + a.amount_in);
+        if !amount_in.is_zero() {
+            let t0_balance: U256 = t0_cont
+                .query("balanceOf", addr, None, Options::default(), None)
+                .await?;
+            if t0_balance < amount_in {
+                return Err("Insufficient token0 balance".into());
+            }
+        }
+
+        // Approve the whole allocation of every router up front, so swaps
+        // sent before the previous ones are mined still see the allowance
+        let mut routers: Vec<(Address, U256)> = vec![];
+        for a in tokens {
+            match routers.iter_mut().find(|(r, _)| *r == a.router) {
+                Some((_, amount)) => *amount += a.amount_in,
+                None => routers.push((a.router, a.amount_in)),
+            }
+        }
+        let policy = ApprovalPolicy::from_env();
+        for (r, amount) in routers {
+            Approval::approve(w3, db, acc, &t0_cont, r, amount, policy, p.speed().into()).await?;
+        }
+
+        let mut hashes = vec![];
+        for a in &quote.allocations {
+            let amount_out_min = apply_slippage(a.amount_received(), p.slippage);
+            let hash = Self::execute(
+                w3,
+                db,
+                acc,
+                m,
+                a,
+                SwapMode::ExactInput,
+                a.amount_in,
+                amount_out_min,
+                p.deadline,
+                p.speed().into(),
+            )
+            .await?;
+            hashes.push(hash);
+        }
+        Ok((quote, hashes))
+    }
+
+    /// Send the router transaction of a priced route, token0 must already be approved.
+    ///
+    /// Exact input swaps take the exact `amount_in` and the minimum `amount_out`,
+    /// exact output swaps take the maximum `amount_in` and the exact `amount_out`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn execute(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        route: &V2Route,
+        mode: SwapMode,
+        amount_in: U256,
+        amount_out: U256,
+        deadline: u32,
+        speed: Speed,
+    ) -> Result<H256, Box<dyn Error>> {
+        let addr: Address = acc.address.parse()?;
+        let r = route.router;
+        let r_cont = Contract::from_json(w3.eth(), r, &Self::abi(m, "IUniswapV2Router02")?)?;
+        let swap_type = Self::swap_type(route, mode);
+        let deadline = get_deadline(deadline);
+
+        let fees = Fees::estimate(w3, speed).await?;
+        let (data, value) = Self::swap_call(
+            &r_cont,
+            swap_type,
+            amount_in,
+            amount_out,
+            &route.tokens,
+            addr,
+            deadline,
+        )?;
+        let call = CallRequest {
+            from: Some(addr),
+            to: Some(r),
+            value: Some(value),
+            data: Some(data.clone()),
+            ..Default::default()
+        };
+        Self::simulate(w3, &call).await?;
+        // Estimation fails while the approval is still pending
+        let gas_estimate =
+            w3.eth().estimate_gas(call, None).await.unwrap_or_else(|_| {
+                U256::from(FALLBACK_SWAP_GAS * (route.tokens.len() as u64 - 1))
+            });
+        let mut tx_payload = TransactionParameters {
+            to: Some(r),
+            gas: gas_limit(gas_estimate),
+            value,
+            data,
+            ..Default::default()
+        };
+        fees.apply(&mut tx_payload);
+        Ok(Wallet::send(w3, db, acc, &tx_payload).await?)
+    }
+
+    /// Run a call with `eth_call` on the pending block, which sees the approvals sent
+    /// just before, and return its output. A revert comes back as an error with its
+    /// decoded reason.
+    pub(crate) async fn simulate(
+        w3: &Web3<WebSocket>,
+        call: &CallRequest,
+    ) -> Result<Bytes, Box<dyn Error>> {
+        let block = BlockId::Number(BlockNumber::Pending);
+        match w3.eth().call(call.clone(), Some(block)).await {
+            Ok(output) => Ok(output),
+            Err(err) => match revert_reason(&err) {
+                Some(reason) => Err(format!("Simulation reverted: {}", reason).into()),
+                None => Err(err.into()),
+            },
+        }
+    }
+
+    /// Gas of a call estimated on the pending block, like `simulate`, so that
+    /// approvals sent just before are seen
+    pub(crate) async fn estimate_gas(
+        w3: &Web3<WebSocket>,
+        call: CallRequest,
+    ) -> Result<U256, Box<dyn Error>> {
+        Ok(w3
+            .eth()
+            .estimate_gas(call, Some(BlockNumber::Pending))
+            .await?)
+    }
+
+    /// Pair of the router's factory for the tokens, with its reserves and LP supply
+    pub async fn pair(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        r: &Address,
+        ta: &Address,
+        tb: &Address,
+    ) -> Result<PairState, Box<dyn Error>> {
+        let r_cont = Contract::from_json(w3.eth(), *r, &Self::abi(m, "IUniswapV2Router02")?)?;
+        let factory: Address = r_cont
+            .query("factory", (), None, Options::default(), None)
+            .await?;
+        let f_cont = Contract::from_json(w3.eth(), factory, &Self::abi(m, "IUniswapV2Factory")?)?;
+        let pair: Address = f_cont
+            .query("getPair", (*ta, *tb), None, Options::default(), None)
+            .await?;
+        if pair.is_zero() {
+            return Err("No pair for token pair".into());
+        }
+
+        let p_cont = Contract::from_json(w3.eth(), pair, &Self::abi(m, "IUniswapV2Pair")?)?;
+        let token0: Address = p_cont
+            .query("token0", (), None, Options::default(), None)
+            .await?;
+        let token1 = if &token0 == ta { *tb } else { *ta };
+        let (reserve0, reserve1, _): (U256, U256, U256) = p_cont
+            .query("getReserves", (), None, Options::default(), None)
+            .await?;
+        let total_supply: U256 = p_cont
+            .query("totalSupply", (), None, Options::default(), None)
+            .await?;
+        let t_abi = Self::abi(m, "IERC20")?;
+        let t0_mult = Self::multiplier(&Contract::from_json(w3.eth(), token0, &t_abi)?).await?;
+        let t1_mult = Self::multiplier(&Contract::from_json(w3.eth(), token1, &t_abi)?).await?;
+        Ok(PairState {
+            pair,
+            token0,
+            token1,
+            reserve0,
+            reserve1,
+            sqrt_price_x96: U256::zero(),
+            tick: 0,
+            liquidity: U256::zero(),
+            fee: V2_FEE,
+            total_supply,
+            t0_mult,
+            t1_mult,
+        })
+    }
+
+    // pub fn weth() {}
+
+    /// Slippage tolerance must be a percentage within `0..=MAX_SLIPPAGE`
+    pub fn check_slippage(slippage: f32) -> Result<(), String> {
+        if !slippage.is_finite() || !(0.0..=MAX_SLIPPAGE).contains(&slippage) {
+            return Err(format!(
+                "Slippage must be between 0 and {}%, got {}",
+                MAX_SLIPPAGE, slippage
+            ));
+        }
+        Ok(())
+    }
+
+    /// Amount must be a finite number above zero
+    pub fn check_amount(amount: f32) -> Result<(), String> {
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(format!("Amount must be above 0, got {}", amount));
+        }
+        Ok(())
+    }
+
+    /// Deposit amounts must be finite and not negative, with at least one above zero
+    pub fn check_amounts(amount0: f32, amount1: f32) -> Result<(), String> {
+        let valid = |a: f32| a.is_finite() && a >= 0.0;
+        if !valid(amount0) || !valid(amount1) || amount0 + amount1 <= 0.0 {
+            return Err(format!(
+                "Amounts must not be negative and not both 0, got {} and {}",
+                amount0, amount1
+            ));
+        }
+        Ok(())
+    }
+
+    /// Deadline must be within `1..=MAX_DEADLINE` seconds from now
+    pub fn check_deadline(deadline: u32) -> Result<(), String> {
+        if !(1..=MAX_DEADLINE).contains(&deadline) {
+            return Err(format!(
+                "Deadline must be between 1 and {} seconds, got {}",
+                MAX_DEADLINE, deadline
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn abi(m: &Market, name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        m.try_get_abi(&name.to_string())
+            .ok_or_else(|| format!("Unknown abi {}", name).into())
+    }
+
+    /// Router function for a route, swapping native currency when it starts or ends with WETH.
+    ///
+    /// Routes through fee-on-transfer tokens use the `SupportingFeeOnTransferTokens`
+    /// variants, which check the amount received instead of the amount priced.
+    fn swap_type(route: &V2Route, mode: SwapMode) -> &'static str {
+        let (tokens, weth) = (&route.tokens, Some(&route.weth));
+        let exact_out = mode == SwapMode::ExactOutput;
+        let fee_on_transfer = route.transfer_tax > 0;
+        if tokens.first() == weth {
+            if exact_out {
+                "swapETHForExactTokens"
+            } else if fee_on_transfer {
+                "swapExactETHForTokensSupportingFeeOnTransferTokens"
+            } else {
+                "swapExactETHForTokens"
+            }
+        } else if tokens.last() == weth {
+            if exact_out {
+                "swapTokensForExactETH"
+            } else if fee_on_transfer {
+                "swapExactTokensForETHSupportingFeeOnTransferTokens"
+            } else {
+                "swapExactTokensForETH"
+            }
+        } else if exact_out {
+            "swapTokensForExactTokens"
+        } else if fee_on_transfer {
+            "swapExactTokensForTokensSupportingFeeOnTransferTokens"
+        } else {
+            "swapExactTokensForTokens"
+        }
+    }
+
+    /// Decimals multiplier of an ERC20 token
+    pub(crate) async fn multiplier(t_cont: &Contract<WebSocket>) -> Result<f32, Box<dyn Error>> {
+        let dec: U256 = t_cont
             .query("decimals", (), None, Options::default(), None)
-            .await
-            .unwrap();
-        let t0_mult = match t0_dec.as_u32() {
+            .await?;
+        Ok(match dec.as_u32() {
             3 => 1e3,
             6 => 1e6,
             9 => 1e9,
             18 => 1e18,
             _ => 1e18,
-        };
-
-        let swap_type: &str;
-        let route: Vec<Address>;
-        if t0 == &weth {
-            swap_type = "swapExactETHForTokens".trim();
-            route = vec![weth, t1.to_owned()];
-        } else if t1 == &weth {
-            swap_type = "swapExactTokensForETH".trim();
-            route = vec![t0.to_owned(), weth];
-        } else {
-            swap_type = "swapExactTokensForTokens".trim();
-            route = vec![t0.to_owned(), weth, t1.to_owned()];
-        }
-
-        let block = w3.eth().block_number().await.unwrap();
-        let amount_in = convert_to_wei(p.amount.to_owned(), t0_mult);
-        let amount_out: Vec<U256> = r_cont
-            .query(
-                "getAmountsOut",
-                (amount_in, route.clone()),
-                addr,
-                Options::default(),
-                Some(block.into()),
-            )
-            .await
-            .unwrap();
-        let t0_balance: U256 = t0_cont
-            .query("balanceOf", addr, None, Options::default(), None)
-            .await
-            .unwrap();
-        if t0_balance < amount_in {
-            return Err("Insufficient token0 balance".into());
-        }
+        })
+    }
 
-        let t0_allowance: U256 = t0_cont
-            .query(
-                "allowance",
-                (addr, r.to_owned()),
-                None,
-                Options::default(),
-                None,
-            )
-            .await
-            .unwrap();
-        if t0_allowance < amount_in {
-            let nonce = w3.eth().transaction_count(addr, None).await.unwrap();
-            let gas_price = w3.eth().gas_price().await.unwrap();
-            let data = t0_cont
-                .abi()
-                .function("approve")
-                .unwrap()
-                .encode_input(&(r.to_owned(), amount_in).into_tokens())
-                .unwrap();
-            let gas_estimate = t0_cont
-                .estimate_gas(
-                    "approve",
-                    (r.to_owned(), amount_in),
-                    addr,
-                    Options {
-                        ..Default::default()
-                    },
-                )
-                .await
-                .unwrap();
-            let tx_payload = TransactionParameters {
-                nonce: Some(nonce),
-                to: Some(t0_cont.address()),
-                value: U256::zero(),
-                gas_price: Some(gas_price),
-                max_priority_fee_per_gas: Some(gas_estimate),
-                data: Bytes(data),
-                ..Default::default()
-            };
-            Wallet::send(w3, acc, &tx_payload).await?;
-        }
-
-        let valid_timestamp = get_valid_timestamp(300000);
-        let deadline = U256::from_dec_str(&valid_timestamp.to_string()).unwrap();
-
-        let nonce = w3.eth().transaction_count(addr, None).await.unwrap();
-        let gas_price = w3.eth().gas_price().await.unwrap();
-        let tx_payload: TransactionParameters;
+    /// Calldata and value of a router swap call.
+    ///
+    /// Exact input swaps take the exact `amount_in` and the minimum `amount_out`,
+    /// exact output swaps take the maximum `amount_in` and the exact `amount_out`.
+    fn swap_call(
+        r_cont: &Contract<WebSocket>,
+        swap_type: &str,
+        amount_in: U256,
+        amount_out: U256,
+        route: &[Address],
+        to: Address,
+        deadline: U256,
+    ) -> Result<(Bytes, U256), Box<dyn Error>> {
+        let f = r_cont.abi().function(swap_type)?;
+        let route = route.to_vec();
         match swap_type {
-            "swapExactETHForTokens" => {
-                let gas_estimate = r_cont
-                    .estimate_gas(
-                        swap_type,
-                        (amount_out.clone(), route.clone(), addr, deadline),
-                        addr,
-                        Options {
-                            value: Some(amount_in),
-                            ..Default::default()
-                        },
-                    )
-                    .await
-                    .unwrap();
-                let data = r_cont
-                    .abi()
-                    .function(swap_type)
-                    .unwrap()
-                    .encode_input(&(amount_out, route, addr, deadline).into_tokens())
-                    .unwrap();
-                tx_payload = TransactionParameters {
-                    nonce: Some(nonce),
-                    to: Some(r.to_owned()),
-                    value: amount_in,
-                    gas_price: Some(gas_price),
-                    max_priority_fee_per_gas: Some(gas_estimate),
-                    data: Bytes(data),
-                    ..Default::default()
-                };
+            "swapExactETHForTokens"
+            | "swapExactETHForTokensSupportingFeeOnTransferTokens"
+            | "swapETHForExactTokens" => {
+                // Unspent ETH of exact output swaps is refunded by the router
+                let data = f.encode_input(&(amount_out, route, to, deadline).into_tokens())?;
+                Ok((Bytes(data), amount_in))
             }
-            "swapExactTokensForETH" => {
-                let in_gas_estimate = r_cont
-                    .estimate_gas(
-                        swap_type,
-                        (amount_in, amount_out.clone(), route.clone(), addr, deadline),
-                        addr,
-                        Options {
-                            value: Some(U256::zero()),
-                            gas: Some(500_000.into()),
-                            ..Default::default()
-                        },
-                    )
-                    .await
-                    .unwrap();
-                let data = r_cont
-                    .abi()
-                    .function(swap_type)
-                    .unwrap()
-                    .encode_input(&(amount_in, amount_out, route, addr, deadline).into_tokens())
-                    .unwrap();
-                tx_payload = TransactionParameters {
-                    nonce: Some(nonce),
-                    to: Some(r.to_owned()),
-                    value: U256::zero(),
-                    gas_price: Some(gas_price),
-                    max_priority_fee_per_gas: Some(in_gas_estimate),
-                    data: Bytes(data),
-                    ..Default::default()
-                };
-            }
-            "swapExactTokensForTokens" => {
-                let in_gas_estimate = r_cont
-                    .estimate_gas(
-                        swap_type,
-                        (amount_in, amount_out.clone(), route.clone(), addr, deadline),
-                        addr,
-                        Options {
-                            value: Some(U256::zero()),
-                            gas: Some(500_000.into()),
-                            ..Default::default()
-                        },
-                    )
-                    .await
-                    .unwrap();
-                let data = r_cont
-                    .abi()
-                    .function(swap_type)
-                    .unwrap()
-                    .encode_input(&(amount_in, amount_out, route, addr, deadline).into_tokens())
-                    .unwrap();
-                tx_payload = TransactionParameters {
-                    nonce: Some(nonce),
-                    to: Some(r.to_owned()),
-                    value: U256::zero(),
-                    gas_price: Some(gas_price),
-                    data: Bytes(data),
-                    max_priority_fee_per_gas: Some(in_gas_estimate),
-                    ..Default::default()
-                };
+            "swapTokensForExactTokens" | "swapTokensForExactETH" => {
+                let data =
+                    f.encode_input(&(amount_out, amount_in, route, to, deadline).into_tokens())?;
+                Ok((Bytes(data), U256::zero()))
             }
             _ => {
-                return Err("Invalid swap type".into());
+                let data =
+                    f.encode_input(&(amount_in, amount_out, route, to, deadline).into_tokens())?;
+                Ok((Bytes(data), U256::zero()))
             }
         }
-        Ok(Wallet::send(w3, acc, &tx_payload).await.unwrap())
     }
 
-    // pub async fn pair() {}
-    // pub async fn liquidity() {}
-    // pub fn weth() {}
+    /// Price impact of a V2 route: output compared with the fee-adjusted mid price
+    /// taken from the reserves of every pair along the route
+    pub(crate) async fn price_impact(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        r_cont: &Contract<WebSocket>,
+        route: &[Address],
+        amount_in: U256,
+        amount_out: U256,
+    ) -> Result<f64, Box<dyn Error>> {
+        let f_abi = Self::abi(m, "IUniswapV2Factory")?;
+        let p_abi = Self::abi(m, "IUniswapV2Pair")?;
+        let factory: Address = r_cont
+            .query("factory", (), None, Options::default(), None)
+            .await?;
+        let f_cont = Contract::from_json(w3.eth(), factory, &f_abi)?;
+
+        let mut mid_out = to_f64(amount_in);
+        for hop in route.windows(2) {
+            let pair: Address = f_cont
+                .query("getPair", (hop[0], hop[1]), None, Options::default(), None)
+                .await?;
+            if pair.is_zero() {
+                return Err(format!("No pair for {:?} and {:?}", hop[0], hop[1]).into());
+            }
+            let p_cont = Contract::from_json(w3.eth(), pair, &p_abi)?;
+            let token0: Address = p_cont
+                .query("token0", (), None, Options::default(), None)
+                .await?;
+            let (r0, r1, _): (U256, U256, U256) = p_cont
+                .query("getReserves", (), None, Options::default(), None)
+                .await?;
+            let (r_in, r_out) = if token0 == hop[0] { (r0, r1) } else { (r1, r0) };
+            if r_in.is_zero() {
+                return Err(format!("Empty reserves in pair {:?}", pair).into());
+            }
+            mid_out = mid_out * to_f64(r_out) / to_f64(r_in) * V2_FEE_FACTOR;
+        }
+
+        if mid_out == 0.0 {
+            return Ok(0.0);
+        }
+        Ok((1.0 - to_f64(amount_out) / mid_out).max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_are_checked() {
+        assert!(Trade::check_amount(1.5).is_ok());
+        assert!(Trade::check_amount(0.0).is_err());
+        assert!(Trade::check_amount(-1.0).is_err());
+        assert!(Trade::check_amount(f32::NAN).is_err());
+        assert!(Trade::check_amount(f32::INFINITY).is_err());
+
+        assert!(Trade::check_amounts(1.0, 0.0).is_ok());
+        assert!(Trade::check_amounts(0.0, 2.0).is_ok());
+        assert!(Trade::check_amounts(0.0, 0.0).is_err());
+        assert!(Trade::check_amounts(-1.0, 2.0).is_err());
+        assert!(Trade::check_amounts(1.0, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn invalid_amounts_are_not_converted() {
+        assert_eq!(convert_to_wei(1.5, 1e6), Ok(U256::from(1_500_000)));
+        assert!(convert_to_wei(-1.0, 1e18).is_err());
+        assert!(convert_to_wei(f32::NAN, 1e18).is_err());
+        assert!(convert_to_wei(1e30, 1e18).is_err());
+    }
 }
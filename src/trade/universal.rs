@@ -0,0 +1,671 @@
+//! # Universal Router
+//!
+//! The Universal Router runs a list of commands in one `execute` call. Swaps are
+//! planned as one or two legs on Uniswap V2 pairs or V3 pools, with the wrapping of
+//! native currency and the Permit2 signature of the input token in the same transaction.
+
+use crate::database::models::account::Account;
+use crate::database::pool::PgPool;
+use crate::market::market::Market;
+use crate::trade::approval::{Approval, Authorization, SignedPermitSingle, Spender};
+use crate::trade::handler::trade_rpc::{QuoteRequest, SwapMode, SwapRequest};
+use crate::trade::router::{Router, V2Route};
+use crate::trade::trade::{Quote, Trade, DEFAULT_DEADLINE};
+use crate::trade::v3::{encode_path, TradeV3, V3Route};
+use crate::wallet::fee::{gas_limit, Fees};
+use crate::wallet::util::{apply_slippage, convert_to_wei, get_deadline};
+use crate::wallet::wallet::Wallet;
+use std::error::Error;
+use web3::contract::{Contract, Options};
+use web3::ethabi::{encode, Token};
+use web3::types::{Address, Bytes, CallRequest, TransactionParameters, H256, U256};
+use web3::{transports::WebSocket, Web3};
+
+/// Command types of `execute`
+pub const V3_SWAP_EXACT_IN: u8 = 0x00;
+pub const SWEEP: u8 = 0x04;
+pub const V2_SWAP_EXACT_IN: u8 = 0x08;
+pub const PERMIT2_PERMIT: u8 = 0x0a;
+pub const WRAP_ETH: u8 = 0x0b;
+pub const UNWRAP_WETH: u8 = 0x0c;
+
+/// Recipient standing for the router itself
+pub fn address_this() -> Address {
+    Address::from_low_u64_be(2)
+}
+
+/// Input amount standing for the router's whole balance of the token
+pub fn contract_balance() -> U256 {
+    U256::one() << 255
+}
+
+/// V2 router the Universal Router's pairs are priced with
+const V2_ROUTER: &str = "UniswapV2Router02";
+
+/// Uniswap V2 pairs fee, in hundredths of a bip like the V3 fee tiers
+const V2_POOL_FEE: u32 = 3000;
+
+/// Gas limit reported by `quote` for every hop when `execute` can't be estimated yet
+/// (e.g. Permit2 has no allowance for token0)
+const FALLBACK_HOP_GAS: u64 = 150_000;
+
+/// Command of an `execute` call, with the parameters the router decodes from its input
+#[derive(Clone, Debug)]
+pub enum Command {
+    V2SwapExactIn {
+        recipient: Address,
+        amount_in: U256,
+        amount_out_min: U256,
+        path: Vec<Address>,
+        /// Tokens are taken from the caller through Permit2, otherwise from the router
+        payer_is_user: bool,
+    },
+    V3SwapExactIn {
+        recipient: Address,
+        amount_in: U256,
+        amount_out_min: U256,
+        /// Packed path, see `encode_path`
+        path: Vec<u8>,
+        payer_is_user: bool,
+    },
+    /// Wrap the native currency sent with the call
+    WrapEth {
+        recipient: Address,
+        amount_min: U256,
+    },
+    /// Unwrap the router's whole wrapped-native balance
+    UnwrapWeth {
+        recipient: Address,
+        amount_min: U256,
+    },
+    /// Redeem a Permit2 allowance for the router
+    Permit2Permit(SignedPermitSingle),
+    /// Send the router's whole balance of the token
+    Sweep {
+        token: Address,
+        recipient: Address,
+        amount_min: U256,
+    },
+}
+
+impl Command {
+    pub fn code(&self) -> u8 {
+        match *self {
+            Command::V2SwapExactIn { .. } => V2_SWAP_EXACT_IN,
+            Command::V3SwapExactIn { .. } => V3_SWAP_EXACT_IN,
+            Command::WrapEth { .. } => WRAP_ETH,
+            Command::UnwrapWeth { .. } => UNWRAP_WETH,
+            Command::Permit2Permit(_) => PERMIT2_PERMIT,
+            Command::Sweep { .. } => SWEEP,
+        }
+    }
+
+    /// ABI encoded parameters of the command
+    pub fn input(&self) -> Vec<u8> {
+        let tokens = match self {
+            Command::V2SwapExactIn {
+                recipient,
+                amount_in,
+                amount_out_min,
+                path,
+                payer_is_user,
+            } => vec![
+                Token::Address(*recipient),
+                Token::Uint(*amount_in),
+                Token::Uint(*amount_out_min),
+                Token::Array(path.iter().map(|t| Token::Address(*t)).collect()),
+                Token::Bool(*payer_is_user),
+            ],
+            Command::V3SwapExactIn {
+                recipient,
+                amount_in,
+                amount_out_min,
+                path,
+                payer_is_user,
+            } => vec![
+                Token::Address(*recipient),
+                Token::Uint(*amount_in),
+                Token::Uint(*amount_out_min),
+                Token::Bytes(path.clone()),
+                Token::Bool(*payer_is_user),
+            ],
+            Command::WrapEth {
+                recipient,
+                amount_min,
+            }
+            | Command::UnwrapWeth {
+                recipient,
+                amount_min,
+            } => vec![Token::Address(*recipient), Token::Uint(*amount_min)],
+            Command::Permit2Permit(permit) => vec![
+                permit.permit.clone().into_token(),
+                Token::Bytes(permit.signature_bytes()),
+            ],
+            Command::Sweep {
+                token,
+                recipient,
+                amount_min,
+            } => vec![
+                Token::Address(*token),
+                Token::Address(*recipient),
+                Token::Uint(*amount_min),
+            ],
+        };
+        encode(&tokens)
+    }
+}
+
+/// Calldata of `execute(commands, inputs, deadline)`
+pub fn execute_call(
+    m: &Market,
+    commands: &[Command],
+    deadline: U256,
+) -> Result<Bytes, Box<dyn Error>> {
+    let r_abi = web3::ethabi::Contract::load(Trade::abi(m, "IUniversalRouter")?.as_slice())?;
+    let execute = r_abi
+        .functions_by_name("execute")?
+        .iter()
+        .find(|f| f.inputs.len() == 3)
+        .ok_or("No execute overload with deadline")?;
+    let codes = commands.iter().map(Command::code).collect();
+    let inputs = commands.iter().map(|c| Token::Bytes(c.input())).collect();
+    Ok(Bytes(execute.encode_input(&[
+        Token::Bytes(codes),
+        Token::Array(inputs),
+        Token::Uint(deadline),
+    ])?))
+}
+
+/// Part of a route swapped on a single protocol
+#[derive(Clone, Debug)]
+pub enum Leg {
+    V2(V2Route),
+    V3 { route: V3Route, amount_in: U256 },
+}
+
+impl Leg {
+    pub fn tokens(&self) -> &[Address] {
+        match self {
+            Leg::V2(route) => &route.tokens,
+            Leg::V3 { route, .. } => &route.tokens,
+        }
+    }
+
+    /// Pool fee of every hop
+    pub fn fees(&self) -> Vec<u32> {
+        match self {
+            Leg::V2(route) => vec![V2_POOL_FEE; route.tokens.len() - 1],
+            Leg::V3 { route, .. } => route.fees.clone(),
+        }
+    }
+
+    pub fn amount_out(&self) -> U256 {
+        match self {
+            Leg::V2(route) => route.amount_out,
+            Leg::V3 { route, .. } => route.amount_out,
+        }
+    }
+
+    fn command(
+        &self,
+        recipient: Address,
+        amount_in: U256,
+        amount_out_min: U256,
+        payer_is_user: bool,
+    ) -> Command {
+        match self {
+            Leg::V2(route) => Command::V2SwapExactIn {
+                recipient,
+                amount_in,
+                amount_out_min,
+                path: route.tokens.clone(),
+                payer_is_user,
+            },
+            Leg::V3 { route, .. } => Command::V3SwapExactIn {
+                recipient,
+                amount_in,
+                amount_out_min,
+                path: encode_path(&route.tokens, &route.fees),
+                payer_is_user,
+            },
+        }
+    }
+}
+
+/// Commands of an exact input swap along `legs`, delivering to `to`.
+///
+/// Native input is wrapped by the router and native output unwrapped by it. The first
+/// leg pays with the caller's tokens, redeeming `permit` first when there is one, the
+/// next legs swap the router's balance. The output of a multi leg route is swept from
+/// the router, so the minimum is checked on the total.
+pub fn commands(
+    legs: &[Leg],
+    weth: &Address,
+    amount_in: U256,
+    amount_out_min: U256,
+    to: Address,
+    permit: Option<SignedPermitSingle>,
+) -> Vec<Command> {
+    let t0 = legs[0].tokens()[0];
+    let t1 = *legs[legs.len() - 1].tokens().last().unwrap_or(&t0);
+    let eth_in = t0 == *weth;
+    let eth_out = t1 == *weth;
+    let direct = legs.len() == 1 && !eth_out;
+
+    let mut commands = vec![];
+    if eth_in {
+        commands.push(Command::WrapEth {
+            recipient: address_this(),
+            amount_min: amount_in,
+        });
+    } else if let Some(permit) = permit {
+        commands.push(Command::Permit2Permit(permit));
+    }
+    for (i, leg) in legs.iter().enumerate() {
+        let (amount, payer_is_user) = if i == 0 {
+            (amount_in, !eth_in)
+        } else {
+            (contract_balance(), false)
+        };
+        let (recipient, min) = if direct {
+            (to, amount_out_min)
+        } else {
+            (address_this(), U256::zero())
+        };
+        commands.push(leg.command(recipient, amount, min, payer_is_user));
+    }
+    if eth_out {
+        commands.push(Command::UnwrapWeth {
+            recipient: to,
+            amount_min: amount_out_min,
+        });
+    } else if !direct {
+        commands.push(Command::Sweep {
+            token: t1,
+            recipient: to,
+            amount_min: amount_out_min,
+        });
+    }
+    commands
+}
+
+pub struct TradeUniversal {}
+
+impl TradeUniversal {
+    /// The Universal Router is picked by exchange name
+    pub fn supports(exchange: &str) -> bool {
+        exchange == "UniversalRouter"
+    }
+
+    pub async fn quote(
+        w3: &Web3<WebSocket>,
+        acc: &Account,
+        m: &Market,
+        r: &Address,
+        t0: &Address,
+        t1: &Address,
+        p: &QuoteRequest,
+    ) -> Result<Quote, Box<dyn Error>> {
+        TradeV3::check_transfer_tax(m, &p.chain_id, t0, t1)?;
+        let addr: Address = acc.address.parse()?;
+        let t_abi = Trade::abi(m, "IERC20")?;
+        let t0_cont = Contract::from_json(w3.eth(), *t0, &t_abi)?;
+        let t1_cont = Contract::from_json(w3.eth(), *t1, &t_abi)?;
+        let weth = Self::weth(m, &p.chain_id)?;
+        let t0_mult = Trade::multiplier(&t0_cont).await?;
+        let t1_mult = Trade::multiplier(&t1_cont).await?;
+
+        let amount_in = convert_to_wei(p.amount.to_owned(), t0_mult)?;
+        let legs = Self::best_route(w3, m, &p.chain_id, t0, t1, &weth, amount_in).await?;
+        let amount_out = legs[legs.len() - 1].amount_out();
+        let amount_out_min = apply_slippage(amount_out, p.slippage);
+        let price_impact = Self::price_impact(w3, m, &p.chain_id, &legs).await?;
+
+        let commands = commands(&legs, &weth, amount_in, amount_out_min, addr, None);
+        let data = execute_call(m, &commands, get_deadline(DEFAULT_DEADLINE))?;
+        let value = if t0 == &weth { amount_in } else { U256::zero() };
+        let hops: usize = legs.iter().map(|l| l.tokens().len() - 1).sum();
+        let gas = w3
+            .eth()
+            .estimate_gas(
+                CallRequest {
+                    from: Some(addr),
+                    to: Some(*r),
+                    value: Some(value),
+                    data: Some(data),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap_or_else(|_| U256::from(FALLBACK_HOP_GAS * hops as u64));
+        let gas_price = Fees::estimate(w3, p.speed().into()).await?.gas_price();
+
+        let mut route = vec![*t0];
+        for leg in &legs {
+            route.extend_from_slice(&leg.tokens()[1..]);
+        }
+        Ok(Quote {
+            exchange: p.exchange.clone(),
+            amount_out,
+            amount_out_min,
+            route,
+            fees: legs.iter().flat_map(Leg::fees).collect(),
+            price_impact,
+            gas,
+            gas_price,
+            t1_mult,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn swap(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        r: &Address,
+        t0: &Address,
+        t1: &Address,
+        p: &SwapRequest,
+    ) -> Result<H256, Box<dyn Error>> {
+        if p.mode() == SwapMode::ExactOutput {
+            return Err("Exact output swaps are supported on V2 routers only".into());
+        }
+        TradeV3::check_transfer_tax(m, &p.chain_id, t0, t1)?;
+        let addr: Address = acc.address.parse()?;
+        let t0_cont = Contract::from_json(w3.eth(), *t0, &Trade::abi(m, "IERC20")?)?;
+        let weth = Self::weth(m, &p.chain_id)?;
+        let t0_mult = Trade::multiplier(&t0_cont).await?;
+
+        let amount_in = convert_to_wei(p.amount.to_owned(), t0_mult)?;
+        let legs = Self::best_route(w3, m, &p.chain_id, t0, t1, &weth, amount_in).await?;
+        let amount_out_min = apply_slippage(legs[legs.len() - 1].amount_out(), p.slippage);
+        let deadline = get_deadline(p.deadline);
+
+        let mut permit = None;
+        if t0 == &weth {
+            let balance = w3.eth().balance(addr, None).await?;
+            if balance < amount_in {
+                return Err("Insufficient native balance".into());
+            }
+        } else {
+            let t0_balance: U256 = t0_cont
+                .query("balanceOf", addr, None, Options::default(), None)
+                .await?;
+            if t0_balance < amount_in {
+                return Err("Insufficient token0 balance".into());
+            }
+            let auth = Approval::authorize(
+                w3,
+                db,
+                acc,
+                m,
+                &p.chain_id,
+                &t0_cont,
+                Spender::Permit2(*r),
+                amount_in,
+                deadline,
+                p.speed().into(),
+            )
+            .await?;
+            if let Authorization::Permit2(signed) = auth {
+                permit = Some(signed);
+            }
+        }
+
+        let commands = commands(&legs, &weth, amount_in, amount_out_min, addr, permit);
+        let data = execute_call(m, &commands, deadline)?;
+        let value = if t0 == &weth { amount_in } else { U256::zero() };
+
+        let fees = Fees::estimate(w3, p.speed().into()).await?;
+        let call = CallRequest {
+            from: Some(addr),
+            to: Some(*r),
+            value: Some(value),
+            data: Some(data.clone()),
+            ..Default::default()
+        };
+        Trade::simulate(w3, &call).await?;
+        let gas = Trade::estimate_gas(w3, call).await?;
+        let mut tx_payload = TransactionParameters {
+            to: Some(*r),
+            gas: gas_limit(gas),
+            value,
+            data,
+            ..Default::default()
+        };
+        fees.apply(&mut tx_payload);
+        Ok(Wallet::send(w3, db, acc, &tx_payload).await?)
+    }
+
+    /// Wrapped native token the router wraps and unwraps native currency with
+    fn weth(m: &Market, chain: &u32) -> Result<Address, Box<dyn Error>> {
+        Ok(m.try_get_wrapped_native(chain)
+            .ok_or("No wrapped native token on chain")?)
+    }
+
+    /// Best of the V2 route, the V3 route and the two-leg routes through WETH which
+    /// swap one leg on V2 pairs and the other on V3 pools. Protocols missing on the
+    /// chain, or without liquidity for a leg, are skipped.
+    async fn best_route(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+        t0: &Address,
+        t1: &Address,
+        weth: &Address,
+        amount_in: U256,
+    ) -> Result<Vec<Leg>, Box<dyn Error>> {
+        let mut candidates: Vec<Vec<Leg>> = vec![];
+        if let Some(leg) = Self::v2_leg(w3, m, chain, t0, t1, amount_in).await {
+            candidates.push(vec![leg]);
+        }
+        if let Some(leg) = Self::v3_leg(w3, m, chain, t0, t1, weth, amount_in).await {
+            candidates.push(vec![leg]);
+        }
+        if t0 != weth && t1 != weth {
+            if let Some(first) = Self::v2_leg(w3, m, chain, t0, weth, amount_in).await {
+                let amount = first.amount_out();
+                if let Some(second) = Self::v3_leg(w3, m, chain, weth, t1, weth, amount).await {
+                    candidates.push(vec![first, second]);
+                }
+            }
+            if let Some(first) = Self::v3_leg(w3, m, chain, t0, weth, weth, amount_in).await {
+                let amount = first.amount_out();
+                if let Some(second) = Self::v2_leg(w3, m, chain, weth, t1, amount).await {
+                    candidates.push(vec![first, second]);
+                }
+            }
+        }
+
+        candidates
+            .into_iter()
+            .max_by_key(|legs| legs[legs.len() - 1].amount_out())
+            .ok_or_else(|| "No Universal Router route for token pair".into())
+    }
+
+    /// Leg on the Uniswap V2 pairs, `None` without a V2 route
+    async fn v2_leg(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+        t0: &Address,
+        t1: &Address,
+        amount_in: U256,
+    ) -> Option<Leg> {
+        let mode = SwapMode::ExactInput;
+        let route = Router::best_route(w3, m, chain, V2_ROUTER, t0, t1, amount_in, mode).await;
+        route.ok().map(Leg::V2)
+    }
+
+    /// Leg on the Uniswap V3 pools, `None` without a V3 route
+    #[allow(clippy::too_many_arguments)]
+    async fn v3_leg(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+        t0: &Address,
+        t1: &Address,
+        weth: &Address,
+        amount_in: U256,
+    ) -> Option<Leg> {
+        let route = TradeV3::best_route(w3, m, chain, t0, t1, weth, amount_in).await;
+        route.ok().map(|route| Leg::V3 { route, amount_in })
+    }
+
+    /// Price impact of the route, compounded over its legs
+    async fn price_impact(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+        legs: &[Leg],
+    ) -> Result<f64, Box<dyn Error>> {
+        let mut kept = 1.0;
+        for leg in legs {
+            let impact = match leg {
+                Leg::V2(route) => {
+                    let r_cont = Contract::from_json(
+                        w3.eth(),
+                        route.router,
+                        &Trade::abi(m, "IUniswapV2Router02")?,
+                    )?;
+                    Trade::price_impact(
+                        w3,
+                        m,
+                        &r_cont,
+                        &route.tokens,
+                        route.amount_in,
+                        route.amount_out,
+                    )
+                    .await?
+                }
+                Leg::V3 { route, amount_in } => {
+                    TradeV3::price_impact(w3, m, chain, route, *amount_in).await?
+                }
+            };
+            kept *= 1.0 - impact;
+        }
+        Ok(1.0 - kept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_leg(tokens: Vec<Address>, amount_in: u64, amount_out: u64) -> Leg {
+        Leg::V2(V2Route {
+            exchange: V2_ROUTER.to_string(),
+            router: Address::repeat_byte(0x72),
+            weth: Address::repeat_byte(0xee),
+            tokens,
+            amount_in: U256::from(amount_in),
+            amount_out: U256::from(amount_out),
+            transfer_tax: 0,
+        })
+    }
+
+    fn v3_leg(tokens: Vec<Address>, amount_in: u64, amount_out: u64) -> Leg {
+        Leg::V3 {
+            route: V3Route {
+                fees: vec![500; tokens.len() - 1],
+                tokens,
+                amount_out: U256::from(amount_out),
+                gas: U256::zero(),
+            },
+            amount_in: U256::from(amount_in),
+        }
+    }
+
+    fn codes(commands: &[Command]) -> Vec<u8> {
+        commands.iter().map(Command::code).collect()
+    }
+
+    #[test]
+    fn single_leg_pays_the_recipient_directly() {
+        let (t0, t1, weth) = (
+            Address::repeat_byte(0x01),
+            Address::repeat_byte(0x02),
+            Address::repeat_byte(0xee),
+        );
+        let to = Address::repeat_byte(0xaa);
+        let legs = vec![v3_leg(vec![t0, t1], 1000, 990)];
+        let commands = commands(&legs, &weth, U256::from(1000), U256::from(980), to, None);
+        assert_eq!(codes(&commands), vec![V3_SWAP_EXACT_IN]);
+
+        // recipient | amountIn | amountOutMin | path offset | payerIsUser | path
+        let input = commands[0].input();
+        assert_eq!(&input[12..32], to.as_bytes());
+        assert_eq!(U256::from_big_endian(&input[32..64]), U256::from(1000));
+        assert_eq!(U256::from_big_endian(&input[64..96]), U256::from(980));
+        assert_eq!(U256::from_big_endian(&input[128..160]), U256::one());
+        assert_eq!(U256::from_big_endian(&input[160..192]), U256::from(43));
+        assert_eq!(&input[192..235], encode_path(&[t0, t1], &[500]).as_slice());
+    }
+
+    #[test]
+    fn mixed_route_from_native_is_wrapped_and_swept() {
+        let (weth, t1) = (Address::repeat_byte(0xee), Address::repeat_byte(0x02));
+        let mid = Address::repeat_byte(0x03);
+        let to = Address::repeat_byte(0xaa);
+        let legs = vec![
+            v2_leg(vec![weth, mid], 1000, 500),
+            v3_leg(vec![mid, t1], 500, 250),
+        ];
+        let commands = commands(&legs, &weth, U256::from(1000), U256::from(245), to, None);
+        assert_eq!(
+            codes(&commands),
+            vec![WRAP_ETH, V2_SWAP_EXACT_IN, V3_SWAP_EXACT_IN, SWEEP]
+        );
+
+        match &commands[1] {
+            Command::V2SwapExactIn {
+                recipient,
+                amount_in,
+                payer_is_user,
+                ..
+            } => {
+                assert_eq!(*recipient, address_this());
+                assert_eq!(*amount_in, U256::from(1000));
+                assert!(!payer_is_user);
+            }
+            c => panic!("Unexpected command {:?}", c),
+        }
+        match &commands[2] {
+            Command::V3SwapExactIn {
+                amount_in,
+                amount_out_min,
+                ..
+            } => {
+                assert_eq!(*amount_in, contract_balance());
+                assert!(amount_out_min.is_zero());
+            }
+            c => panic!("Unexpected command {:?}", c),
+        }
+        match &commands[3] {
+            Command::Sweep {
+                token,
+                recipient,
+                amount_min,
+            } => {
+                assert_eq!((*token, *recipient), (t1, to));
+                assert_eq!(*amount_min, U256::from(245));
+            }
+            c => panic!("Unexpected command {:?}", c),
+        }
+    }
+
+    #[test]
+    fn native_output_is_unwrapped_to_the_recipient() {
+        let (t0, weth) = (Address::repeat_byte(0x01), Address::repeat_byte(0xee));
+        let to = Address::repeat_byte(0xaa);
+        let legs = vec![v2_leg(vec![t0, weth], 1000, 990)];
+        let commands = commands(&legs, &weth, U256::from(1000), U256::from(980), to, None);
+        assert_eq!(codes(&commands), vec![V2_SWAP_EXACT_IN, UNWRAP_WETH]);
+        assert_eq!(
+            commands[1].input(),
+            encode(&[Token::Address(to), Token::Uint(U256::from(980))])
+        );
+    }
+}
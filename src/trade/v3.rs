@@ -0,0 +1,581 @@
+use crate::database::models::account::Account;
+use crate::database::pool::PgPool;
+use crate::market::market::Market;
+use crate::trade::approval::{Approval, Authorization, SignedPermit, Spender};
+use crate::trade::handler::trade_rpc::{QuoteRequest, SwapMode, SwapRequest};
+use crate::trade::trade::{PairState, Quote, Trade, DEFAULT_DEADLINE};
+use crate::wallet::fee::{gas_limit, Fees};
+use crate::wallet::util::{apply_slippage, convert_to_wei, get_deadline, to_f64};
+use crate::wallet::wallet::Wallet;
+use std::error::Error;
+use web3::contract::{Contract, Options};
+use web3::ethabi::Token;
+use web3::types::{Address, Bytes, CallRequest, TransactionParameters, H256, U256};
+use web3::{transports::WebSocket, Web3};
+
+/// Fee tiers enabled on the Uniswap V3 factory, in hundredths of a bip
+pub const FEE_TIERS: [u32; 4] = [100, 500, 3000, 10000];
+
+/// Router overhead added to the quoter's pool swaps estimate
+/// when the router call itself can't be estimated
+const ROUTER_GAS: u64 = 60_000;
+
+/// Route through Uniswap V3 pools priced by QuoterV2
+#[derive(Clone, Debug)]
+pub struct V3Route {
+    pub tokens: Vec<Address>,
+    /// Pool fee tier of every hop
+    pub fees: Vec<u32>,
+    pub amount_out: U256,
+    /// Quoter's gas estimate of the pool swaps
+    pub gas: U256,
+}
+
+/// Packed `exactInput` path: token (20 bytes) | fee (3 bytes) | token | ...
+pub fn encode_path(tokens: &[Address], fees: &[u32]) -> Vec<u8> {
+    let mut path = Vec::with_capacity(tokens.len() * 20 + fees.len() * 3);
+    for (i, token) in tokens.iter().enumerate() {
+        path.extend_from_slice(token.as_bytes());
+        if let Some(fee) = fees.get(i) {
+            path.extend_from_slice(&fee.to_be_bytes()[1..]);
+        }
+    }
+    path
+}
+
+pub struct TradeV3 {}
+
+impl TradeV3 {
+    /// Uniswap V3 routers are picked by exchange name
+    pub fn supports(exchange: &str) -> bool {
+        exchange.starts_with("UniswapV3Router")
+    }
+
+    /// V3 routers check the amounts priced by the pools, which fee-on-transfer tokens
+    /// don't deliver
+    pub(crate) fn check_transfer_tax(
+        m: &Market,
+        chain: &u32,
+        t0: &Address,
+        t1: &Address,
+    ) -> Result<(), Box<dyn Error>> {
+        if m.transfer_tax(chain, t0) > 0 || m.transfer_tax(chain, t1) > 0 {
+            return Err("Fee-on-transfer tokens can only be swapped on V2 exchanges".into());
+        }
+        Ok(())
+    }
+
+    pub async fn quote(
+        w3: &Web3<WebSocket>,
+        acc: &Account,
+        m: &Market,
+        r: &Address,
+        t0: &Address,
+        t1: &Address,
+        p: &QuoteRequest,
+    ) -> Result<Quote, Box<dyn Error>> {
+        Self::check_transfer_tax(m, &p.chain_id, t0, t1)?;
+        let addr: Address = acc.address.parse()?;
+        let t_abi = Trade::abi(m, "IERC20")?;
+        let t0_cont = Contract::from_json(w3.eth(), *t0, &t_abi)?;
+        let t1_cont = Contract::from_json(w3.eth(), *t1, &t_abi)?;
+        let weth = Self::weth(w3, m, r).await?;
+        let t0_mult = Trade::multiplier(&t0_cont).await?;
+        let t1_mult = Trade::multiplier(&t1_cont).await?;
+
+        let amount_in = convert_to_wei(p.amount.to_owned(), t0_mult)?;
+        let route = Self::best_route(w3, m, &p.chain_id, t0, t1, &weth, amount_in).await?;
+        let amount_out_min = apply_slippage(route.amount_out, p.slippage);
+        let price_impact = Self::price_impact(w3, m, &p.chain_id, &route, amount_in).await?;
+
+        let (data, value) = Self::swap_call(
+            m,
+            &p.exchange,
+            r,
+            &route,
+            &weth,
+            amount_in,
+            amount_out_min,
+            addr,
+            get_deadline(DEFAULT_DEADLINE),
+            None,
+        )?;
+        let gas = w3
+            .eth()
+            .estimate_gas(
+                CallRequest {
+                    from: Some(addr),
+                    to: Some(*r),
+                    value: Some(value),
+                    data: Some(data),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap_or_else(|_| route.gas + U256::from(ROUTER_GAS));
+        let gas_price = Fees::estimate(w3, p.speed().into()).await?.gas_price();
+
+        Ok(Quote {
+            exchange: p.exchange.clone(),
+            amount_out: route.amount_out,
+            amount_out_min,
+            route: route.tokens,
+            fees: route.fees,
+            price_impact,
+            gas,
+            gas_price,
+            t1_mult,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn swap(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        r: &Address,
+        t0: &Address,
+        t1: &Address,
+        p: &SwapRequest,
+    ) -> Result<H256, Box<dyn Error>> {
+        if p.mode() == SwapMode::ExactOutput {
+            return Err("Exact output swaps are supported on V2 routers only".into());
+        }
+        Self::check_transfer_tax(m, &p.chain_id, t0, t1)?;
+        let addr: Address = acc.address.parse()?;
+        let t0_cont = Contract::from_json(w3.eth(), *t0, &Trade::abi(m, "IERC20")?)?;
+        let weth = Self::weth(w3, m, r).await?;
+        let t0_mult = Trade::multiplier(&t0_cont).await?;
+
+        let amount_in = convert_to_wei(p.amount.to_owned(), t0_mult)?;
+        let route = Self::best_route(w3, m, &p.chain_id, t0, t1, &weth, amount_in).await?;
+        let deadline = get_deadline(p.deadline);
+
+        let mut auth = Authorization::Approved;
+        if t0 == &weth {
+            let balance = w3.eth().balance(addr, None).await?;
+            if balance < amount_in {
+                return Err("Insufficient native balance".into());
+            }
+        } else {
+            let t0_balance: U256 = t0_cont
+                .query("balanceOf", addr, None, Options::default(), None)
+                .await?;
+            if t0_balance < amount_in {
+                return Err("Insufficient token0 balance".into());
+            }
+            let spender = Spender::SelfPermit(*r);
+            let speed = p.speed().into();
+            auth = Approval::authorize(
+                w3,
+                db,
+                acc,
+                m,
+                &p.chain_id,
+                &t0_cont,
+                spender,
+                amount_in,
+                deadline,
+                speed,
+            )
+            .await?;
+        }
+        let permit = match &auth {
+            Authorization::Permit(permit) => Some(permit),
+            _ => None,
+        };
+
+        let amount_out_min = apply_slippage(route.amount_out, p.slippage);
+        let (data, value) = Self::swap_call(
+            m,
+            &p.exchange,
+            r,
+            &route,
+            &weth,
+            amount_in,
+            amount_out_min,
+            addr,
+            deadline,
+            permit,
+        )?;
+
+        let fees = Fees::estimate(w3, p.speed().into()).await?;
+        let call = CallRequest {
+            from: Some(addr),
+            to: Some(*r),
+            value: Some(value),
+            data: Some(data.clone()),
+            ..Default::default()
+        };
+        Trade::simulate(w3, &call).await?;
+        let gas = Trade::estimate_gas(w3, call).await?;
+        let mut tx_payload = TransactionParameters {
+            to: Some(*r),
+            gas: gas_limit(gas),
+            value,
+            data,
+            ..Default::default()
+        };
+        fees.apply(&mut tx_payload);
+        Ok(Wallet::send(w3, db, acc, &tx_payload).await?)
+    }
+
+    /// Wrapped native token the router pays native currency with
+    async fn weth(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        r: &Address,
+    ) -> Result<Address, Box<dyn Error>> {
+        let r_cont = Contract::from_json(w3.eth(), *r, &Trade::abi(m, "IPeripheryPayments")?)?;
+        Ok(r_cont
+            .query("WETH9", (), None, Options::default(), None)
+            .await?)
+    }
+
+    pub(crate) fn factory(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+    ) -> Result<Contract<WebSocket>, Box<dyn Error>> {
+        let f_addr = m
+            .try_get_exchange(chain, &"UniswapV3Factory".to_string())
+            .ok_or("No Uniswap V3 factory on chain")?;
+        Ok(Contract::from_json(
+            w3.eth(),
+            f_addr,
+            &Trade::abi(m, "IUniswapV3Factory")?,
+        )?)
+    }
+
+    /// Quote the direct pools and the two-hop routes through WETH on every fee tier
+    /// and keep the route with the largest output
+    pub async fn best_route(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+        t0: &Address,
+        t1: &Address,
+        weth: &Address,
+        amount_in: U256,
+    ) -> Result<V3Route, Box<dyn Error>> {
+        let f_cont = Self::factory(w3, m, chain)?;
+        let q_addr = m
+            .try_get_exchange(chain, &"UniswapV3QuoterV2".to_string())
+            .ok_or("No Uniswap V3 quoter on chain")?;
+        let q_cont = Contract::from_json(w3.eth(), q_addr, &Trade::abi(m, "IQuoterV2")?)?;
+
+        let mut candidates: Vec<(Vec<Address>, Vec<u32>)> = vec![];
+        for fee in Self::pool_fees(&f_cont, t0, t1).await? {
+            candidates.push((vec![*t0, *t1], vec![fee]));
+        }
+        if t0 != weth && t1 != weth {
+            let first = Self::pool_fees(&f_cont, t0, weth).await?;
+            let second = Self::pool_fees(&f_cont, weth, t1).await?;
+            for f0 in &first {
+                for f1 in &second {
+                    candidates.push((vec![*t0, *weth, *t1], vec![*f0, *f1]));
+                }
+            }
+        }
+
+        let mut best: Option<V3Route> = None;
+        for (tokens, fees) in candidates {
+            // Pools without liquidity in range make the quoter revert
+            if let Ok((amount_out, gas)) =
+                Self::quote_path(&q_cont, &tokens, &fees, amount_in).await
+            {
+                let better = match &best {
+                    Some(b) => amount_out > b.amount_out,
+                    None => true,
+                };
+                if better {
+                    best = Some(V3Route {
+                        tokens,
+                        fees,
+                        amount_out,
+                        gas,
+                    });
+                }
+            }
+        }
+        best.ok_or_else(|| "No Uniswap V3 pool for token pair".into())
+    }
+
+    /// Fee tiers which have a deployed pool for the token pair
+    async fn pool_fees(
+        f_cont: &Contract<WebSocket>,
+        a: &Address,
+        b: &Address,
+    ) -> Result<Vec<u32>, Box<dyn Error>> {
+        let mut fees = vec![];
+        for fee in FEE_TIERS {
+            let pool: Address = f_cont
+                .query(
+                    "getPool",
+                    (*a, *b, U256::from(fee)),
+                    None,
+                    Options::default(),
+                    None,
+                )
+                .await?;
+            if !pool.is_zero() {
+                fees.push(fee);
+            }
+        }
+        Ok(fees)
+    }
+
+    /// Output amount and gas estimate of an exact input swap from QuoterV2
+    async fn quote_path(
+        q_cont: &Contract<WebSocket>,
+        tokens: &[Address],
+        fees: &[u32],
+        amount_in: U256,
+    ) -> Result<(U256, U256), Box<dyn Error>> {
+        if tokens.len() == 2 {
+            let params = Token::Tuple(vec![
+                Token::Address(tokens[0]),
+                Token::Address(tokens[1]),
+                Token::Uint(amount_in),
+                Token::Uint(U256::from(fees[0])),
+                Token::Uint(U256::zero()),
+            ]);
+            let (amount_out, _, _, gas): (U256, U256, U256, U256) = q_cont
+                .query(
+                    "quoteExactInputSingle",
+                    (params,),
+                    None,
+                    Options::default(),
+                    None,
+                )
+                .await?;
+            Ok((amount_out, gas))
+        } else {
+            let (amount_out, _, _, gas): (U256, Vec<U256>, Vec<U256>, U256) = q_cont
+                .query(
+                    "quoteExactInput",
+                    (Bytes(encode_path(tokens, fees)), amount_in),
+                    None,
+                    Options::default(),
+                    None,
+                )
+                .await?;
+            Ok((amount_out, gas))
+        }
+    }
+
+    /// Pool of the factory for the tokens on the fee tier, or on the tier with the most
+    /// in-range liquidity when `fee` is 0
+    pub async fn pool(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+        ta: &Address,
+        tb: &Address,
+        fee: u32,
+    ) -> Result<PairState, Box<dyn Error>> {
+        let f_cont = Self::factory(w3, m, chain)?;
+        let p_abi = Trade::abi(m, "IUniswapV3Pool")?;
+        let fees = if fee == 0 {
+            Self::pool_fees(&f_cont, ta, tb).await?
+        } else {
+            vec![fee]
+        };
+
+        let mut best: Option<(Address, u32, U256)> = None;
+        for fee in fees {
+            let pool: Address = f_cont
+                .query(
+                    "getPool",
+                    (*ta, *tb, U256::from(fee)),
+                    None,
+                    Options::default(),
+                    None,
+                )
+                .await?;
+            if pool.is_zero() {
+                continue;
+            }
+            let p_cont = Contract::from_json(w3.eth(), pool, &p_abi)?;
+            let liquidity: U256 = p_cont
+                .query("liquidity", (), None, Options::default(), None)
+                .await?;
+            if best.is_none_or(|(_, _, l)| liquidity > l) {
+                best = Some((pool, fee, liquidity));
+            }
+        }
+        let (pool, fee, liquidity) = best.ok_or("No Uniswap V3 pool for token pair")?;
+
+        let p_cont = Contract::from_json(w3.eth(), pool, &p_abi)?;
+        let token0: Address = p_cont
+            .query("token0", (), None, Options::default(), None)
+            .await?;
+        let token1 = if &token0 == ta { *tb } else { *ta };
+        let (sqrt_price_x96, tick, ..): (U256, i32, Token, Token, Token, Token, Token) = p_cont
+            .query("slot0", (), None, Options::default(), None)
+            .await?;
+        let t_abi = Trade::abi(m, "IERC20")?;
+        let t0_cont = Contract::from_json(w3.eth(), token0, &t_abi)?;
+        let t1_cont = Contract::from_json(w3.eth(), token1, &t_abi)?;
+        let reserve0: U256 = t0_cont
+            .query("balanceOf", pool, None, Options::default(), None)
+            .await?;
+        let reserve1: U256 = t1_cont
+            .query("balanceOf", pool, None, Options::default(), None)
+            .await?;
+        let t0_mult = Trade::multiplier(&t0_cont).await?;
+        let t1_mult = Trade::multiplier(&t1_cont).await?;
+        Ok(PairState {
+            pair: pool,
+            token0,
+            token1,
+            reserve0,
+            reserve1,
+            sqrt_price_x96,
+            tick,
+            liquidity,
+            fee,
+            total_supply: U256::zero(),
+            t0_mult,
+            t1_mult,
+        })
+    }
+
+    /// Price impact of a V3 route: output compared with the fee-adjusted mid price
+    /// taken from `slot0` of every pool along the route
+    pub(crate) async fn price_impact(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+        route: &V3Route,
+        amount_in: U256,
+    ) -> Result<f64, Box<dyn Error>> {
+        let f_cont = Self::factory(w3, m, chain)?;
+        let p_abi = Trade::abi(m, "IUniswapV3Pool")?;
+
+        let mut mid_out = to_f64(amount_in);
+        for (i, fee) in route.fees.iter().enumerate() {
+            let (a, b) = (route.tokens[i], route.tokens[i + 1]);
+            let pool: Address = f_cont
+                .query(
+                    "getPool",
+                    (a, b, U256::from(*fee)),
+                    None,
+                    Options::default(),
+                    None,
+                )
+                .await?;
+            let p_cont = Contract::from_json(w3.eth(), pool, &p_abi)?;
+            let (sqrt_price, ..): (U256, Token, Token, Token, Token, Token, Token) = p_cont
+                .query("slot0", (), None, Options::default(), None)
+                .await?;
+            // sqrtPriceX96 is sqrt(token1 / token0) in Q64.96, token0 is the lower address
+            let sqrt = to_f64(sqrt_price) / 2f64.powi(96);
+            let price = sqrt * sqrt;
+            let rate = if a < b { price } else { 1.0 / price };
+            mid_out *= rate * (1.0 - *fee as f64 / 1e6);
+        }
+
+        if mid_out == 0.0 || !mid_out.is_finite() {
+            return Ok(0.0);
+        }
+        Ok((1.0 - to_f64(route.amount_out) / mid_out).max(0.0))
+    }
+
+    /// Router calldata and value of an exact input swap along the route.
+    ///
+    /// `UniswapV3Router01` takes the deadline in the swap params, `UniswapV3Router02`
+    /// takes it in `multicall`. Wrapped native output is unwrapped to `to` in the same call,
+    /// a permit of the input token is redeemed with `selfPermit` before the swap.
+    #[allow(clippy::too_many_arguments)]
+    fn swap_call(
+        m: &Market,
+        exchange: &str,
+        r: &Address,
+        route: &V3Route,
+        weth: &Address,
+        amount_in: U256,
+        amount_out_min: U256,
+        to: Address,
+        deadline: U256,
+        permit: Option<&SignedPermit>,
+    ) -> Result<(Bytes, U256), Box<dyn Error>> {
+        let legacy = exchange == "UniswapV3Router01";
+        let single = route.tokens.len() == 2;
+        let eth_out = route.tokens.last() == Some(weth);
+        let recipient = if eth_out { *r } else { to };
+
+        let (swap_fn, mut params) = if single {
+            (
+                "exactInputSingle",
+                vec![
+                    Token::Address(route.tokens[0]),
+                    Token::Address(route.tokens[1]),
+                    Token::Uint(U256::from(route.fees[0])),
+                    Token::Address(recipient),
+                    Token::Uint(amount_in),
+                    Token::Uint(amount_out_min),
+                    Token::Uint(U256::zero()),
+                ],
+            )
+        } else {
+            (
+                "exactInput",
+                vec![
+                    Token::Bytes(encode_path(&route.tokens, &route.fees)),
+                    Token::Address(recipient),
+                    Token::Uint(amount_in),
+                    Token::Uint(amount_out_min),
+                ],
+            )
+        };
+        let r_abi = if legacy {
+            // ISwapRouter params carry the deadline right after the recipient
+            params.insert(if single { 4 } else { 2 }, Token::Uint(deadline));
+            "ISwapRouter"
+        } else {
+            "IV3SwapRouter"
+        };
+
+        let r_abi = web3::ethabi::Contract::load(Trade::abi(m, r_abi)?.as_slice())?;
+        let mut calls = vec![r_abi
+            .function(swap_fn)?
+            .encode_input(&[Token::Tuple(params)])?];
+        if let Some(permit) = permit {
+            calls.insert(0, permit.self_permit_call(m)?);
+        }
+        if eth_out {
+            let pay_abi =
+                web3::ethabi::Contract::load(Trade::abi(m, "IPeripheryPayments")?.as_slice())?;
+            calls.push(
+                pay_abi
+                    .function("unwrapWETH9")?
+                    .encode_input(&[Token::Uint(amount_out_min), Token::Address(to)])?,
+            );
+        }
+
+        let value = if route.tokens.first() == Some(weth) {
+            amount_in
+        } else {
+            U256::zero()
+        };
+        if legacy && calls.len() == 1 {
+            return Ok((Bytes(calls.remove(0)), value));
+        }
+
+        let mc_abi = web3::ethabi::Contract::load(Trade::abi(m, "IMulticall")?.as_slice())?;
+        let data = Token::Array(calls.into_iter().map(Token::Bytes).collect());
+        let mut inputs = vec![data];
+        if !legacy {
+            inputs.insert(0, Token::Uint(deadline));
+        }
+        let multicall = mc_abi
+            .functions_by_name("multicall")?
+            .iter()
+            .find(|f| f.inputs.len() == inputs.len())
+            .ok_or("No multicall overload")?;
+        Ok((Bytes(multicall.encode_input(&inputs)?), value))
+    }
+}
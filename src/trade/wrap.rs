@@ -0,0 +1,125 @@
+use crate::database::models::account::Account;
+use crate::database::pool::PgPool;
+use crate::market::market::Market;
+use crate::trade::trade::Trade;
+use crate::wallet::fee::{gas_limit, Fees, Speed};
+use crate::wallet::util::convert_to_wei;
+use crate::wallet::wallet::Wallet;
+use std::error::Error;
+use web3::contract::{tokens::Tokenize, Contract, Options};
+use web3::types::{Address, Bytes, TransactionParameters, H256, U256};
+use web3::{transports::WebSocket, Web3};
+
+/// Native currency and its wrapped token both have 18 decimals
+const NATIVE_MULT: f32 = 1e18;
+
+/// Conversions between the native currency and the chain's wrapped-native token
+pub struct Wrap {}
+
+impl Wrap {
+    /// Turn `amount` of native currency into the wrapped token with `deposit`
+    pub async fn wrap(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        chain: &u32,
+        amount: f32,
+        speed: Speed,
+    ) -> Result<H256, Box<dyn Error>> {
+        let addr: Address = acc.address.parse()?;
+        let w_cont = Self::contract(w3, m, chain)?;
+        let amount = convert_to_wei(amount, NATIVE_MULT)?;
+        let balance = w3.eth().balance(addr, None).await?;
+        if balance < amount {
+            return Err("Insufficient native balance".into());
+        }
+        Self::send(w3, db, acc, &w_cont, "deposit", (), amount, speed).await
+    }
+
+    /// Turn `amount` of the wrapped token back into native currency with `withdraw`
+    pub async fn unwrap(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        m: &Market,
+        chain: &u32,
+        amount: f32,
+        speed: Speed,
+    ) -> Result<H256, Box<dyn Error>> {
+        let addr: Address = acc.address.parse()?;
+        let w_cont = Self::contract(w3, m, chain)?;
+        let amount = convert_to_wei(amount, NATIVE_MULT)?;
+        let balance: U256 = w_cont
+            .query("balanceOf", addr, None, Options::default(), None)
+            .await?;
+        if balance < amount {
+            return Err("Insufficient wrapped native balance".into());
+        }
+        Self::send(
+            w3,
+            db,
+            acc,
+            &w_cont,
+            "withdraw",
+            amount,
+            U256::zero(),
+            speed,
+        )
+        .await
+    }
+
+    fn contract(
+        w3: &Web3<WebSocket>,
+        m: &Market,
+        chain: &u32,
+    ) -> Result<Contract<WebSocket>, Box<dyn Error>> {
+        let weth = m
+            .try_get_wrapped_native(chain)
+            .ok_or("No wrapped native token on chain")?;
+        Ok(Contract::from_json(
+            w3.eth(),
+            weth,
+            &Trade::abi(m, "IWETH")?,
+        )?)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn send<P: Tokenize + Clone>(
+        w3: &Web3<WebSocket>,
+        db: &PgPool,
+        acc: &Account,
+        w_cont: &Contract<WebSocket>,
+        func: &str,
+        params: P,
+        value: U256,
+        speed: Speed,
+    ) -> Result<H256, Box<dyn Error>> {
+        let addr: Address = acc.address.parse()?;
+        let fees = Fees::estimate(w3, speed).await?;
+        let data = w_cont
+            .abi()
+            .function(func)?
+            .encode_input(&params.clone().into_tokens())?;
+        let gas_estimate = w_cont
+            .estimate_gas(
+                func,
+                params,
+                addr,
+                Options {
+                    value: Some(value),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let mut tx_payload = TransactionParameters {
+            to: Some(w_cont.address()),
+            gas: gas_limit(gas_estimate),
+            value,
+            data: Bytes(data),
+            ..Default::default()
+        };
+        fees.apply(&mut tx_payload);
+        Ok(Wallet::send(w3, db, acc, &tx_payload).await?)
+    }
+}
@@ -1,5 +1,6 @@
 //! # Core domain logic module errors
 
+use super::util::RLPError;
 use hex;
 use secp256k1;
 use std::{error, fmt};
@@ -9,7 +10,6 @@ use std::{error, fmt};
 pub enum Error {
     // Invalid ABI
     //InvalidABI(String),
-
     /// An invalid length
     InvalidLength(usize),
 
@@ -21,6 +21,15 @@ pub enum Error {
 
     /// ECDSA crypto error
     EcdsaCrypto(secp256k1::Error),
+
+    /// A transaction field required for signing is not set
+    MissingField(&'static str),
+
+    /// An unknown transaction envelope type
+    UnsupportedTxType(u64),
+
+    /// Raw transaction isn't valid RLP or misses fields
+    InvalidRlp(RLPError),
 }
 
 impl From<hex::FromHexError> for Error {
@@ -29,6 +38,12 @@ impl From<hex::FromHexError> for Error {
     }
 }
 
+impl From<RLPError> for Error {
+    fn from(err: RLPError) -> Self {
+        Error::InvalidRlp(err)
+    }
+}
+
 impl From<secp256k1::Error> for Error {
     fn from(err: secp256k1::Error) -> Self {
         Error::EcdsaCrypto(err)
@@ -45,6 +60,9 @@ impl fmt::Display for Error {
                 write!(f, "Unexpected hexadecimal encoding: {}", err)
             }
             Error::EcdsaCrypto(ref err) => write!(f, "ECDSA crypto error: {}", err),
+            Error::MissingField(name) => write!(f, "Missing transaction field: {}", name),
+            Error::UnsupportedTxType(t) => write!(f, "Unsupported transaction type: {}", t),
+            Error::InvalidRlp(ref err) => write!(f, "Invalid RLP: {}", err),
         }
     }
 }
@@ -58,6 +76,7 @@ impl error::Error for Error {
         match *self {
             Error::UnexpectedHexEncoding(ref err) => Some(err),
             Error::EcdsaCrypto(ref err) => Some(err),
+            Error::InvalidRlp(ref err) => Some(err),
             _ => None,
         }
     }
@@ -6,24 +6,29 @@ use super::Error;
 use hex;
 use rand::rngs::OsRng;
 use rand::Rng;
-use secp256k1::{Message, PublicKey, Secp256k1, SecretKey, SignOnly};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey, SignOnly, VerifyOnly};
 use std::{fmt, ops, str};
-use web3::signing::keccak256;
+use web3::signing::{keccak256, Key, Signature as Web3Signature, SigningError};
+use web3::types::{H160, H256};
 
 pub const KECCAK256_BYTES: usize = 32;
 pub const PRIVATE_KEY_BYTES: usize = 32;
-pub const ECDSA_SIGNATURE_BYTES: usize = 64;
+/// Recovery id followed by the `r` and `s` points
+pub const ECDSA_SIGNATURE_BYTES: usize = 65;
 
 // Create a new Secp256k1 context with the specified capabilities
 
 lazy_static! {
     static ref ECDSA: Secp256k1<SignOnly> = Secp256k1::signing_only();
+    static ref ECDSA_VERIFY: Secp256k1<VerifyOnly> = Secp256k1::verification_only();
 }
 
 /// Transaction sign data (see Appendix F. "Signing Transactions" from Yellow Paper)
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Signature {
-    /// ‘recovery id’, a 1 byte value specifying the sign and finiteness of the curve point
+    /// ‘recovery id’ in Electrum notation (27 or 28),
+    /// specifying the sign and finiteness of the curve point
     pub v: u8,
 
     /// ECDSA signature first point (0 < r < secp256k1n)
@@ -45,6 +50,22 @@ impl From<[u8; ECDSA_SIGNATURE_BYTES]> for Signature {
     }
 }
 
+impl Signature {
+    /// Recover the `Address` which signed the given hash
+    pub fn recover(&self, hash: [u8; KECCAK256_BYTES]) -> Result<Address, Error> {
+        let msg = Message::from_slice(&hash)?;
+        let rid = RecoveryId::from_i32(i32::from(self.v) - 27)?;
+        let mut compact = [0u8; 64];
+        compact[..32].copy_from_slice(&self.r);
+        compact[32..].copy_from_slice(&self.s);
+        let sig = RecoverableSignature::from_compact(&compact, rid)?;
+
+        let key = ECDSA_VERIFY.recover_ecdsa(&msg, &sig)?;
+        let hash = keccak256(&key.serialize_uncompressed()[1..] /* cut '04' */);
+        Ok(Address(to_arr(&hash[12..])))
+    }
+}
+
 impl Into<(u8, [u8; 32], [u8; 32])> for Signature {
     fn into(self) -> (u8, [u8; 32], [u8; 32]) {
         (self.v, self.r, self.s)
@@ -121,14 +142,60 @@ impl PrivateKey {
         let msg = Message::from_slice(&hash)?;
         let key = SecretKey::from_slice(&self)?;
 
-        let s = ECDSA.sign_ecdsa(&msg, &key);
-        let ret = s.serialize_compact();
+        let (rid, sig) = ECDSA.sign_ecdsa_recoverable(&msg, &key).serialize_compact();
+
+        let mut buf = [0u8; ECDSA_SIGNATURE_BYTES];
+        buf[0] = (rid.to_i32() + 27) as u8;
+        buf[1..65].copy_from_slice(&sig[0..64]);
+
+        Ok(Signature::from(buf))
+    }
+}
+
+/// Lets the decrypted key sign `web3::types::TransactionParameters` directly
+/// through `Accounts::sign_transaction`
+impl Key for PrivateKey {
+    fn sign(&self, message: &[u8], chain_id: Option<u64>) -> Result<Web3Signature, SigningError> {
+        let (rid, sig) = self.sign_recoverable(message)?;
 
-        // let mut buf = [0u8; ECDSA_SIGNATURE_BYTES];
-        // buf[0] = (rid.to_i32() + 27) as u8;
-        // buf[1..65].copy_from_slice(&sig[0..64]);
+        // [Simple replay attack protection](https://github.com/ethereum/eips/issues/155)
+        let v = match chain_id {
+            Some(id) => rid + 35 + id * 2,
+            None => rid + 27,
+        };
+
+        Ok(Web3Signature {
+            v,
+            r: H256::from_slice(&sig[..32]),
+            s: H256::from_slice(&sig[32..]),
+        })
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Result<Web3Signature, SigningError> {
+        let (rid, sig) = self.sign_recoverable(message)?;
+
+        Ok(Web3Signature {
+            v: rid,
+            r: H256::from_slice(&sig[..32]),
+            s: H256::from_slice(&sig[32..]),
+        })
+    }
+
+    fn address(&self) -> H160 {
+        let key = PublicKey::from_secret_key(&ECDSA, &(*self).into());
+        let hash = keccak256(&key.serialize_uncompressed()[1..] /* cut '04' */);
+        H160::from_slice(&hash[12..])
+    }
+}
+
+impl PrivateKey {
+    /// Sign a 32 bytes hash keeping the recovery id
+    fn sign_recoverable(&self, hash: &[u8]) -> Result<(u64, [u8; 64]), SigningError> {
+        let msg = Message::from_slice(hash).map_err(|_| SigningError::InvalidMessage)?;
+        let key = SecretKey::from_slice(self).map_err(|_| SigningError::InvalidMessage)?;
+        let (rid, sig) = ECDSA.sign_ecdsa_recoverable(&msg, &key).serialize_compact();
 
-        Ok(Signature::from(ret))
+        Ok((rid.to_i32() as u64, sig))
     }
 }
 
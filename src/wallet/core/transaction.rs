@@ -1,20 +1,62 @@
 //! # Account transaction
 
-use super::util::{trim_bytes, RLPList, WriteRLP};
+use super::util::{to_arr, trim_bytes, RLPError, RLPItem, RLPList, WriteRLP};
 use super::{Address, Error, PrivateKey, Signature};
 use web3::signing::keccak256;
+use web3::types::TransactionParameters;
 
 pub const KECCAK256_BYTES: usize = 32;
 
+/// Transaction envelope type ([EIP-2718](https://eips.ethereum.org/EIPS/eip-2718))
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TxType {
+    /// Untyped transaction with EIP-155 replay protection
+    #[default]
+    Legacy,
+
+    /// [EIP-2930](https://eips.ethereum.org/EIPS/eip-2930) transaction with an access list
+    AccessList,
+
+    /// [EIP-1559](https://eips.ethereum.org/EIPS/eip-1559) transaction with a dynamic fee
+    DynamicFee,
+}
+
+impl TxType {
+    /// Envelope type byte, `None` for legacy transactions
+    pub fn id(&self) -> Option<u8> {
+        match *self {
+            TxType::Legacy => None,
+            TxType::AccessList => Some(1),
+            TxType::DynamicFee => Some(2),
+        }
+    }
+}
+
+/// Contract storage slots a transaction plans to access
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessListItem {
+    /// Contract address
+    pub address: Address,
+
+    /// Storage keys
+    pub storage_keys: Vec<[u8; 32]>,
+}
+
 /// Transaction data
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Transaction {
+    /// Envelope type
+    pub tx_type: TxType,
+
     /// Nonce
     pub nonce: u64,
 
-    /// Gas Price
+    /// Gas Price, or max fee per gas for dynamic fee transactions
     pub gas_price: [u8; 32],
 
+    /// Max priority fee per gas, for dynamic fee transactions only
+    pub max_priority_fee_per_gas: [u8; 32],
+
     /// Gas Limit
     pub gas_limit: u64,
 
@@ -26,53 +68,211 @@ pub struct Transaction {
 
     /// Data transferred with transaction
     pub data: Vec<u8>,
+
+    /// Access list, for typed transactions only
+    pub access_list: Vec<AccessListItem>,
+}
+
+/// Signed transaction parsed from its raw encoding
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedTransaction {
+    /// Transaction data
+    pub tx: Transaction,
+
+    /// Chain id, or None for legacy transactions without replay protection
+    pub chain_id: Option<u64>,
+
+    /// Signature, `v` in Electrum notation
+    pub signature: Signature,
+
+    /// Sender recovered from the signature
+    pub from: Address,
+
+    /// Transaction hash
+    pub hash: [u8; KECCAK256_BYTES],
 }
 
 impl Transaction {
+    /// Parse a signed raw transaction, legacy or typed, and recover its sender
+    pub fn from_signed_raw(raw: &[u8]) -> Result<SignedTransaction, Error> {
+        let first = *raw.first().ok_or(RLPError::UnexpectedEnd)?;
+        let (tx_type, payload) = match first {
+            0x01 => (TxType::AccessList, &raw[1..]),
+            0x02 => (TxType::DynamicFee, &raw[1..]),
+            0x00..=0x7f => return Err(Error::UnsupportedTxType(u64::from(first))),
+            _ => (TxType::Legacy, raw),
+        };
+        let item = RLPItem::decode(payload)?;
+        let fields = item.as_list()?;
+        let expected = match tx_type {
+            TxType::Legacy => 9,
+            TxType::AccessList => 11,
+            TxType::DynamicFee => 12,
+        };
+        if fields.len() != expected {
+            return Err(Error::InvalidLength(fields.len()));
+        }
+
+        let mut tx = Transaction {
+            tx_type,
+            ..Default::default()
+        };
+        let mut i = 0;
+        let typed_chain = match tx_type {
+            TxType::Legacy => None,
+            _ => {
+                i += 1;
+                Some(fields[0].as_u64()?)
+            }
+        };
+        tx.nonce = fields[i].as_u64()?;
+        i += 1;
+        if tx_type == TxType::DynamicFee {
+            tx.max_priority_fee_per_gas = fields[i].as_u256()?;
+            i += 1;
+        }
+        tx.gas_price = fields[i].as_u256()?;
+        tx.gas_limit = fields[i + 1].as_u64()?;
+        tx.to = match fields[i + 2].as_bytes()? {
+            [] => None,
+            addr => Some(Address::try_from(addr)?),
+        };
+        tx.value = fields[i + 3].as_u256()?;
+        tx.data = fields[i + 4].as_bytes()?.to_vec();
+        i += 5;
+        if tx_type != TxType::Legacy {
+            tx.access_list = read_access_list(&fields[i])?;
+            i += 1;
+        }
+
+        let v = fields[i].as_u64()?;
+        let (chain_id, parity) = match typed_chain {
+            Some(chain) => (Some(chain), v),
+            None if v == 27 || v == 28 => (None, v - 27),
+            None if v >= 35 => (Some((v - 35) / 2), (v - 35) % 2),
+            None => return Err(secp256k1::Error::InvalidRecoveryId.into()),
+        };
+        if parity > 1 {
+            return Err(secp256k1::Error::InvalidRecoveryId.into());
+        }
+        let signature = Signature {
+            v: parity as u8 + 27,
+            r: fields[i + 1].as_u256()?,
+            s: fields[i + 2].as_u256()?,
+        };
+        let from = signature.recover(keccak256(&tx.to_rlp(chain_id)))?;
+
+        Ok(SignedTransaction {
+            tx,
+            chain_id,
+            signature,
+            from,
+            hash: keccak256(raw),
+        })
+    }
+
     /// Sign transaction data with provided private key
-    pub fn to_signed_raw(&self, pk: PrivateKey, chain: u8) -> Result<Vec<u8>, Error> {
+    pub fn to_signed_raw(&self, pk: PrivateKey, chain: u64) -> Result<Vec<u8>, Error> {
         let sig = pk.sign_hash(self.hash(chain))?;
         Ok(self.raw_from_sig(chain, &sig))
     }
 
-    /// RLP packed signed transaction from provided `Signature`
-    pub fn raw_from_sig(&self, chain: u8, sig: &Signature) -> Vec<u8> {
-        let mut rlp = self.to_rlp_raw(None);
+    /// RLP packed signed transaction from provided `Signature`,
+    /// wrapped in its type envelope for typed transactions
+    pub fn raw_from_sig(&self, chain: u64, sig: &Signature) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self.tx_type.id() {
+            None => {
+                let mut rlp = self.to_rlp_raw(None);
 
-        // [Simple replay attack protection](https://github.com/ethereum/eips/issues/155)
-        // Can be already applied by HD wallet.
-        // TODO: refactor to avoid this check
-        let mut v = u16::from(sig.v);
-        let stamp = u16::from(chain * 2 + 35 - 27);
-        if v + stamp <= 0xff {
-            v += stamp;
-        }
+                // [Simple replay attack protection](https://github.com/ethereum/eips/issues/155)
+                let v = u64::from(sig.v) - 27 + chain * 2 + 35;
 
-        rlp.push(&(v as u8));
-        rlp.push(&sig.r[..]);
-        rlp.push(&sig.s[..]);
+                rlp.push(&v);
+                rlp.push(trim_bytes(&sig.r));
+                rlp.push(trim_bytes(&sig.s));
+                rlp.write_rlp(&mut buf);
+            }
+            Some(id) => {
+                let mut rlp = self.to_typed_rlp_raw(chain);
 
-        let mut buf = Vec::new();
-        rlp.write_rlp(&mut buf);
+                // Typed transactions sign with the bare y parity
+                rlp.push(&(sig.v - 27));
+                rlp.push(trim_bytes(&sig.r));
+                rlp.push(trim_bytes(&sig.s));
+                buf.push(id);
+                rlp.write_rlp(&mut buf);
+            }
+        }
 
         buf
     }
 
-    /// RLP packed transaction
-    pub fn to_rlp(&self, chain_id: Option<u8>) -> Vec<u8> {
+    /// RLP packed transaction, the signing payload when `chain_id` is provided.
+    ///
+    /// Typed transactions always carry their chain id, a missing one is encoded as zero.
+    pub fn to_rlp(&self, chain_id: Option<u64>) -> Vec<u8> {
         let mut buf = Vec::new();
-        self.to_rlp_raw(chain_id).write_rlp(&mut buf);
+        match self.tx_type.id() {
+            None => self.to_rlp_raw(chain_id).write_rlp(&mut buf),
+            Some(id) => {
+                buf.push(id);
+                self.to_typed_rlp_raw(chain_id.unwrap_or_default())
+                    .write_rlp(&mut buf);
+            }
+        }
 
         buf
     }
 
-    fn to_rlp_raw(&self, chain_id: Option<u8>) -> RLPList {
+    fn to_rlp_raw(&self, chain_id: Option<u64>) -> RLPList {
+        let mut data = RLPList::default();
+
+        data.push(&self.nonce);
+        data.push(trim_bytes(&self.gas_price));
+        data.push(&self.gas_limit);
+        self.push_call(&mut data);
+
+        if let Some(id) = chain_id {
+            data.push(&id);
+            data.push(&[][..]);
+            data.push(&[][..]);
+        }
+
+        data
+    }
+
+    /// Unsigned fields of a typed transaction
+    fn to_typed_rlp_raw(&self, chain: u64) -> RLPList {
         let mut data = RLPList::default();
 
+        data.push(&chain);
         data.push(&self.nonce);
+        if self.tx_type == TxType::DynamicFee {
+            data.push(trim_bytes(&self.max_priority_fee_per_gas));
+        }
         data.push(trim_bytes(&self.gas_price));
         data.push(&self.gas_limit);
+        self.push_call(&mut data);
 
+        let mut access_list = RLPList::default();
+        for item in &self.access_list {
+            let mut keys = RLPList::default();
+            for key in &item.storage_keys {
+                keys.push(&key[..]);
+            }
+            let mut entry = RLPList::default();
+            entry.push(&item.address[..]);
+            entry.push(&keys);
+            access_list.push(&entry);
+        }
+        data.push(&access_list);
+
+        data
+    }
+
+    /// Target, value and data fields
+    fn push_call(&self, data: &mut RLPList) {
         match self.to {
             Some(addr) => data.push(&Some(&addr[..])),
             _ => data.push::<Option<&[u8]>>(&None),
@@ -80,21 +280,273 @@ impl Transaction {
 
         data.push(trim_bytes(&self.value));
         data.push(self.data.as_slice());
+    }
 
-        if let Some(id) = chain_id {
-            data.push(&id);
-            data.push(&[][..]);
-            data.push(&[][..]);
+    fn hash(&self, chain: u64) -> [u8; KECCAK256_BYTES] {
+        keccak256(&self.to_rlp(Some(chain)))
+    }
+}
+
+/// Access list items from their `[address, [storage keys]]` RLP encoding
+fn read_access_list(item: &RLPItem) -> Result<Vec<AccessListItem>, Error> {
+    let mut access_list = Vec::new();
+    for entry in item.as_list()? {
+        let entry = entry.as_list()?;
+        if entry.len() != 2 {
+            return Err(Error::InvalidLength(entry.len()));
         }
+        let mut storage_keys = Vec::new();
+        for key in entry[1].as_list()? {
+            let key = key.as_bytes()?;
+            if key.len() != 32 {
+                return Err(Error::InvalidLength(key.len()));
+            }
+            storage_keys.push(to_arr(key));
+        }
+        access_list.push(AccessListItem {
+            address: Address::try_from(entry[0].as_bytes()?)?,
+            storage_keys,
+        });
+    }
+    Ok(access_list)
+}
 
-        data
+/// Converts the web3 parameters built by the trade module,
+/// `nonce` and the fee of the transaction type must be set
+impl TryFrom<&TransactionParameters> for Transaction {
+    type Error = Error;
+
+    fn try_from(tx: &TransactionParameters) -> Result<Self, Self::Error> {
+        let tx_type = match tx.transaction_type.map(|t| t.as_u64()) {
+            None | Some(0) => TxType::Legacy,
+            Some(1) => TxType::AccessList,
+            Some(2) => TxType::DynamicFee,
+            Some(t) => return Err(Error::UnsupportedTxType(t)),
+        };
+        let nonce = tx.nonce.ok_or(Error::MissingField("nonce"))?;
+        let (gas_price, max_priority_fee_per_gas) = match tx_type {
+            TxType::DynamicFee => {
+                let max_fee = tx
+                    .max_fee_per_gas
+                    .ok_or(Error::MissingField("max_fee_per_gas"))?;
+                (max_fee, tx.max_priority_fee_per_gas.unwrap_or(max_fee))
+            }
+            _ => (
+                tx.gas_price.ok_or(Error::MissingField("gas_price"))?,
+                Default::default(),
+            ),
+        };
+        if nonce.bits() > 64 || tx.gas.bits() > 64 {
+            return Err(Error::InvalidLength(32));
+        }
+
+        let mut tx_out = Transaction {
+            tx_type,
+            nonce: nonce.as_u64(),
+            gas_limit: tx.gas.as_u64(),
+            to: tx.to.map(|addr| Address(addr.0)),
+            data: tx.data.0.clone(),
+            ..Default::default()
+        };
+        gas_price.to_big_endian(&mut tx_out.gas_price);
+        max_priority_fee_per_gas.to_big_endian(&mut tx_out.max_priority_fee_per_gas);
+        tx.value.to_big_endian(&mut tx_out.value);
+        if tx_type != TxType::Legacy {
+            tx_out.access_list = tx
+                .access_list
+                .iter()
+                .flatten()
+                .map(|item| AccessListItem {
+                    address: Address(item.address.0),
+                    storage_keys: item.storage_keys.iter().map(|key| key.0).collect(),
+                })
+                .collect();
+        }
+
+        Ok(tx_out)
     }
+}
 
-    fn hash(&self, chain: u8) -> [u8; KECCAK256_BYTES] {
-        let rlp = self.to_rlp_raw(Some(chain));
-        let mut vec = Vec::new();
-        rlp.write_rlp(&mut vec);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        keccak256(&vec)
+    /// Transaction from the [EIP-155 example](https://eips.ethereum.org/EIPS/eip-155)
+    fn eip155_tx() -> (Transaction, PrivateKey) {
+        let mut gas_price = [0u8; 32];
+        gas_price[24..].copy_from_slice(&20_000_000_000u64.to_be_bytes());
+        let mut value = [0u8; 32];
+        value[24..].copy_from_slice(&1_000_000_000_000_000_000u64.to_be_bytes());
+
+        let tx = Transaction {
+            nonce: 9,
+            gas_price,
+            gas_limit: 21000,
+            to: Some(Address([0x35; 20])),
+            value,
+            data: vec![],
+            ..Default::default()
+        };
+        (tx, PrivateKey([0x46; 32]))
+    }
+
+    fn signed_hex(chain: u64) -> String {
+        let (tx, pk) = eip155_tx();
+        hex::encode(tx.to_signed_raw(pk, chain).unwrap())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn signs_mainnet_transaction() {
+        assert_eq!(
+            signed_hex(1),
+            "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             8025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f\
+             761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+    }
+
+    #[test]
+    fn signs_polygon_transaction() {
+        // v = 137 * 2 + 35 + 1 doesn't fit in a byte
+        assert_eq!(
+            signed_hex(137),
+            "f86e098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             80820136a00d02a2ce7ed82574448f5581c0c45a2eb0b6e2ccf6971eff1dae61b6bb1cec81a00ce65a78\
+             4c4aa7fe79935ec1fadee65e7a0238186fffd3848275dc275b26ccf1"
+        );
+    }
+
+    #[test]
+    fn signs_polygon_testnet_transaction() {
+        assert_eq!(
+            signed_hex(80001),
+            "f86f098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             8083027126a0b46033b09db95baf47f393acd01667cf9ffba0bd7752fcb3499a9efe24a48e46a006095c\
+             4c0acb4487364a0978d595c05f921e83f522be4c56abba77f4bc2e599c"
+        );
+    }
+
+    #[test]
+    fn signs_bsc_transaction() {
+        assert_eq!(
+            signed_hex(56),
+            "f86d098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             808194a007764d8e7d4ce45afb36cb2f5bec741f96eee3b8f23304e4d35b0f389912e7f7a047c14849f3\
+             2c33c269dec152d168ddeca152fec8ceeb7369177ff3a06dc72c48"
+        );
+    }
+
+    #[test]
+    fn signature_recovers_signer() {
+        use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+        use secp256k1::{Message, Secp256k1};
+
+        let (tx, pk) = eip155_tx();
+        let hash = tx.hash(137);
+        let sig = pk.sign_hash(hash).unwrap();
+        assert!(sig.v == 27 || sig.v == 28);
+
+        let mut compact = [0u8; 64];
+        compact[..32].copy_from_slice(&sig.r);
+        compact[32..].copy_from_slice(&sig.s);
+        let rid = RecoveryId::from_i32(i32::from(sig.v) - 27).unwrap();
+        let rec = RecoverableSignature::from_compact(&compact, rid).unwrap();
+        let key = Secp256k1::verification_only()
+            .recover_ecdsa(&Message::from_slice(&hash).unwrap(), &rec)
+            .unwrap();
+        let addr = keccak256(&key.serialize_uncompressed()[1..]);
+        assert_eq!(&addr[12..], &pk.to_address().unwrap()[..]);
+    }
+
+    fn typed_tx(tx_type: TxType) -> Transaction {
+        let (mut tx, _) = eip155_tx();
+        tx.tx_type = tx_type;
+        if tx_type == TxType::DynamicFee {
+            tx.max_priority_fee_per_gas[31] = 0x7f;
+        }
+        tx.data = vec![0xa9, 0x05, 0x9c, 0xbb];
+        tx.access_list = vec![AccessListItem {
+            address: Address([0x22; 20]),
+            storage_keys: vec![[0; 32], [1; 32]],
+        }];
+        tx
+    }
+
+    #[test]
+    fn parses_eip155_raw_transaction() {
+        let (tx, pk) = eip155_tx();
+        let raw = tx.to_signed_raw(pk, 1).unwrap();
+
+        let parsed = Transaction::from_signed_raw(&raw).unwrap();
+
+        assert_eq!(parsed.tx, tx);
+        assert_eq!(parsed.chain_id, Some(1));
+        assert_eq!(parsed.from, pk.to_address().unwrap());
+        assert_eq!(parsed.hash, keccak256(&raw));
+    }
+
+    #[test]
+    fn parses_unprotected_raw_transaction() {
+        let (tx, pk) = eip155_tx();
+        let sig = pk.sign_hash(keccak256(&tx.to_rlp(None))).unwrap();
+        let mut rlp = tx.to_rlp_raw(None);
+        rlp.push(&sig.v);
+        rlp.push(trim_bytes(&sig.r));
+        rlp.push(trim_bytes(&sig.s));
+        let mut raw = Vec::new();
+        rlp.write_rlp(&mut raw);
+
+        let parsed = Transaction::from_signed_raw(&raw).unwrap();
+
+        assert_eq!(parsed.chain_id, None);
+        assert_eq!(parsed.signature, sig);
+        assert_eq!(parsed.from, pk.to_address().unwrap());
+    }
+
+    #[test]
+    fn round_trips_typed_transactions() {
+        let (_, pk) = eip155_tx();
+        for tx_type in [TxType::Legacy, TxType::AccessList, TxType::DynamicFee] {
+            let mut tx = typed_tx(tx_type);
+            if tx_type == TxType::Legacy {
+                tx.access_list = vec![];
+            }
+            let raw = tx.to_signed_raw(pk, 137).unwrap();
+
+            let parsed = Transaction::from_signed_raw(&raw).unwrap();
+
+            assert_eq!(parsed.tx, tx);
+            assert_eq!(parsed.chain_id, Some(137));
+            assert_eq!(parsed.from, pk.to_address().unwrap());
+            assert_eq!(parsed.tx.raw_from_sig(137, &parsed.signature), raw);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_raw_transactions() {
+        let (_, pk) = eip155_tx();
+        let raw = typed_tx(TxType::DynamicFee).to_signed_raw(pk, 137).unwrap();
+
+        assert!(matches!(
+            Transaction::from_signed_raw(&raw[..raw.len() - 1]),
+            Err(Error::InvalidRlp(RLPError::UnexpectedEnd))
+        ));
+        assert!(matches!(
+            Transaction::from_signed_raw(&[raw.clone(), vec![0]].concat()),
+            Err(Error::InvalidRlp(RLPError::TrailingBytes))
+        ));
+        assert!(matches!(
+            Transaction::from_signed_raw(&[&[0x03], &raw[1..]].concat()),
+            Err(Error::UnsupportedTxType(3))
+        ));
+        // Single byte below 0x80 must be its own encoding
+        assert_eq!(RLPItem::decode(&[0x81, 0x05]), Err(RLPError::NonCanonical));
+        assert_eq!(
+            RLPItem::decode(&[0xc3, 0x05, 0x81, 0x80]),
+            Ok(RLPItem::List(vec![
+                RLPItem::Bytes(vec![0x05]),
+                RLPItem::Bytes(vec![0x80])
+            ]))
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! # Wallet module errors
+
+use super::core;
+use super::keystore::{self, KeystoreError, SerializeError};
+use diesel::r2d2::PoolError;
+use std::{env, error, fmt};
+use web3::Error as Web3Error;
+
+/// Wallet errors
+#[derive(Debug)]
+pub enum Error {
+    /// Required environment variable is missing
+    MissingConfig(String),
+
+    /// Stored keystore file can't be decoded
+    InvalidKeyfile(SerializeError),
+
+    /// Keystore file can't be unlocked
+    KeystoreFault(keystore::Error),
+
+    /// Keystore file can't be saved
+    KeyfileStorage(String),
+
+    /// Transaction can't be encoded or signed
+    InvalidTransaction(core::Error),
+
+    /// Signed transaction doesn't recover to the account address
+    SignerMismatch(String),
+
+    /// Signing or JSON-RPC transport error
+    RpcFault(Web3Error),
+
+    /// Transaction can't be recorded in the database
+    StorageFault(String),
+}
+
+impl From<env::VarError> for Error {
+    fn from(err: env::VarError) -> Self {
+        Error::MissingConfig(err.to_string())
+    }
+}
+
+impl From<SerializeError> for Error {
+    fn from(err: SerializeError) -> Self {
+        Error::InvalidKeyfile(err)
+    }
+}
+
+impl From<keystore::Error> for Error {
+    fn from(err: keystore::Error) -> Self {
+        Error::KeystoreFault(err)
+    }
+}
+
+impl From<KeystoreError> for Error {
+    fn from(err: KeystoreError) -> Self {
+        match err {
+            KeystoreError::StorageError(str) => Error::KeyfileStorage(str),
+        }
+    }
+}
+
+impl From<core::Error> for Error {
+    fn from(err: core::Error) -> Self {
+        Error::InvalidTransaction(err)
+    }
+}
+
+impl From<Web3Error> for Error {
+    fn from(err: Web3Error) -> Self {
+        Error::RpcFault(err)
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(err: diesel::result::Error) -> Self {
+        Error::StorageFault(err.to_string())
+    }
+}
+
+impl From<PoolError> for Error {
+    fn from(err: PoolError) -> Self {
+        Error::StorageFault(err.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::MissingConfig(ref str) => write!(f, "Missing configuration: {}", str),
+            Error::InvalidKeyfile(ref err) => write!(f, "Invalid keystore file: {}", err),
+            Error::KeystoreFault(ref err) => write!(f, "Keystore error: {}", err),
+            Error::KeyfileStorage(ref str) => write!(f, "Keystore storage error: {}", str),
+            Error::InvalidTransaction(ref err) => write!(f, "Invalid transaction: {}", err),
+            Error::SignerMismatch(ref addr) => {
+                write!(f, "Transaction signed by unexpected sender: {}", addr)
+            }
+            Error::RpcFault(ref err) => write!(f, "RPC error: {}", err),
+            Error::StorageFault(ref str) => write!(f, "Storage error: {}", str),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Wallet error"
+    }
+
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match *self {
+            Error::InvalidKeyfile(ref err) => Some(err),
+            Error::KeystoreFault(ref err) => Some(err),
+            Error::InvalidTransaction(ref err) => Some(err),
+            Error::RpcFault(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,402 @@
+//! # Transaction fee strategy
+//!
+//! EIP-1559 fees are derived from `eth_feeHistory`: the tip is a percentile of the
+//! priority fees paid in recent blocks, the fee cap leaves room for the base fee to
+//! rise. Chains without a base fee fall back to legacy `eth_gasPrice` pricing.
+
+use web3::types::{BlockNumber, TransactionParameters, U256, U64};
+use web3::{Transport, Web3};
+
+/// Number of recent blocks sampled for priority fees
+const FEE_HISTORY_BLOCKS: u64 = 10;
+
+/// Lowest tip offered when recent blocks paid no priority fee (1 gwei)
+const MIN_PRIORITY_FEE: u64 = 1_000_000_000;
+
+/// Headroom added to the node's gas estimate, in percents
+const GAS_LIMIT_MARGIN: u64 = 20;
+
+/// EIP-1559 transaction type
+const EIP1559_TX_TYPE: u64 = 2;
+
+/// Fee increase nodes require to replace a pending transaction, in percents
+const REPLACEMENT_BUMP: u64 = 10;
+
+/// Fee speed preset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Speed {
+    Slow,
+    Standard,
+    Fast,
+}
+
+impl Speed {
+    /// Percentile of the recent priority fees used as tip
+    fn reward_percentile(&self) -> f64 {
+        match *self {
+            Speed::Slow => 10.0,
+            Speed::Standard => 50.0,
+            Speed::Fast => 90.0,
+        }
+    }
+
+    /// Fee cap over the pending base fee, in percents.
+    /// A full block raises the base fee by 12.5%.
+    fn base_fee_margin(&self) -> u64 {
+        match *self {
+            Speed::Slow => 125,
+            Speed::Standard => 200,
+            Speed::Fast => 300,
+        }
+    }
+
+    /// Share of `eth_gasPrice` paid on legacy chains, in percents
+    fn gas_price_margin(&self) -> u64 {
+        match *self {
+            Speed::Slow => 90,
+            Speed::Standard => 100,
+            Speed::Fast => 125,
+        }
+    }
+}
+
+/// Fees of a transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fees {
+    Eip1559 {
+        /// Base fee of the pending block
+        base_fee: U256,
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
+    Legacy {
+        gas_price: U256,
+    },
+}
+
+impl Fees {
+    /// Fees for the given speed, legacy pricing when the chain reports no base fee
+    pub async fn estimate<T: Transport>(w3: &Web3<T>, speed: Speed) -> Result<Self, web3::Error> {
+        let history = w3
+            .eth()
+            .fee_history(
+                U256::from(FEE_HISTORY_BLOCKS),
+                BlockNumber::Latest,
+                Some(vec![speed.reward_percentile()]),
+            )
+            .await;
+        // Nodes without EIP-1559 support reject `eth_feeHistory`
+        let history = match history {
+            Ok(history) => history,
+            Err(_) => return Self::legacy(w3, speed).await,
+        };
+        // The last entry is the base fee of the pending block
+        let base_fee = history.base_fee_per_gas.last().cloned().unwrap_or_default();
+        if base_fee.is_zero() {
+            return Self::legacy(w3, speed).await;
+        }
+
+        let mut rewards: Vec<U256> = history
+            .reward
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|r| r.first().cloned())
+            .filter(|r| !r.is_zero())
+            .collect();
+        rewards.sort();
+        let tip = rewards
+            .get(rewards.len() / 2)
+            .cloned()
+            .unwrap_or_else(|| U256::from(MIN_PRIORITY_FEE));
+
+        Ok(Fees::Eip1559 {
+            base_fee,
+            max_fee_per_gas: base_fee * speed.base_fee_margin() / 100 + tip,
+            max_priority_fee_per_gas: tip,
+        })
+    }
+
+    async fn legacy<T: Transport>(w3: &Web3<T>, speed: Speed) -> Result<Self, web3::Error> {
+        let gas_price = w3.eth().gas_price().await?;
+        Ok(Fees::Legacy {
+            gas_price: gas_price * speed.gas_price_margin() / 100,
+        })
+    }
+
+    /// Price per gas the transaction is expected to pay
+    pub fn gas_price(&self) -> U256 {
+        match *self {
+            Fees::Eip1559 {
+                base_fee,
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => (base_fee + max_priority_fee_per_gas).min(max_fee_per_gas),
+            Fees::Legacy { gas_price } => gas_price,
+        }
+    }
+
+    /// Fees of a transaction replacing one which paid `previous`, of the same type.
+    /// Every fee field is at least these fees and `REPLACEMENT_BUMP` percent over the
+    /// previous one.
+    pub fn replacing(&self, previous: &Fees) -> Fees {
+        match *previous {
+            Fees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                ..
+            } => {
+                let (base_fee, market_max_fee, market_tip) = match *self {
+                    Fees::Eip1559 {
+                        base_fee,
+                        max_fee_per_gas,
+                        max_priority_fee_per_gas,
+                    } => (base_fee, max_fee_per_gas, max_priority_fee_per_gas),
+                    Fees::Legacy { gas_price } => (U256::zero(), gas_price, gas_price),
+                };
+                let tip = bump(max_priority_fee_per_gas).max(market_tip);
+                Fees::Eip1559 {
+                    base_fee,
+                    max_fee_per_gas: bump(max_fee_per_gas).max(market_max_fee).max(tip),
+                    max_priority_fee_per_gas: tip,
+                }
+            }
+            Fees::Legacy { gas_price } => Fees::Legacy {
+                gas_price: bump(gas_price).max(self.gas_price()),
+            },
+        }
+    }
+
+    /// Set the fee fields of a transaction, as a type 2 transaction for EIP-1559 fees
+    pub fn apply(&self, tx: &mut TransactionParameters) {
+        match *self {
+            Fees::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                ..
+            } => {
+                tx.transaction_type = Some(U64::from(EIP1559_TX_TYPE));
+                tx.gas_price = None;
+                tx.max_fee_per_gas = Some(max_fee_per_gas);
+                tx.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+            }
+            Fees::Legacy { gas_price } => {
+                tx.transaction_type = None;
+                tx.gas_price = Some(gas_price);
+                tx.max_fee_per_gas = None;
+                tx.max_priority_fee_per_gas = None;
+            }
+        }
+    }
+}
+
+/// Lowest fee accepted for a replacement of a transaction paying `fee`
+fn bump(fee: U256) -> U256 {
+    // Rounded up, nodes reject replacements a wei short of the bump
+    fee + (fee * REPLACEMENT_BUMP + 99) / 100
+}
+
+/// Gas limit for a node's gas estimate
+pub fn gas_limit(estimate: U256) -> U256 {
+    estimate + estimate * GAS_LIMIT_MARGIN / 100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use web3::transports::Http;
+
+    const GWEI: u64 = 1_000_000_000;
+
+    /// Local JSON-RPC endpoint answering `eth_feeHistory` with `history`, or rejecting it
+    /// when `None`, and `eth_gasPrice` with 20 gwei. Returns the requests it received.
+    fn mock_node(
+        history: Option<serde_json::Value>,
+    ) -> (Web3<Http>, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (history, requests) = (history.clone(), requests.clone());
+                thread::spawn(move || serve(stream.unwrap(), history, requests));
+            }
+        });
+        (Web3::new(Http::new(&url).unwrap()), received)
+    }
+
+    fn serve(
+        stream: TcpStream,
+        history: Option<serde_json::Value>,
+        requests: Arc<Mutex<Vec<serde_json::Value>>>,
+    ) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        loop {
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    return;
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((k, v)) = line.split_once(':') {
+                    if k.eq_ignore_ascii_case("content-length") {
+                        len = v.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0u8; len];
+            std::io::Read::read_exact(&mut reader, &mut body).unwrap();
+            let req: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            requests.lock().unwrap().push(req.clone());
+            let result = match (req["method"].as_str().unwrap(), &history) {
+                ("eth_feeHistory", Some(history)) => Some(history.clone()),
+                ("eth_gasPrice", _) => Some(serde_json::json!(format!("{:#x}", 20 * GWEI))),
+                _ => None,
+            };
+            let reply = match result {
+                Some(result) => {
+                    serde_json::json!({ "jsonrpc": "2.0", "id": req["id"], "result": result })
+                }
+                None => serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": req["id"],
+                    "error": { "code": -32601, "message": "the method does not exist" },
+                }),
+            };
+            let out = reply.to_string();
+            write!(
+                writer,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                out.len(),
+                out
+            )
+            .unwrap();
+        }
+    }
+
+    /// `eth_feeHistory` result with the base fees in gwei, the last one of the pending
+    /// block, and one reward per block in gwei
+    fn fee_history(base_fees: &[u64], rewards: &[u64]) -> serde_json::Value {
+        let hex = |gwei: &u64| format!("{:#x}", gwei * GWEI);
+        serde_json::json!({
+            "oldestBlock": "0x1",
+            "baseFeePerGas": base_fees.iter().map(hex).collect::<Vec<_>>(),
+            "gasUsedRatio": vec![0.5; rewards.len()],
+            "reward": rewards.iter().map(|r| vec![hex(r)]).collect::<Vec<_>>(),
+        })
+    }
+
+    #[tokio::test]
+    async fn eip1559_fees_use_the_median_tip() {
+        let history = fee_history(&[8, 9, 10, 11, 12, 10], &[3, 0, 1, 5, 2]);
+        let (w3, requests) = mock_node(Some(history));
+
+        let fees = Fees::estimate(&w3, Speed::Fast).await.unwrap();
+        // Empty blocks are ignored, the median of 1, 2, 3 and 5 gwei is taken
+        assert_eq!(fees, eip1559(10 * GWEI, 33 * GWEI, 3 * GWEI));
+        assert_eq!(fees.gas_price(), U256::from(13 * GWEI));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["params"][2], serde_json::json!([90.0]));
+    }
+
+    #[tokio::test]
+    async fn eip1559_tip_has_a_floor() {
+        let history = fee_history(&[10, 10, 10], &[0, 0]);
+        let (w3, _) = mock_node(Some(history));
+
+        let fees = Fees::estimate(&w3, Speed::Slow).await.unwrap();
+        assert_eq!(
+            fees,
+            eip1559(
+                10 * GWEI,
+                12_500_000_000 + MIN_PRIORITY_FEE,
+                MIN_PRIORITY_FEE
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn zero_base_fee_falls_back_to_gas_price() {
+        let (w3, requests) = mock_node(Some(fee_history(&[0, 0, 0], &[1, 1])));
+
+        let fees = Fees::estimate(&w3, Speed::Fast).await.unwrap();
+        assert_eq!(
+            fees,
+            Fees::Legacy {
+                gas_price: U256::from(25 * GWEI)
+            }
+        );
+        assert_eq!(requests.lock().unwrap()[1]["method"], "eth_gasPrice");
+    }
+
+    #[tokio::test]
+    async fn rejected_fee_history_falls_back_to_gas_price() {
+        let (w3, _) = mock_node(None);
+
+        let fees = Fees::estimate(&w3, Speed::Slow).await.unwrap();
+        assert_eq!(
+            fees,
+            Fees::Legacy {
+                gas_price: U256::from(18 * GWEI)
+            }
+        );
+    }
+
+    fn eip1559(base_fee: u64, max_fee: u64, tip: u64) -> Fees {
+        Fees::Eip1559 {
+            base_fee: U256::from(base_fee),
+            max_fee_per_gas: U256::from(max_fee),
+            max_priority_fee_per_gas: U256::from(tip),
+        }
+    }
+
+    #[test]
+    fn replacement_bumps_previous_fees() {
+        // The market got cheaper than the stuck transaction
+        let market = eip1559(10 * GWEI, 20 * GWEI, GWEI);
+        let previous = eip1559(0, 50 * GWEI, 2 * GWEI);
+        assert_eq!(
+            market.replacing(&previous),
+            eip1559(10 * GWEI, 55 * GWEI, 2_200_000_000)
+        );
+
+        // Rounded up to the next wei
+        assert_eq!(
+            eip1559(0, 0, 0).replacing(&eip1559(0, 101, 1)),
+            eip1559(0, 112, 2)
+        );
+    }
+
+    #[test]
+    fn replacement_follows_the_market() {
+        let market = eip1559(80 * GWEI, 170 * GWEI, 3 * GWEI);
+        let previous = eip1559(0, 50 * GWEI, 2 * GWEI);
+        assert_eq!(market.replacing(&previous), market);
+
+        let legacy = Fees::Legacy {
+            gas_price: U256::from(30 * GWEI),
+        };
+        assert_eq!(
+            market.replacing(&legacy),
+            Fees::Legacy {
+                gas_price: U256::from(83 * GWEI)
+            }
+        );
+        assert_eq!(
+            eip1559(0, 0, 0).replacing(&legacy),
+            Fees::Legacy {
+                gas_price: U256::from(33 * GWEI)
+            }
+        );
+    }
+}
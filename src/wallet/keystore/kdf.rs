@@ -143,7 +143,7 @@ impl Kdf {
             }
             Kdf::Scrypt { n, r, p } => {
                 let log_n = (n as f64).log2().round() as u8;
-                let params = Params::new(log_n, r, p).expect("Invalid Scrypt parameters");
+                let params = Params::new(log_n, r, p, len).expect("Invalid Scrypt parameters");
                 scrypt(passphrase.as_bytes(), kdf_salt, &params, &mut key).expect("Scrypt failed");
             }
         }
@@ -1,6 +1,7 @@
 extern crate aes;
 extern crate bitcoin;
 extern crate byteorder;
+extern crate chrono;
 extern crate glob;
 extern crate hex;
 extern crate hmac;
@@ -15,11 +16,15 @@ extern crate serde_json;
 extern crate sha2;
 extern crate sha3;
 extern crate time;
-extern crate chrono;
 extern crate uuid;
 
 mod core;
+pub mod error;
+pub mod fee;
 pub mod keystore;
+pub mod nonce;
+pub mod permit;
+pub mod tracker;
 pub mod util;
 pub mod wallet;
 
@@ -0,0 +1,185 @@
+//! # Nonce manager
+//!
+//! Nonces are reserved in process per (account, chain), so concurrent sends from one
+//! account never share a nonce. The counter of an account starts from the node's
+//! `pending` transaction count and is read again after errors which leave the local
+//! counter out of sync with the node. Nonces of transactions which never reached the
+//! mempool are released and handed out again first, so they don't leave a gap.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use web3::types::{Address, BlockNumber, U256};
+use web3::{Transport, Web3};
+
+lazy_static! {
+    /// Nonces of every account of the service
+    pub static ref NONCES: NonceManager = NonceManager::default();
+}
+
+/// Nonces of an account on a chain
+#[derive(Debug, Default)]
+struct Nonces {
+    /// Nonce after the highest one reserved
+    next: u64,
+    /// Nonces below `next` which were released and not reserved again
+    released: BTreeSet<u64>,
+}
+
+impl Nonces {
+    fn reserve(&mut self) -> u64 {
+        match self.released.pop_first() {
+            Some(nonce) => nonce,
+            None => {
+                self.next += 1;
+                self.next - 1
+            }
+        }
+    }
+
+    fn release(&mut self, nonce: u64) {
+        if nonce >= self.next {
+            return;
+        }
+        self.released.insert(nonce);
+        // Released nonces at the top only lower the counter
+        while self.next > 0 && self.released.remove(&(self.next - 1)) {
+            self.next -= 1;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct NonceManager {
+    accounts: Mutex<HashMap<(Address, u64), Nonces>>,
+}
+
+impl NonceManager {
+    /// Reserve the next nonce of `from` on `chain`, reading the `pending` count
+    /// from the node when the account has no counter yet
+    pub async fn reserve<T: Transport>(
+        &self,
+        w3: &Web3<T>,
+        from: Address,
+        chain: u64,
+    ) -> Result<U256, web3::Error> {
+        if let Some(nonce) = self.try_reserve(from, chain) {
+            return Ok(U256::from(nonce));
+        }
+        let pending = w3
+            .eth()
+            .transaction_count(from, Some(BlockNumber::Pending))
+            .await?;
+        Ok(U256::from(self.reserve_from(from, chain, pending.as_u64())))
+    }
+
+    /// Give back a reserved nonce whose transaction was not broadcast
+    pub fn release(&self, from: Address, chain: u64, nonce: U256) {
+        let mut accounts = self.accounts.lock().unwrap();
+        if let Some(nonces) = accounts.get_mut(&(from, chain)) {
+            nonces.release(nonce.as_u64());
+        }
+    }
+
+    /// Drop the counter of an account, the next reservation reads the node's count again
+    pub fn resync(&self, from: Address, chain: u64) {
+        self.accounts.lock().unwrap().remove(&(from, chain));
+    }
+
+    fn try_reserve(&self, from: Address, chain: u64) -> Option<u64> {
+        let mut accounts = self.accounts.lock().unwrap();
+        accounts.get_mut(&(from, chain)).map(Nonces::reserve)
+    }
+
+    /// Reserve with a counter starting at `pending`, unless another call started it
+    /// while the count was read
+    fn reserve_from(&self, from: Address, chain: u64, pending: u64) -> u64 {
+        let mut accounts = self.accounts.lock().unwrap();
+        accounts
+            .entry((from, chain))
+            .or_insert_with(|| Nonces {
+                next: pending,
+                ..Default::default()
+            })
+            .reserve()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    const CHAIN: u64 = 80001;
+
+    #[test]
+    fn reserves_consecutive_nonces_per_account_and_chain() {
+        let nonces = NonceManager::default();
+        let a = Address::repeat_byte(0x0a);
+        let b = Address::repeat_byte(0x0b);
+
+        assert_eq!(nonces.reserve_from(a, CHAIN, 5), 5);
+        assert_eq!(nonces.reserve_from(a, CHAIN, 5), 6);
+        assert_eq!(nonces.try_reserve(a, CHAIN), Some(7));
+        assert_eq!(nonces.try_reserve(b, CHAIN), None);
+        assert_eq!(nonces.reserve_from(b, CHAIN, 0), 0);
+        assert_eq!(nonces.reserve_from(a, 1, 40), 40);
+    }
+
+    #[test]
+    fn concurrent_reservations_are_unique() {
+        let nonces = Arc::new(NonceManager::default());
+        let from = Address::repeat_byte(0x0a);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let nonces = nonces.clone();
+                thread::spawn(move || {
+                    (0..50)
+                        .map(|_| nonces.reserve_from(from, CHAIN, 3))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let mut reserved: Vec<u64> = handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+        reserved.sort();
+        assert_eq!(reserved, (3..403).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn released_nonces_fill_gaps_first() {
+        let nonces = NonceManager::default();
+        let from = Address::repeat_byte(0x0a);
+        for _ in 0..4 {
+            nonces.reserve_from(from, CHAIN, 10);
+        }
+
+        nonces.release(from, CHAIN, U256::from(11));
+        assert_eq!(nonces.try_reserve(from, CHAIN), Some(11));
+        assert_eq!(nonces.try_reserve(from, CHAIN), Some(14));
+
+        // Releasing the top nonces lowers the counter
+        nonces.release(from, CHAIN, U256::from(13));
+        nonces.release(from, CHAIN, U256::from(14));
+        assert_eq!(nonces.try_reserve(from, CHAIN), Some(13));
+
+        // Unknown nonces are ignored
+        nonces.release(from, CHAIN, U256::from(99));
+        assert_eq!(nonces.try_reserve(from, CHAIN), Some(14));
+    }
+
+    #[test]
+    fn resync_restarts_from_node_count() {
+        let nonces = NonceManager::default();
+        let from = Address::repeat_byte(0x0a);
+        nonces.reserve_from(from, CHAIN, 10);
+        nonces.reserve_from(from, CHAIN, 10);
+
+        nonces.resync(from, CHAIN);
+        assert_eq!(nonces.try_reserve(from, CHAIN), None);
+        assert_eq!(nonces.reserve_from(from, CHAIN, 10), 10);
+    }
+}
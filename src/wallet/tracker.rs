@@ -0,0 +1,458 @@
+//! # Receipt tracker
+//!
+//! One task per chain follows the new block headers and fetches the receipts of the
+//! chain's pending transactions. Receipt fields are saved as soon as a transaction is
+//! mined, its status becomes final once the block has enough confirmations.
+//! Every change is published as a `TxEvent` to the subscribers of the tracker.
+
+use crate::database::models::transaction::{Transaction, TxReceipt, TxStatus};
+use crate::database::pool::PgPool;
+use crate::wallet::error::Error;
+use crate::wallet::nonce::NONCES;
+use crate::wallet::util::revert_reason;
+use crate::wallet::wallet::Wallet;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use futures::StreamExt;
+use std::env;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use web3::transports::WebSocket;
+use web3::types::{
+    Address, BlockId, BlockNumber, Bytes, CallRequest, TransactionId, TransactionReceipt, H256,
+    U256, U64,
+};
+use web3::{Transport, Web3};
+
+/// Confirmations used when `TX_CONFIRMATIONS` is not set
+const DEFAULT_CONFIRMATIONS: u64 = 12;
+
+/// Wait before subscribing again to new heads after the subscription failed
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Events kept for subscribers which fall behind
+const EVENT_BUFFER: usize = 1024;
+
+/// Kind of a transaction status event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxEventKind {
+    /// Waiting in the mempool, also after its block was reorganized away
+    Pending,
+    /// Mined in a block for the first time
+    Included,
+    /// Mined successfully, one more block confirms it
+    Confirmed,
+    /// Mined and reverted
+    Reverted,
+    /// Another transaction of the sender was mined with the same nonce
+    Replaced,
+    /// Evicted from the mempool
+    Dropped,
+}
+
+/// Status change of a tracked transaction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxEvent {
+    pub hash: String,
+    pub kind: TxEventKind,
+    pub block_number: Option<u64>,
+    /// Blocks from the including one up to the head, 0 while not mined
+    pub confirmations: u64,
+    /// No event follows a final one
+    pub is_final: bool,
+    /// Revert reason of reverted transactions
+    pub reason: Option<String>,
+    /// Hash of the transaction mined at the nonce of a replaced one, when it is known
+    pub replaced_by: Option<String>,
+}
+
+impl TxEvent {
+    fn unmined(hash: &str, kind: TxEventKind, is_final: bool) -> Self {
+        Self {
+            hash: hash.to_string(),
+            kind,
+            block_number: None,
+            confirmations: 0,
+            is_final,
+            reason: None,
+            replaced_by: None,
+        }
+    }
+
+    fn replaced(hash: &str, by: Option<String>) -> Self {
+        Self {
+            replaced_by: by,
+            ..Self::unmined(hash, TxEventKind::Replaced, true)
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Tracker {
+    pool: PgPool,
+    /// Blocks, the including one too, after which a receipt is final
+    confirmations: u64,
+    events: broadcast::Sender<TxEvent>,
+}
+
+impl Tracker {
+    pub fn new(pool: PgPool, confirmations: u64) -> Self {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        Self {
+            pool,
+            confirmations: confirmations.max(1),
+            events,
+        }
+    }
+
+    /// Tracker with the number of confirmations from `TX_CONFIRMATIONS`
+    pub fn from_env(pool: PgPool) -> Self {
+        dotenv::dotenv().ok();
+        let confirmations = env::var("TX_CONFIRMATIONS")
+            .ok()
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(DEFAULT_CONFIRMATIONS);
+        Self::new(pool, confirmations)
+    }
+
+    /// Events of every tracked transaction, published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<TxEvent> {
+        self.events.subscribe()
+    }
+
+    /// Start a tracking task for every chain of the wallet
+    pub fn spawn(self, wallet: &Wallet) {
+        for chain in wallet.chains() {
+            if let Some(w3) = wallet.try_get_instance(&chain) {
+                let tracker = self.clone();
+                tokio::spawn(async move { tracker.watch(chain, w3).await });
+            }
+        }
+    }
+
+    /// Current status of a stored transaction as an event, `head` being the latest block
+    pub fn snapshot(&self, tx: &Transaction, head: u64) -> TxEvent {
+        let status = tx.status.parse().unwrap_or(TxStatus::Pending);
+        let receipt = tx.receipt();
+        match (status, receipt.block_number) {
+            (TxStatus::Replaced, _) => TxEvent::replaced(&tx.hash, tx.replaced_by.clone()),
+            // Failed without a block means the node rejected the transaction
+            (TxStatus::Dropped, _) | (TxStatus::Failed, None) => {
+                TxEvent::unmined(&tx.hash, TxEventKind::Dropped, true)
+            }
+            (TxStatus::Pending, None) => TxEvent::unmined(&tx.hash, TxEventKind::Pending, false),
+            (status, Some(_)) => {
+                let mut event = mined_event(&tx.hash, &receipt, false, head, self.confirmations);
+                event.is_final = status != TxStatus::Pending;
+                event
+            }
+            (TxStatus::Confirmed, None) => TxEvent::unmined(&tx.hash, TxEventKind::Confirmed, true),
+        }
+    }
+
+    /// Track the pending transactions of the chain on every new block
+    async fn watch(&self, chain: u32, w3: Web3<WebSocket>) {
+        loop {
+            match w3.eth_subscribe().subscribe_new_heads().await {
+                Ok(mut heads) => {
+                    while let Some(head) = heads.next().await {
+                        let number = match head {
+                            Ok(head) => head.number,
+                            Err(err) => {
+                                warn!("New heads subscription failed on chain {}: {}", chain, err);
+                                break;
+                            }
+                        };
+                        if let Some(number) = number {
+                            if let Err(err) = self.track(&w3, chain, number.as_u64()).await {
+                                error!("Receipt tracking failed on chain {}: {}", chain, err);
+                            }
+                        }
+                    }
+                }
+                Err(err) => warn!("Can't subscribe to new heads on chain {}: {}", chain, err),
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+
+    /// Update the pending transactions of the chain for the block `head`
+    pub async fn track<T: Transport>(
+        &self,
+        w3: &Web3<T>,
+        chain: u32,
+        head: u64,
+    ) -> Result<(), Error> {
+        let mut conn = self.pool.get()?;
+        for (tx, from) in Transaction::list_pending(&(chain as i64), &mut conn).await? {
+            let hash: H256 = tx
+                .hash
+                .parse()
+                .map_err(|_| Error::StorageFault(format!("Invalid hash {}", tx.hash)))?;
+            let from: Address = from
+                .parse()
+                .map_err(|_| Error::StorageFault(format!("Invalid address {}", from)))?;
+            let stored = tx.receipt();
+            let receipt = w3.eth().transaction_receipt(hash).await?;
+            // A receipt which disappears was mined in a reorganized block
+            let mut current = receipt.as_ref().map(receipt_fields).unwrap_or_default();
+            if current.reverted == Some(true) {
+                current.revert_reason = if current.block_number == stored.block_number {
+                    stored.revert_reason.clone()
+                } else {
+                    replay(w3, &tx, from, &current).await
+                };
+            }
+            if current != stored {
+                Transaction::update_receipt(&tx.hash, &current, &mut conn).await?;
+            }
+
+            let (event, status) = if current.block_number.is_some() {
+                let included = current.block_number != stored.block_number;
+                let event = mined_event(&tx.hash, &current, included, head, self.confirmations);
+                (event, final_status(&current, head, self.confirmations))
+            } else if stored.block_number.is_some() {
+                let event = TxEvent::unmined(&tx.hash, TxEventKind::Pending, false);
+                (event, None)
+            } else {
+                let status = dropped(w3, &tx, from, hash).await?;
+                if status.is_some() {
+                    // The account's pending count no longer covers this nonce
+                    NONCES.resync(from, chain as u64);
+                }
+                match status {
+                    Some(TxStatus::Replaced) => {
+                        let by = replacement(w3, &tx, &mut conn).await?;
+                        Transaction::mark_replaced(&tx.hash, by.as_deref(), &mut conn).await?;
+                        (TxEvent::replaced(&tx.hash, by), None)
+                    }
+                    Some(status) => {
+                        let event = TxEvent::unmined(&tx.hash, TxEventKind::Dropped, true);
+                        (event, Some(status))
+                    }
+                    // Still waiting in the mempool, nothing changed
+                    None => continue,
+                }
+            };
+            if let Some(status) = status {
+                Transaction::update_status(&tx.hash, status, &mut conn).await?;
+            }
+            // Sending only fails when nobody is subscribed
+            let _ = self.events.send(event);
+        }
+        Ok(())
+    }
+}
+
+/// Stored fields of a receipt, empty while the transaction is not in a block
+fn receipt_fields(receipt: &TransactionReceipt) -> TxReceipt {
+    match receipt.block_number {
+        Some(block_number) => TxReceipt {
+            block_number: Some(block_number.as_u64() as i64),
+            gas_used: receipt.gas_used.map(|g| g.low_u64() as i64),
+            effective_gas_price: receipt.effective_gas_price.map(|p| p.to_string()),
+            reverted: receipt.status.map(|s| s.is_zero()),
+            revert_reason: None,
+        },
+        None => TxReceipt::default(),
+    }
+}
+
+/// Final status of a mined transaction once `head` confirms it, `None` before
+fn final_status(receipt: &TxReceipt, head: u64, confirmations: u64) -> Option<TxStatus> {
+    let block_number = receipt.block_number? as u64;
+    if head + 1 < block_number + confirmations {
+        return None;
+    }
+    match receipt.reverted {
+        Some(true) => Some(TxStatus::Failed),
+        _ => Some(TxStatus::Confirmed),
+    }
+}
+
+/// Event of a mined transaction at block `head`, `included` when its block is new
+fn mined_event(
+    hash: &str,
+    receipt: &TxReceipt,
+    included: bool,
+    head: u64,
+    confirmations: u64,
+) -> TxEvent {
+    let block_number = receipt.block_number.map(|b| b as u64);
+    let is_final = final_status(receipt, head, confirmations).is_some();
+    let kind = if receipt.reverted == Some(true) {
+        TxEventKind::Reverted
+    } else if included && !is_final {
+        TxEventKind::Included
+    } else {
+        TxEventKind::Confirmed
+    };
+    TxEvent {
+        hash: hash.to_string(),
+        kind,
+        block_number,
+        confirmations: block_number.map_or(0, |b| (head + 1).saturating_sub(b)),
+        is_final,
+        reason: receipt.revert_reason.clone(),
+        replaced_by: None,
+    }
+}
+
+/// Revert reason of a reverted transaction, replayed with `eth_call` on its block
+async fn replay<T: Transport>(
+    w3: &Web3<T>,
+    tx: &Transaction,
+    from: Address,
+    receipt: &TxReceipt,
+) -> Option<String> {
+    let call = CallRequest {
+        from: Some(from),
+        to: tx.to_address.as_ref().and_then(|to| to.parse().ok()),
+        gas: Some(U256::from(tx.gas_limit as u64)),
+        value: U256::from_dec_str(&tx.value).ok(),
+        data: hex::decode(tx.data.trim_start_matches("0x"))
+            .ok()
+            .map(Bytes),
+        ..Default::default()
+    };
+    let block = BlockId::Number(BlockNumber::Number(U64::from(receipt.block_number? as u64)));
+    match w3.eth().call(call, Some(block)).await {
+        Err(err) => revert_reason(&err),
+        // The replay can succeed when the revert depended on the transaction's position
+        Ok(_) => None,
+    }
+}
+
+/// Hash of the transaction sent by the service which was mined at the nonce of `tx`
+async fn replacement<T: Transport>(
+    w3: &Web3<T>,
+    tx: &Transaction,
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Option<String>, Error> {
+    for sibling in Transaction::siblings(tx, conn).await? {
+        let hash: H256 = match sibling.hash.parse() {
+            Ok(hash) => hash,
+            Err(_) => continue,
+        };
+        if w3.eth().transaction_receipt(hash).await?.is_some() {
+            return Ok(Some(sibling.hash));
+        }
+    }
+    Ok(None)
+}
+
+/// Final status of an unmined transaction which left the mempool: replaced when
+/// another transaction of the sender took its nonce, dropped when the node
+/// doesn't know the transaction anymore
+async fn dropped<T: Transport>(
+    w3: &Web3<T>,
+    tx: &Transaction,
+    from: Address,
+    hash: H256,
+) -> Result<Option<TxStatus>, Error> {
+    let mined = w3
+        .eth()
+        .transaction_count(from, Some(BlockNumber::Latest))
+        .await?;
+    if mined > U256::from(tx.nonce as u64) {
+        // The transaction itself may have been mined since its receipt was fetched
+        if w3.eth().transaction_receipt(hash).await?.is_some() {
+            return Ok(None);
+        }
+        return Ok(Some(TxStatus::Replaced));
+    }
+    let known = w3
+        .eth()
+        .transaction(TransactionId::Hash(hash))
+        .await?
+        .is_some();
+    Ok(if known { None } else { Some(TxStatus::Dropped) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mined(block_number: u64, status: u64) -> TransactionReceipt {
+        TransactionReceipt {
+            block_number: Some(U64::from(block_number)),
+            gas_used: Some(U256::from(21_000)),
+            effective_gas_price: Some(U256::from(30_000_000_000u64)),
+            status: Some(U64::from(status)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn receipt_fields_of_mined_transaction() {
+        assert_eq!(
+            receipt_fields(&mined(100, 1)),
+            TxReceipt {
+                block_number: Some(100),
+                gas_used: Some(21_000),
+                effective_gas_price: Some("30000000000".to_string()),
+                reverted: Some(false),
+                revert_reason: None,
+            }
+        );
+        assert_eq!(receipt_fields(&mined(100, 0)).reverted, Some(true));
+        let pending = TransactionReceipt {
+            block_number: None,
+            ..mined(100, 1)
+        };
+        assert_eq!(receipt_fields(&pending), TxReceipt::default());
+    }
+
+    #[test]
+    fn final_status_waits_for_confirmations() {
+        let receipt = receipt_fields(&mined(100, 1));
+        assert_eq!(final_status(&receipt, 100, 3), None);
+        assert_eq!(final_status(&receipt, 101, 3), None);
+        assert_eq!(final_status(&receipt, 102, 3), Some(TxStatus::Confirmed));
+        assert_eq!(final_status(&receipt, 100, 1), Some(TxStatus::Confirmed));
+
+        let reverted = receipt_fields(&mined(100, 0));
+        assert_eq!(final_status(&reverted, 102, 3), Some(TxStatus::Failed));
+
+        assert_eq!(final_status(&TxReceipt::default(), 200, 3), None);
+    }
+
+    #[test]
+    fn mined_events_count_confirmations() {
+        let receipt = receipt_fields(&mined(100, 1));
+
+        let included = mined_event("0x01", &receipt, true, 100, 3);
+        assert_eq!(included.kind, TxEventKind::Included);
+        assert_eq!(included.block_number, Some(100));
+        assert_eq!(included.confirmations, 1);
+        assert!(!included.is_final);
+
+        let confirmed = mined_event("0x01", &receipt, false, 101, 3);
+        assert_eq!(confirmed.kind, TxEventKind::Confirmed);
+        assert_eq!(confirmed.confirmations, 2);
+        assert!(!confirmed.is_final);
+
+        let last = mined_event("0x01", &receipt, false, 102, 3);
+        assert_eq!(last.confirmations, 3);
+        assert!(last.is_final);
+
+        // A single confirmation makes the inclusion final
+        let instant = mined_event("0x01", &receipt, true, 100, 1);
+        assert_eq!(instant.kind, TxEventKind::Confirmed);
+        assert!(instant.is_final);
+    }
+
+    #[test]
+    fn mined_events_carry_revert_reason() {
+        let receipt = TxReceipt {
+            revert_reason: Some("UniswapV2Router: EXPIRED".to_string()),
+            ..receipt_fields(&mined(100, 0))
+        };
+
+        let event = mined_event("0x01", &receipt, true, 100, 3);
+        assert_eq!(event.kind, TxEventKind::Reverted);
+        assert_eq!(event.reason.as_deref(), Some("UniswapV2Router: EXPIRED"));
+        assert!(!event.is_final);
+        assert!(mined_event("0x01", &receipt, false, 102, 3).is_final);
+    }
+}
@@ -1,8 +1,10 @@
 //! # Util functions module
+mod revert;
 mod rlp;
 
 //pub use self::crypto::{keccak256, KECCAK256_BYTES};
-pub use self::rlp::{RLPList, WriteRLP};
+pub use self::revert::revert_reason;
+pub use self::rlp::{RLPError, RLPItem, RLPList, WriteRLP};
 use byteorder::{BigEndian, WriteBytesExt};
 use chrono::prelude::Utc;
 // use hex::FromHex;
@@ -250,10 +252,11 @@ pub fn timestamp() -> String {
 //     dur.as_secs() << 30 | dur.subsec_nanos() as u64
 // }
 
-pub fn get_valid_timestamp(future_millis: u128) -> u128 {
+/// Unix timestamp (seconds) `future_secs` from now, as expected by router deadlines
+pub fn get_deadline(future_secs: u32) -> U256 {
     let start = SystemTime::now();
     let since_epoch = start.duration_since(UNIX_EPOCH).unwrap();
-    since_epoch.as_millis().checked_add(future_millis).unwrap()
+    U256::from(since_epoch.as_secs() + u64::from(future_secs))
 }
 
 // pub fn wei_to_eth(wei_val: U256) -> f64 {
@@ -267,12 +270,32 @@ pub fn get_valid_timestamp(future_millis: u128) -> u128 {
 //     U256::from(result)
 // }
 
-pub fn convert_to_wei(amount: f32, multiplier: f32) -> U256 {
-    let ether = format!("{:.0}", amount * multiplier);
-    U256::from_dec_str(&ether).unwrap()
+/// Amount in the smallest unit of a token, negative, NaN and infinite amounts are refused
+pub fn convert_to_wei(amount: f32, multiplier: f32) -> Result<U256, String> {
+    let wei = amount * multiplier;
+    if !wei.is_finite() || wei < 0.0 {
+        return Err(format!("Invalid amount {}", amount));
+    }
+    U256::from_dec_str(&format!("{:.0}", wei)).map_err(|_| format!("Invalid amount {}", amount))
 }
 
-// pub fn convert_from_wei(wei: U256, multiplier: f32) -> f32 {
-//     let wei_u128 = wei.as_u128();
-//     (wei_u128 as f32) / multiplier
-// }
+pub fn convert_from_wei(wei: U256, multiplier: f32) -> f32 {
+    (to_f64(wei) / multiplier as f64) as f32
+}
+
+/// Lossy conversion of a `U256` into `f64` for prices and ratios
+pub fn to_f64(x: U256) -> f64 {
+    x.to_string().parse().unwrap_or(f64::MAX)
+}
+
+/// Lower bound of `amount` for a slippage tolerance given in percents
+pub fn apply_slippage(amount: U256, slippage: f32) -> U256 {
+    let bps = U256::from(((slippage * 100.0).round() as u64).min(10_000));
+    amount * (U256::from(10_000) - bps) / U256::from(10_000)
+}
+
+/// Upper bound of `amount` for a slippage tolerance given in percents
+pub fn apply_slippage_max(amount: U256, slippage: f32) -> U256 {
+    let bps = U256::from(((slippage * 100.0).round() as u64).min(10_000));
+    amount * (U256::from(10_000) + bps) / U256::from(10_000)
+}
@@ -0,0 +1,155 @@
+//! # Revert reasons
+
+use web3::ethabi::{self, ParamType};
+use web3::types::U256;
+
+/// Selector of `Error(string)`, used by `require` and `revert` with a message
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Selector of `Panic(uint256)`, used by failing asserts and arithmetic checks
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// Human readable reason of a revert from its return data
+pub fn decode_revert(data: &[u8]) -> Option<String> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (selector, payload) = data.split_at(4);
+    if selector == ERROR_SELECTOR {
+        match ethabi::decode(&[ParamType::String], payload).ok()?.pop()? {
+            ethabi::Token::String(reason) => Some(reason),
+            _ => None,
+        }
+    } else if selector == PANIC_SELECTOR {
+        match ethabi::decode(&[ParamType::Uint(256)], payload)
+            .ok()?
+            .pop()?
+        {
+            ethabi::Token::Uint(code) => Some(format!("panic: {}", panic_message(code))),
+            _ => None,
+        }
+    } else {
+        Some(format!("custom error 0x{}", hex::encode(data)))
+    }
+}
+
+/// Revert reason of a failed `eth_call`, from the error data or message of the node
+pub fn revert_reason(err: &web3::Error) -> Option<String> {
+    let err = match err {
+        web3::Error::Rpc(err) => err,
+        _ => return None,
+    };
+    let data = err
+        .data
+        .as_ref()
+        .and_then(|d| d.as_str())
+        .and_then(|d| hex::decode(d.trim_start_matches("0x")).ok());
+    if let Some(reason) = data.as_deref().and_then(decode_revert) {
+        return Some(reason);
+    }
+    err.message
+        .strip_prefix("execution reverted")
+        .map(|reason| reason.trim_start_matches(':').trim())
+        .map(|reason| match reason {
+            "" => "execution reverted".to_string(),
+            reason => reason.to_string(),
+        })
+}
+
+/// Solidity panic codes
+fn panic_message(code: U256) -> String {
+    match code.low_u64() {
+        0x01 => "assertion failed".to_string(),
+        0x11 => "arithmetic overflow or underflow".to_string(),
+        0x12 => "division by zero".to_string(),
+        0x21 => "invalid enum value".to_string(),
+        0x31 => "pop on empty array".to_string(),
+        0x32 => "array index out of bounds".to_string(),
+        0x41 => "out of memory".to_string(),
+        0x51 => "call to uninitialized function".to_string(),
+        _ => format!("code 0x{:x}", code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::ethabi::Token;
+
+    #[test]
+    fn decodes_error_string() {
+        let mut data = ERROR_SELECTOR.to_vec();
+        data.extend(ethabi::encode(&[Token::String(
+            "UniswapV2Router: EXPIRED".to_string(),
+        )]));
+        assert_eq!(
+            decode_revert(&data).as_deref(),
+            Some("UniswapV2Router: EXPIRED")
+        );
+    }
+
+    #[test]
+    fn decodes_panic_code() {
+        let mut data = PANIC_SELECTOR.to_vec();
+        data.extend(ethabi::encode(&[Token::Uint(U256::from(0x11))]));
+        assert_eq!(
+            decode_revert(&data).as_deref(),
+            Some("panic: arithmetic overflow or underflow")
+        );
+    }
+
+    #[test]
+    fn reads_reason_from_rpc_error() {
+        let err = web3::Error::Rpc(
+            serde_json::from_value(serde_json::json!({
+                "code": 3,
+                "message": "execution reverted: TransferHelper: TRANSFER_FROM_FAILED",
+            }))
+            .unwrap(),
+        );
+        assert_eq!(
+            revert_reason(&err).as_deref(),
+            Some("TransferHelper: TRANSFER_FROM_FAILED")
+        );
+        assert_eq!(decode_revert(&[0x01]), None);
+    }
+
+    #[test]
+    fn reads_reason_from_rpc_error_data() {
+        let mut data = ERROR_SELECTOR.to_vec();
+        data.extend(ethabi::encode(&[Token::String(
+            "UniswapV2Router: INSUFFICIENT_OUTPUT_AMOUNT".to_string(),
+        )]));
+        let err = web3::Error::Rpc(
+            serde_json::from_value(serde_json::json!({
+                "code": 3,
+                "message": "execution reverted",
+                "data": format!("0x{}", hex::encode(&data)),
+            }))
+            .unwrap(),
+        );
+        assert_eq!(
+            revert_reason(&err).as_deref(),
+            Some("UniswapV2Router: INSUFFICIENT_OUTPUT_AMOUNT")
+        );
+
+        // Reverts without data or message
+        let err = web3::Error::Rpc(
+            serde_json::from_value(serde_json::json!({
+                "code": 3,
+                "message": "execution reverted",
+            }))
+            .unwrap(),
+        );
+        assert_eq!(revert_reason(&err).as_deref(), Some("execution reverted"));
+
+        let err = web3::Error::Rpc(
+            serde_json::from_value(serde_json::json!({
+                "code": -32000,
+                "message": "insufficient funds for gas * price + value",
+            }))
+            .unwrap(),
+        );
+        assert_eq!(revert_reason(&err), None);
+    }
+}
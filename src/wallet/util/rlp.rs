@@ -4,6 +4,7 @@
 //! See [RLP spec](https://github.com/ethereumproject/wiki/wiki/RLP)
 
 use super::{bytes_count, to_bytes, trim_bytes};
+use std::{error, fmt};
 
 /// The `WriteRLP` trait is used to specify functionality of serializing data to RLP bytes
 pub trait WriteRLP {
@@ -149,7 +150,11 @@ impl<T: WriteRLP> WriteRLP for Vec<T> {
 impl WriteRLP for [u8] {
     fn write_rlp(&self, buf: &mut Vec<u8>) {
         let len = self.len();
-        if len <= 55 {
+        if len == 1 && self[0] <= 0x7f {
+            // For a single byte whose value is in the [0x00, 0x7f] range, that byte is its own
+            // RLP encoding.
+            buf.push(self[0]);
+        } else if len <= 55 {
             // Otherwise, if a string is 0-55 bytes long, the RLP encoding consists of a single byte
             // with value 0x80 plus the length of the string followed by the string. The range of
             // the first byte is thus [0x80, 0xb7].
@@ -191,3 +196,169 @@ impl WriteRLP for RLPList {
         buf.extend_from_slice(&self.tail);
     }
 }
+
+/// RLP decoding errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RLPError {
+    /// Input ends inside an item
+    UnexpectedEnd,
+
+    /// Input continues after the top level item
+    TrailingBytes,
+
+    /// Item isn't encoded in its shortest form
+    NonCanonical,
+
+    /// A byte string was expected, got a list
+    ExpectedBytes,
+
+    /// A list was expected, got a byte string
+    ExpectedList,
+
+    /// Integer doesn't fit the requested size
+    Overflow,
+}
+
+impl fmt::Display for RLPError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RLPError::UnexpectedEnd => f.write_str("Unexpected end of RLP data"),
+            RLPError::TrailingBytes => f.write_str("Trailing bytes after RLP item"),
+            RLPError::NonCanonical => f.write_str("Non canonical RLP encoding"),
+            RLPError::ExpectedBytes => f.write_str("Expected RLP byte string"),
+            RLPError::ExpectedList => f.write_str("Expected RLP list"),
+            RLPError::Overflow => f.write_str("RLP integer overflow"),
+        }
+    }
+}
+
+impl error::Error for RLPError {
+    fn description(&self) -> &str {
+        "RLP decoding error"
+    }
+}
+
+/// A decoded RLP item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RLPItem {
+    /// Byte string
+    Bytes(Vec<u8>),
+
+    /// List of items
+    List(Vec<RLPItem>),
+}
+
+impl RLPItem {
+    /// Decode a single item spanning the whole input
+    pub fn decode(data: &[u8]) -> Result<RLPItem, RLPError> {
+        let (item, len) = decode_item(data)?;
+        if len != data.len() {
+            return Err(RLPError::TrailingBytes);
+        }
+        Ok(item)
+    }
+
+    /// Content of a byte string item
+    pub fn as_bytes(&self) -> Result<&[u8], RLPError> {
+        match *self {
+            RLPItem::Bytes(ref bytes) => Ok(bytes),
+            RLPItem::List(_) => Err(RLPError::ExpectedBytes),
+        }
+    }
+
+    /// Items of a list item
+    pub fn as_list(&self) -> Result<&[RLPItem], RLPError> {
+        match *self {
+            RLPItem::List(ref items) => Ok(items),
+            RLPItem::Bytes(_) => Err(RLPError::ExpectedList),
+        }
+    }
+
+    /// Big endian integer, right aligned in 32 bytes
+    pub fn as_u256(&self) -> Result<[u8; 32], RLPError> {
+        let bytes = self.as_uint()?;
+        if bytes.len() > 32 {
+            return Err(RLPError::Overflow);
+        }
+        let mut buf = [0u8; 32];
+        buf[32 - bytes.len()..].copy_from_slice(bytes);
+        Ok(buf)
+    }
+
+    /// Big endian integer which fits in 8 bytes
+    pub fn as_u64(&self) -> Result<u64, RLPError> {
+        let bytes = self.as_uint()?;
+        if bytes.len() > 8 {
+            return Err(RLPError::Overflow);
+        }
+        Ok(bytes.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b)))
+    }
+
+    /// Integer bytes, which must not have leading zeros
+    fn as_uint(&self) -> Result<&[u8], RLPError> {
+        let bytes = self.as_bytes()?;
+        if bytes.first() == Some(&0) {
+            return Err(RLPError::NonCanonical);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Decode the item at the start of `data`, returning it with its encoded length
+fn decode_item(data: &[u8]) -> Result<(RLPItem, usize), RLPError> {
+    let prefix = *data.first().ok_or(RLPError::UnexpectedEnd)?;
+    match prefix {
+        0x00..=0x7f => Ok((RLPItem::Bytes(vec![prefix]), 1)),
+        0x80..=0xbf => {
+            let (offset, len) = payload_bounds(data, 0x80, 0xb7)?;
+            let bytes = &data[offset..offset + len];
+            if len == 1 && bytes[0] <= 0x7f {
+                return Err(RLPError::NonCanonical);
+            }
+            Ok((RLPItem::Bytes(bytes.to_vec()), offset + len))
+        }
+        _ => {
+            let (offset, len) = payload_bounds(data, 0xc0, 0xf7)?;
+            let mut items = Vec::new();
+            let mut pos = offset;
+            while pos < offset + len {
+                let (item, item_len) = decode_item(&data[pos..offset + len])?;
+                items.push(item);
+                pos += item_len;
+            }
+            Ok((RLPItem::List(items), offset + len))
+        }
+    }
+}
+
+/// Payload offset and length of a string (`short` = 0x80, `long` = 0xb7)
+/// or list (`short` = 0xc0, `long` = 0xf7) item
+fn payload_bounds(data: &[u8], short: u8, long: u8) -> Result<(usize, usize), RLPError> {
+    let prefix = data[0];
+    let (offset, len) = if prefix <= long {
+        (1, usize::from(prefix - short))
+    } else {
+        let len_bytes = usize::from(prefix - long);
+        let len_data = data.get(1..1 + len_bytes).ok_or(RLPError::UnexpectedEnd)?;
+        if len_data[0] == 0 {
+            return Err(RLPError::NonCanonical);
+        }
+        if len_bytes > 8 {
+            return Err(RLPError::Overflow);
+        }
+        let len = len_data
+            .iter()
+            .fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+        if len <= 55 {
+            return Err(RLPError::NonCanonical);
+        }
+        (
+            1 + len_bytes,
+            usize::try_from(len).map_err(|_| RLPError::Overflow)?,
+        )
+    };
+    if data.len() - offset < len {
+        return Err(RLPError::UnexpectedEnd);
+    }
+    Ok((offset, len))
+}
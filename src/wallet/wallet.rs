@@ -1,23 +1,21 @@
 use rand::thread_rng;
-use secp256k1::SecretKey;
 use std::{collections::HashMap, env};
 use web3::{
-    transports::{self, WebSocket}, signing::SecretKeyRef,
-    types::{TransactionParameters, TransactionRequest, H160, H256},
-    Error as Web3Error, Web3,
+    signing::Key,
+    transports::{self, WebSocket},
+    types::{Bytes, TransactionParameters, H256, U64},
+    Transport, Web3,
 };
-use web3_keystore::KeyStoreError;
 
-use crate::wallet::keystore::{save_keyfile, Kdf, KeyFile, KeyfileStorage, Keystore};
+use crate::wallet::error::Error;
+use crate::wallet::keystore::{save_keyfile, Kdf, KeyFile};
+use crate::wallet::nonce::NONCES;
 use crate::{
     database::models::account::Account,
-    wallet::core::{Address, PrivateKey},
+    database::models::transaction::{self, NewTransaction, TxStatus},
+    database::pool::PgPool,
+    wallet::core::{self, Address, PrivateKey, Signature, Transaction},
 };
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
-
-use super::Transaction;
 
 pub async fn build_wallet() -> Wallet {
     dotenv::dotenv().ok();
@@ -43,6 +41,29 @@ pub async fn build_wallet() -> Wallet {
     Wallet::new(intstances).clone()
 }
 
+/// Storage of the transactions submitted by `Wallet::send`
+pub trait TxRecorder {
+    /// Save a signed transaction before it is broadcast
+    async fn record(&self, tx: &NewTransaction) -> Result<(), Error>;
+
+    /// Update the status of a saved transaction
+    async fn mark(&self, hash: &H256, status: TxStatus) -> Result<(), Error>;
+}
+
+impl TxRecorder for PgPool {
+    async fn record(&self, tx: &NewTransaction) -> Result<(), Error> {
+        let mut conn = self.get()?;
+        tx.create(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn mark(&self, hash: &H256, status: TxStatus) -> Result<(), Error> {
+        let mut conn = self.get()?;
+        transaction::Transaction::update_status(&format!("{:?}", hash), status, &mut conn).await?;
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Wallet {
     inst: HashMap<u32, Web3<WebSocket>>,
@@ -57,69 +78,487 @@ impl Wallet {
         self.inst.get(chain).cloned()
     }
 
-    pub fn new_account() -> Result<(Address, KeyFile), KeyStoreError> {
-        let (kdf, ks, pk) = create_keystore().unwrap();
-        let binding = env::var("KEYSTORE_PATH").unwrap();
-        let path = binding.as_str();
-        let s = &env::var("SECRET").unwrap();
+    pub fn chains(&self) -> Vec<u32> {
+        self.inst.keys().cloned().collect()
+    }
+
+    pub fn new_account() -> Result<(Address, KeyFile), Error> {
+        dotenv::dotenv().ok();
+        let path = env::var("KEYSTORE_PATH")?;
+        let s = env::var("SECRET")?;
+        let pk = PrivateKey::gen();
+        let kdf = Kdf::from((8, 2, 1));
         let mut rng = thread_rng();
-        let keyfile = KeyFile::new_custom(pk, &s, kdf, &mut rng, None, None).unwrap();
-        let _ = save_keyfile(keyfile.clone(), path).unwrap();
-        let accs = ks.list_accounts(false).unwrap();
-        let (addr, _) = unlock_keyfile(&accs[0].filename.to_string()).unwrap();
-        Ok((addr, keyfile))
+        let keyfile = KeyFile::new_custom(pk, &s, kdf, &mut rng, None, None)?;
+        save_keyfile(keyfile.clone(), &path)?;
+        Ok((keyfile.address, keyfile))
     }
 
-    pub async fn send(
-        w3: &Web3<WebSocket>,
+    /// Sign transaction with the account's key and submit it with `eth_sendRawTransaction`
+    ///
+    /// Missing fee and `chain_id` are filled from the node, a missing `nonce` is reserved
+    /// from `NONCES` and released when the send fails. The transaction is recorded as
+    /// pending before the broadcast and marked failed when the node rejects it.
+    pub async fn send<T: Transport, R: TxRecorder>(
+        w3: &Web3<T>,
+        rec: &R,
         acc: &Account,
         tx: &TransactionParameters,
-    ) -> Result<H256, Web3Error> {
+    ) -> Result<H256, Error> {
         dotenv::dotenv().ok();
-        let s = &env::var("DEFAULT_PASS").unwrap();
-        let ks = acc.keystore.as_str().unwrap();
-        let kf = KeyFile::decode(ks).unwrap();
-        let pk = kf.decrypt_key(s).expect("Wrong passphrase");
-        // let sk: SecretKey = pk.into();
-        // let skr = SecretKeyRef::new(&sk);
+        let s = env::var("SECRET")?;
+        let kf = KeyFile::decode(&acc.keystore.to_string())?;
+        let pk = kf.decrypt_key(&s)?;
+        let mut tx = tx.to_owned();
+        let chain = match tx.chain_id {
+            Some(chain) => chain,
+            None => w3.eth().chain_id().await?.as_u64(),
+        };
+        if tx.transaction_type == Some(U64::from(2)) {
+            if tx.max_fee_per_gas.is_none() {
+                tx.max_fee_per_gas = Some(w3.eth().gas_price().await?);
+            }
+        } else if tx.gas_price.is_none() {
+            tx.gas_price = Some(w3.eth().gas_price().await?);
+        }
+        let from = Key::address(&pk);
+        let reserved = match tx.nonce {
+            Some(_) => None,
+            None => {
+                let nonce = NONCES.reserve(w3, from, chain).await?;
+                tx.nonce = Some(nonce);
+                Some(nonce)
+            }
+        };
 
-        // let signed = w3.accounts().sign_transaction(tx.to_owned(), sk.into());
+        let res = Self::sign_and_send(w3, rec, acc, &tx, pk, chain, None).await;
+        if let (Err(err), Some(nonce)) = (&res, reserved) {
+            if nonce_out_of_sync(err) {
+                NONCES.resync(from, chain);
+            } else {
+                NONCES.release(from, chain, nonce);
+            }
+        }
+        res
+    }
 
-        Ok(H256::random())
+    /// Replace the pending transaction `original` with `tx`, sent at the same nonce
+    ///
+    /// `tx` must carry the nonce, chain id and fees. The replacement is recorded with
+    /// a link to the transaction it replaces.
+    pub async fn replace<T: Transport, R: TxRecorder>(
+        w3: &Web3<T>,
+        rec: &R,
+        acc: &Account,
+        tx: &TransactionParameters,
+        original: &H256,
+    ) -> Result<H256, Error> {
+        dotenv::dotenv().ok();
+        let s = env::var("SECRET")?;
+        let kf = KeyFile::decode(&acc.keystore.to_string())?;
+        let pk = kf.decrypt_key(&s)?;
+        let chain = tx.chain_id.ok_or(core::Error::MissingField("chain_id"))?;
+        if tx.nonce.is_none() {
+            return Err(core::Error::MissingField("nonce").into());
+        }
+        Self::sign_and_send(w3, rec, acc, tx, pk, chain, Some(original)).await
     }
-}
 
-fn create_keystore() -> Result<(Kdf, Keystore, PrivateKey), KeyStoreError> {
-    let pk = PrivateKey::gen();
-    let kdf = Kdf::from((8, 2, 1));
-    let binding = env::var("KEYSTORE_PATH").unwrap();
-    let path = binding.as_str();
-    let ks = Keystore::new(&path);
-    Ok((kdf, ks, pk))
-}
+    /// Sign an EIP-712 digest, e.g. of a permit, with the account's key
+    pub fn sign_digest(acc: &Account, digest: [u8; 32]) -> Result<Signature, Error> {
+        dotenv::dotenv().ok();
+        let s = env::var("SECRET")?;
+        let kf = KeyFile::decode(&acc.keystore.to_string())?;
+        let pk = kf.decrypt_key(&s)?;
+        Ok(pk.sign_hash(digest)?)
+    }
+
+    async fn sign_and_send<T: Transport, R: TxRecorder>(
+        w3: &Web3<T>,
+        rec: &R,
+        acc: &Account,
+        tx: &TransactionParameters,
+        pk: PrivateKey,
+        chain: u64,
+        replaces: Option<&H256>,
+    ) -> Result<H256, Error> {
+        let raw = Transaction::try_from(tx)?.to_signed_raw(pk, chain)?;
 
-fn unlock_keyfile(filename: &str) -> Result<(Address, PrivateKey), KeyStoreError> {
-    let binding = env::var("SECRET").unwrap();
-    let s = binding.as_str();
-    let path =
-        env::var("KEYSTORE_PATH").unwrap() + &keyfile_path(filename).to_str().unwrap().to_string();
-    let mut file = File::open(path).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    let keyfile = KeyFile::decode(&contents).unwrap();
-    let k = keyfile.decrypt_key(s).expect("Wrong passphrase");
-    let h = KeyFile::decode(&contents).unwrap().address;
-    Ok((h, k))
+        // Audit the payload before it leaves the service
+        let signed = Transaction::from_signed_raw(&raw)?;
+        let sender = format!("{:?}", web3::types::Address::from(signed.from.0));
+        if !sender.eq_ignore_ascii_case(&acc.address) {
+            return Err(Error::SignerMismatch(sender));
+        }
+        let hash = H256::from(signed.hash);
+        let record = NewTransaction {
+            replaces: replaces.map(|h| format!("{:?}", h)),
+            ..NewTransaction::new(acc.id, chain, tx, &hash)
+        };
+        rec.record(&record).await?;
+        if let Err(err) = w3.eth().send_raw_transaction(Bytes(raw)).await {
+            rec.mark(&hash, TxStatus::Failed).await?;
+            return Err(err.into());
+        }
+
+        Ok(hash)
+    }
 }
 
-pub fn keyfile_path(name: &str) -> PathBuf {
-    let mut path = keystore_path();
-    path.push(name);
-    path
+/// Whether a failed send may have used its nonce or the node disagrees with the
+/// local counter, so the counter has to be read from the node again
+fn nonce_out_of_sync(err: &Error) -> bool {
+    match err {
+        Error::RpcFault(web3::Error::Rpc(err)) => {
+            let message = err.message.to_lowercase();
+            ["nonce", "known", "replacement"]
+                .iter()
+                .any(|m| message.contains(m))
+        }
+        // The transaction may have reached the node before the connection failed
+        Error::RpcFault(_) => true,
+        _ => false,
+    }
 }
 
-pub fn keystore_path() -> PathBuf {
-    let mut buf = PathBuf::from("");
-    buf.push("/");
-    buf
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use web3::signing::{keccak256, Key};
+    use web3::transports::Http;
+    use web3::types::{AccessList, AccessListItem, U256};
+
+    const PASSPHRASE: &str = "test-secret";
+
+    /// Recorder keeping the saved transactions and status updates in memory
+    #[derive(Default)]
+    struct MemoryRecorder {
+        recorded: Mutex<Vec<NewTransaction>>,
+        marked: Mutex<Vec<(H256, TxStatus)>>,
+    }
+
+    impl TxRecorder for MemoryRecorder {
+        async fn record(&self, tx: &NewTransaction) -> Result<(), Error> {
+            self.recorded.lock().unwrap().push(tx.clone());
+            Ok(())
+        }
+
+        async fn mark(&self, hash: &H256, status: TxStatus) -> Result<(), Error> {
+            self.marked.lock().unwrap().push((*hash, status));
+            Ok(())
+        }
+    }
+
+    /// Local JSON-RPC endpoint which records every `eth_sendRawTransaction` payload
+    fn mock_node(accept_raw: bool) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let recorded = sent.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let sent = sent.clone();
+                thread::spawn(move || serve(stream.unwrap(), accept_raw, sent));
+            }
+        });
+        (url, recorded)
+    }
+
+    fn serve(stream: TcpStream, accept_raw: bool, sent: Arc<Mutex<Vec<String>>>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        loop {
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    return;
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((k, v)) = line.split_once(':') {
+                    if k.eq_ignore_ascii_case("content-length") {
+                        len = v.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0u8; len];
+            std::io::Read::read_exact(&mut reader, &mut body).unwrap();
+            let req: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            let reply = match req["method"].as_str().unwrap() {
+                "eth_chainId" => json_result(&req, "0x13881"),
+                "eth_getTransactionCount" => json_result(&req, "0x5"),
+                "eth_sendRawTransaction" if accept_raw => {
+                    let raw = req["params"][0].as_str().unwrap().to_string();
+                    let hash = keccak256(&hex::decode(&raw[2..]).unwrap());
+                    sent.lock().unwrap().push(raw);
+                    json_result(&req, &format!("0x{}", hex::encode(hash)))
+                }
+                _ => serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": req["id"],
+                    "error": { "code": -32000, "message": "nonce too low" },
+                }),
+            };
+            let out = reply.to_string();
+            write!(
+                writer,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                out.len(),
+                out
+            )
+            .unwrap();
+        }
+    }
+
+    fn json_result(req: &serde_json::Value, result: &str) -> serde_json::Value {
+        serde_json::json!({ "jsonrpc": "2.0", "id": req["id"], "result": result })
+    }
+
+    fn test_account(passphrase: &str) -> (Account, PrivateKey) {
+        let kdf = Kdf::from((8, 2, 1));
+        let kf = KeyFile::new_custom(
+            PrivateKey::gen(),
+            passphrase,
+            kdf,
+            &mut thread_rng(),
+            None,
+            None,
+        )
+        .unwrap();
+        let pk = kf.decrypt_key(passphrase).unwrap();
+        let acc = Account {
+            id: 1,
+            user_id: "user".to_string(),
+            address: kf.address.to_string(),
+            keystore: serde_json::to_value(&kf).unwrap(),
+        };
+        (acc, pk)
+    }
+
+    fn test_tx() -> TransactionParameters {
+        TransactionParameters {
+            nonce: Some(U256::from(7)),
+            to: Some(web3::types::Address::repeat_byte(0x11)),
+            gas: U256::from(21_000),
+            gas_price: Some(U256::from(30_000_000_000u64)),
+            value: U256::from(1_000_000_000_000_000u64),
+            data: Bytes(vec![0xde, 0xad, 0xbe, 0xef]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn send_signs_and_broadcasts_raw_transaction() {
+        env::set_var("SECRET", PASSPHRASE);
+        let (url, sent) = mock_node(true);
+        let w3 = Web3::new(Http::new(&url).unwrap());
+        let (acc, pk) = test_account(PASSPHRASE);
+        let rec = MemoryRecorder::default();
+
+        let hash = Wallet::send(&w3, &rec, &acc, &test_tx()).await.unwrap();
+
+        let sent = sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        let raw = hex::decode(&sent[0][2..]).unwrap();
+        assert_eq!(hash, H256::from(keccak256(&raw)));
+
+        let expected = w3
+            .accounts()
+            .sign_transaction(
+                TransactionParameters {
+                    chain_id: Some(80001),
+                    ..test_tx()
+                },
+                pk,
+            )
+            .await
+            .unwrap();
+        assert_eq!(raw, expected.raw_transaction.0);
+        assert_eq!(hash, expected.transaction_hash);
+        assert_eq!(acc.address, format!("{:?}", Key::address(&pk)));
+
+        let recorded = rec.recorded.lock().unwrap();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].hash, format!("{:?}", hash));
+        assert_eq!(recorded[0].chain_id, 80001);
+        assert_eq!(recorded[0].nonce, 7);
+        assert_eq!(recorded[0].data, "0xdeadbeef");
+        assert_eq!(recorded[0].gas_price.as_deref(), Some("30000000000"));
+        assert_eq!(recorded[0].status, "pending");
+        assert!(rec.marked.lock().unwrap().is_empty());
+    }
+
+    /// Raw transaction sent for `tx`, and the one web3's own signer produces
+    async fn sign_both(tx: TransactionParameters) -> (Vec<u8>, Vec<u8>) {
+        env::set_var("SECRET", PASSPHRASE);
+        let (url, sent) = mock_node(true);
+        let w3 = Web3::new(Http::new(&url).unwrap());
+        let (acc, pk) = test_account(PASSPHRASE);
+        let rec = MemoryRecorder::default();
+
+        Wallet::send(&w3, &rec, &acc, &tx).await.unwrap();
+
+        let sent = sent.lock().unwrap().clone();
+        let expected = w3
+            .accounts()
+            .sign_transaction(
+                TransactionParameters {
+                    chain_id: Some(80001),
+                    ..tx
+                },
+                pk,
+            )
+            .await
+            .unwrap();
+        (
+            hex::decode(&sent[0][2..]).unwrap(),
+            expected.raw_transaction.0,
+        )
+    }
+
+    fn test_access_list() -> Option<AccessList> {
+        Some(vec![AccessListItem {
+            address: web3::types::Address::repeat_byte(0x22),
+            storage_keys: vec![H256::zero(), H256::repeat_byte(0x01)],
+        }])
+    }
+
+    #[tokio::test]
+    async fn send_signs_access_list_transaction() {
+        let (raw, expected) = sign_both(TransactionParameters {
+            transaction_type: Some(U64::from(1)),
+            access_list: test_access_list(),
+            ..test_tx()
+        })
+        .await;
+
+        assert_eq!(raw[0], 0x01);
+        assert_eq!(raw, expected);
+    }
+
+    #[tokio::test]
+    async fn send_signs_dynamic_fee_transaction() {
+        let (raw, expected) = sign_both(TransactionParameters {
+            transaction_type: Some(U64::from(2)),
+            gas_price: None,
+            max_fee_per_gas: Some(U256::from(60_000_000_000u64)),
+            max_priority_fee_per_gas: Some(U256::from(1_500_000_000u64)),
+            access_list: test_access_list(),
+            ..test_tx()
+        })
+        .await;
+
+        assert_eq!(raw[0], 0x02);
+        assert_eq!(raw, expected);
+    }
+
+    #[tokio::test]
+    async fn concurrent_sends_reserve_distinct_nonces() {
+        env::set_var("SECRET", PASSPHRASE);
+        let (url, sent) = mock_node(true);
+        let w3 = Web3::new(Http::new(&url).unwrap());
+        let (acc, _) = test_account(PASSPHRASE);
+        let rec = MemoryRecorder::default();
+        let tx = TransactionParameters {
+            nonce: None,
+            ..test_tx()
+        };
+
+        let (a, b, c) = futures::join!(
+            Wallet::send(&w3, &rec, &acc, &tx),
+            Wallet::send(&w3, &rec, &acc, &tx),
+            Wallet::send(&w3, &rec, &acc, &tx),
+        );
+        a.unwrap();
+        b.unwrap();
+        c.unwrap();
+
+        let mut nonces: Vec<u64> = sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|raw| {
+                let raw = hex::decode(&raw[2..]).unwrap();
+                Transaction::from_signed_raw(&raw).unwrap().tx.nonce
+            })
+            .collect();
+        nonces.sort();
+        assert_eq!(nonces, vec![5, 6, 7]);
+    }
+
+    #[tokio::test]
+    async fn replace_links_the_original_transaction() {
+        env::set_var("SECRET", PASSPHRASE);
+        let (url, sent) = mock_node(true);
+        let w3 = Web3::new(Http::new(&url).unwrap());
+        let (acc, _) = test_account(PASSPHRASE);
+        let rec = MemoryRecorder::default();
+        let original = H256::repeat_byte(0xaa);
+        let tx = TransactionParameters {
+            chain_id: Some(80001),
+            gas_price: Some(U256::from(33_000_000_000u64)),
+            ..test_tx()
+        };
+
+        let hash = Wallet::replace(&w3, &rec, &acc, &tx, &original)
+            .await
+            .unwrap();
+
+        let raw = hex::decode(&sent.lock().unwrap()[0][2..]).unwrap();
+        assert_eq!(Transaction::from_signed_raw(&raw).unwrap().tx.nonce, 7);
+        let recorded = rec.recorded.lock().unwrap().clone();
+        assert_eq!(recorded[0].hash, format!("{:?}", hash));
+        assert_eq!(recorded[0].replaces, Some(format!("{:?}", original)));
+
+        let res = Wallet::replace(
+            &w3,
+            &rec,
+            &acc,
+            &TransactionParameters { nonce: None, ..tx },
+            &original,
+        )
+        .await;
+        assert!(matches!(res, Err(Error::InvalidTransaction(_))));
+    }
+
+    #[tokio::test]
+    async fn send_fails_on_wrong_passphrase() {
+        env::set_var("SECRET", PASSPHRASE);
+        let (url, sent) = mock_node(true);
+        let w3 = Web3::new(Http::new(&url).unwrap());
+        let (acc, _) = test_account("another-secret");
+        let rec = MemoryRecorder::default();
+
+        let res = Wallet::send(&w3, &rec, &acc, &test_tx()).await;
+
+        assert!(matches!(res, Err(Error::KeystoreFault(_))));
+        assert!(sent.lock().unwrap().is_empty());
+        assert!(rec.recorded.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_returns_node_rejection() {
+        env::set_var("SECRET", PASSPHRASE);
+        let (url, sent) = mock_node(false);
+        let w3 = Web3::new(Http::new(&url).unwrap());
+        let (acc, _) = test_account(PASSPHRASE);
+        let rec = MemoryRecorder::default();
+
+        let res = Wallet::send(&w3, &rec, &acc, &test_tx()).await;
+
+        assert!(matches!(res, Err(Error::RpcFault(_))));
+        assert!(sent.lock().unwrap().is_empty());
+
+        let recorded = rec.recorded.lock().unwrap();
+        assert_eq!(recorded.len(), 1);
+        let marked = rec.marked.lock().unwrap();
+        assert_eq!(format!("{:?}", marked[0].0), recorded[0].hash);
+        assert_eq!(marked[0].1, TxStatus::Failed);
+    }
 }